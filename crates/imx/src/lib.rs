@@ -1,3 +1,3 @@
 
-pub use imx_core::Client;
-pub use imx_core::{Addr, Addrs};
+pub use imx_core::{blocking, security, storage, Client, ClientBuilder};
+pub use imx_core::{Addr, Addrs, AppInfo, ClientConfig, ConfigError, ConnState, ConnStateEvent, ConnType, DcConfig, DcEntry, DcStore, DcTable, DEFAULT_DC_ID, Environment, FileDcStore, KeepaliveConfig, MemoryDcStore, PoolConfig, ProxyAuth, ProxyConfig, ReconnectConfig, RequestError, RequestOptions, ResolveFuture, Resolver, RsaKeys, RttStats, SocketConfig, StaticResolver, SystemResolver, TlsConfig, TransportConfig, TransportOptions, Lagged, Update, UpdateStream};
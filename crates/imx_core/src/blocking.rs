@@ -0,0 +1,122 @@
+//! 阻塞调用的客户端, 用于没有异步运行时的调用方
+
+use anyhow::Result;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+
+use crate::{Addrs, ConnStateEvent, Lagged, RequestOptions, RttStats};
+use crate::proto::{MtRpc, Update};
+use crate::storage::ExportedSession;
+
+/// [crate::Client] 的阻塞版本, 持有自己的运行时. 不能在异步上下文中创建或调用
+///
+/// # Examples
+/// ```rust,no_run
+/// use imx_core::blocking::Client;
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let client = Client::new("127.0.0.1:80")?;
+/// client.start();
+/// println!("{:?}", client.rtt_stats());
+/// # Ok(())
+/// # }
+/// ```
+pub struct Client {
+    inner: crate::Client,
+}
+
+impl Client {
+    /// 创建客户端, 需要更多配置时使用 [ClientBuilder::build_blocking](crate::ClientBuilder::build_blocking)
+    pub fn new<T>(addrs: T) -> Result<Self> where T: Into<Addrs> {
+        crate::Client::builder(addrs).build_blocking()
+    }
+
+    pub(crate) fn from_async(inner: crate::Client) -> Self {
+        Self { inner }
+    }
+
+    pub fn start(&self) {
+        self.inner.start()
+    }
+
+    pub fn stop(&self) {
+        self.inner.stop()
+    }
+
+    pub fn pause(&self) {
+        self.inner.pause()
+    }
+
+    pub fn background(&self) {
+        self.inner.background()
+    }
+
+    pub fn resume(&self) {
+        self.inner.resume()
+    }
+
+    /// 发送消息并等待响应, 使用默认的 [RequestOptions]
+    pub fn send<Rpc: MtRpc>(&self, msg: &Rpc) -> Result<Rpc::Return> {
+        self.send_with(msg, RequestOptions::default())
+    }
+
+    /// 发送消息并等待响应, 见 [crate::Client::send_with]
+    pub fn send_with<Rpc: MtRpc>(&self, msg: &Rpc, options: RequestOptions) -> Result<Rpc::Return> {
+        self.inner.block_on(self.inner.send_with(msg, options))
+    }
+
+    pub fn rtt_stats(&self) -> Vec<RttStats> {
+        self.inner.block_on(self.inner.rtt_stats())
+    }
+
+    /// 订阅连接状态变化, 使用 `blocking_recv` 接收
+    pub fn state_events(&self) -> broadcast::Receiver<ConnStateEvent> {
+        self.inner.state_events()
+    }
+
+    /// 订阅服务器推送的 updates, 见 [crate::Client::updates]
+    pub fn updates(&self) -> Updates {
+        Updates { rx: self.inner.subscribe_updates() }
+    }
+
+    /// 见 [crate::Client::export_session]
+    pub fn export_session(&self) -> Result<ExportedSession> {
+        self.inner.block_on(self.inner.export_session())
+    }
+
+    /// 见 [crate::Client::lock]
+    pub fn lock(&self) -> Result<()> {
+        self.inner.block_on(self.inner.lock())
+    }
+
+    /// 见 [crate::Client::unlock]
+    pub fn unlock(&self, passcode: &str) -> Result<()> {
+        self.inner.unlock(passcode)
+    }
+
+    pub fn change_passcode(&self, old: &str, new: &str) -> Result<()> {
+        self.inner.change_passcode(old, new)
+    }
+
+    pub fn release(&self) {
+        self.inner.release()
+    }
+}
+
+/// [Client::updates] 返回的阻塞迭代器, 客户端释放后结束
+#[derive(Debug)]
+pub struct Updates {
+    rx: broadcast::Receiver<Update>,
+}
+
+impl Iterator for Updates {
+    type Item = Result<Update, Lagged>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.rx.blocking_recv() {
+            Ok(update) => Some(Ok(update)),
+            Err(RecvError::Lagged(n)) => Some(Err(Lagged(n))),
+            Err(RecvError::Closed) => None,
+        }
+    }
+}
@@ -1,60 +1,67 @@
 use core::time::Duration;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt::{Debug, Formatter};
-use std::thread;
+use std::future;
+use std::future::Future;
+use std::sync::Arc;
 
 use anyhow::{bail, Result};
 use async_channel::{Receiver, Sender, unbounded};
 use bytes::Bytes;
 use log::{error, info};
-use serde::Serialize;
-use tokio::runtime::{Builder, Runtime};
-use tokio::sync::oneshot;
+use tokio::runtime::{Builder, Handle, Runtime};
+use tokio::sync::{broadcast, oneshot};
 use tokio::time;
-use tokio::time::Interval;
-
-use with_crc::WithCrc;
+use tokio::time::{Instant, Interval};
+use zeroize::Zeroizing;
 
 use crate::{net, proto};
-use crate::net::Addrs;
-use crate::proto::{MtDe, MtRpc};
+use crate::defines::{PASSCODE_KDF_ITERATIONS, UPDATE_BUFFER_SIZE};
+use crate::net::{Addrs, AppInfo, ClientConfig, ConfigError, ConnStateEvent, DcConfig, DcStore, DEFAULT_DC_ID, Environment, KeepaliveConfig, NetConfig, PoolConfig, ReconnectConfig, RequestError, RequestOptions, RsaKeys, RttStats, SocketConfig, TransportOptions, UpdateStream};
+use crate::net::dispatcher::Signal;
+use crate::net::StorageDcStore;
+use crate::proto::{MtDe, MtRpc, Update};
+use crate::security::{EncryptedStorage, SecurityError};
+use crate::storage::{client_keys, ExportedSession, MemoryStorage, Storage};
 
-/// 即时通讯客户端
+/// 即时通讯客户端, 后台任务运行在调用方的 tokio 运行时或客户端自己创建的运行时上,
+/// drop 后后台任务关闭所有连接并退出. 非异步的调用方使用 [blocking::Client](crate::blocking::Client)
 pub struct Client {
-    rt: Runtime,
+    /// 没有可用的运行时时创建, 与客户端一起释放
+    rt: Option<Runtime>,
     tx: Sender<Action>,
+    events: broadcast::Sender<ConnStateEvent>,
+    /// 后台任务持有唯一的 Sender, 释放后订阅者随之结束
+    updates: broadcast::WeakSender<Update>,
+    /// 设置了 [ClientBuilder::passcode] 时加密的本地存储
+    storage: Option<EncryptedStorage<Arc<dyn Storage>>>,
 }
 
 impl Client {
     /// 创建客户端
-    /// - [addrs] 服务器地址, 可以传一个或多个 ipv4 或 ipv6 地址
+    /// - [addrs] home DC ([DEFAULT_DC_ID]) 的地址, 可以传一个或多个 ipv4 或 ipv6 地址
     ///
     /// # Examples
-    /// ```rust
+    /// ```rust,no_run
     /// use imx_core::Client;
     ///
+    /// # fn main() -> anyhow::Result<()> {
     /// let client = Client::new("127.0.0.1:80")?;
     /// let client = Client::new(("127.0.0.1", 80))?;
     /// let client = Client::new(["127.0.0.1:80", "127.0.0.1:443"])?;
     ///
     /// client.start();
     /// client.stop();
+    /// # Ok(())
+    /// # }
     /// ```
     pub fn new<T>(addrs: T) -> Result<Self> where T: Into<Addrs> {
-        let addrs = addrs.into();
-        if addrs.is_empty() { bail!("addrs is empty"); }
-
-        let rt = Builder::new_multi_thread()
-            .thread_name("client-worker")
-            .enable_all()
-            .build()?;
-
-        let (tx, rx) = unbounded();
-
-        rt.spawn(async move {
-            run_client(rx, addrs).await;
-        });
+        Self::builder(addrs).build()
+    }
 
-        Ok(Self { rt, tx })
+    /// 通过 [ClientBuilder] 创建客户端, 可以指定传输层和底层 socket 配置
+    pub fn builder<T>(addrs: T) -> ClientBuilder where T: Into<Addrs> {
+        ClientBuilder::new(addrs)
     }
 
     /// 启动
@@ -71,75 +78,269 @@ impl Client {
         }
     }
 
-    /// 发送消息
+    /// 暂停网络, 挂起所有连接. 期间发送的请求在 [Client::resume] 后发送 (超时的除外)
+    pub fn pause(&self) {
+        if let Err(e) = self.tx.clone().try_send(Action::Pause) {
+            error!("{}", e);
+        }
+    }
+
+    /// 进入后台模式, 关闭 push 以外的连接, push 连接保持并延长 keepalive 间隔.
+    /// 期间发送的请求在 [Client::resume] 后发送
+    pub fn background(&self) {
+        if let Err(e) = self.tx.clone().try_send(Action::Background) {
+            error!("{}", e);
+        }
+    }
+
+    /// 从暂停或后台模式恢复, 重新连接并发送等待中的请求
+    pub fn resume(&self) {
+        if let Err(e) = self.tx.clone().try_send(Action::Resume) {
+            error!("{}", e);
+        }
+    }
+
+    /// 发送消息, 使用默认的 [RequestOptions]
     pub async fn send<Rpc: MtRpc>(&self, msg: &Rpc) -> Result<Rpc::Return> {
+        self.send_with(msg, RequestOptions::default()).await
+    }
+
+    /// 发送消息, 断线重连后自动重发, 直到收到响应或超时.
+    /// 失败时返回的错误可以 downcast 为 [RequestError], 在收到响应前 drop 返回的 future 会取消请求
+    pub async fn send_with<Rpc: MtRpc>(&self, msg: &Rpc, options: RequestOptions) -> Result<Rpc::Return> {
         let bytes = proto::to_bytes(msg)?;
         let (one_tx, one_rx) = oneshot::channel();
-        self.tx.clone().try_send(Action::SendMsg(bytes, one_tx))?;
-        let res = one_rx.await??;
+        self.tx.clone().try_send(Action::SendMsg(bytes, options, one_tx))?;
+        let res = one_rx.await.map_err(|_| RequestError::Stopped)??;
         Rpc::Return::from_bytes(&res)
     }
 
+    /// 订阅连接状态变化
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// use imx_core::Client;
+    ///
+    /// # async fn run() -> anyhow::Result<()> {
+    /// let client = Client::new("127.0.0.1:80")?;
+    /// let mut events = client.state_events();
+    /// client.start();
+    /// while let Ok(ev) = events.recv().await {
+    ///     println!("dc{} {:?}: {:?}", ev.dc_id, ev.conn_type, ev.state);
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn state_events(&self) -> broadcast::Receiver<ConnStateEvent> {
+        self.events.subscribe()
+    }
+
+    /// 订阅服务器推送的 updates, 只收到订阅之后的 updates. 不认识的构造器以 [Update::Raw] 返回,
+    /// 消费太慢时返回 [Lagged](crate::Lagged), 表示缓冲区满后丢弃了部分 updates. 客户端释放后结束
+    ///
+    /// # Examples
+    /// ```rust,no_run
+    /// use imx_core::{Client, Update};
+    /// use tokio_stream::StreamExt;
+    ///
+    /// # async fn run() -> anyhow::Result<()> {
+    /// let client = Client::new("127.0.0.1:80")?;
+    /// let mut updates = client.updates();
+    /// client.start();
+    /// while let Some(update) = updates.next().await {
+    ///     match update {
+    ///         Ok(Update::ShortMessage(msg)) => println!("{}: {}", msg.user_id, msg.message),
+    ///         Ok(update) => println!("{:?}", update),
+    ///         Err(lagged) => println!("{}, need to get difference", lagged),
+    ///     }
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn updates(&self) -> UpdateStream {
+        UpdateStream::new(self.subscribe_updates())
+    }
+
+    /// 客户端已释放时返回已关闭的订阅
+    pub(crate) fn subscribe_updates(&self) -> broadcast::Receiver<Update> {
+        match self.updates.upgrade() {
+            Some(updates) => updates.subscribe(),
+            None => broadcast::channel(1).1,
+        }
+    }
+
+    /// 各个连接的 RTT, 由 keepalive 的 ping 测量. 客户端未启动时返回空列表
+    pub async fn rtt_stats(&self) -> Vec<RttStats> {
+        let (one_tx, one_rx) = oneshot::channel();
+        if self.tx.clone().try_send(Action::RttStats(one_tx)).is_err() { return vec![]; }
+        one_rx.await.unwrap_or_default()
+    }
+
+    /// 导出各个 DataCenter 的密钥和会话, 用于在其他进程或机器上通过 [ClientBuilder::session] 恢复登录状态.
+    /// 还没有任何密钥时返回错误
+    pub async fn export_session(&self) -> Result<ExportedSession> {
+        let (one_tx, one_rx) = oneshot::channel();
+        self.tx.clone().try_send(Action::ExportSession(one_tx))?;
+        let session = one_rx.await.map_err(|_| RequestError::Stopped)?;
+        if session.dcs.is_empty() { bail!("no auth key to export"); }
+        Ok(session)
+    }
+
+    /// 锁定本地存储并暂停网络. 等待所有连接挂起, 内存中的密钥和会话释放后再清除数据密钥,
+    /// 解锁后从本地存储重新加载. 需要通过 [ClientBuilder::passcode] 开启加密
+    pub async fn lock(&self) -> Result<()> {
+        let Some(storage) = &self.storage else { bail!(SecurityError::NotEncrypted); };
+        let (one_tx, one_rx) = oneshot::channel();
+        // 后台任务已退出时没有需要挂起的连接
+        if self.tx.clone().try_send(Action::Lock(one_tx)).is_ok() {
+            one_rx.await.ok();
+        }
+        storage.lock();
+        Ok(())
+    }
+
+    /// 使用口令解锁本地存储并恢复网络. 派生密钥需要较多计算, 不要在对延迟敏感的任务中调用
+    pub fn unlock(&self, passcode: &str) -> Result<()> {
+        let Some(storage) = &self.storage else { bail!(SecurityError::NotEncrypted); };
+        storage.unlock(passcode)?;
+        self.resume();
+        Ok(())
+    }
+
+    /// 更换口令, 已保存的记录不需要重新加密
+    pub fn change_passcode(&self, old: &str, new: &str) -> Result<()> {
+        let Some(storage) = &self.storage else { bail!(SecurityError::NotEncrypted); };
+        storage.change_passcode(old, new)
+    }
+
     /// 释放客户端, 调用之后, 无法通过 `start` 再次启动
     pub fn release(&self) {
         if let Err(e) = self.tx.clone().try_send(Action::Release) {
             error!("{}", e);
         }
     }
+
+    /// 在客户端自己的运行时上阻塞等待 [future], 只用于 [blocking::Client](crate::blocking::Client)
+    pub(crate) fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.rt.as_ref().expect("client has no own runtime").block_on(future)
+    }
 }
 
-async fn run_client(mut rx: Receiver<Action>, addrs: Addrs, ) {
+#[allow(clippy::too_many_arguments)]
+async fn run_client(
+    rx: Receiver<Action>,
+    dcs: HashMap<i32, DcConfig>,
+    home_dc: i32,
+    store: Arc<dyn DcStore>,
+    storage: Arc<dyn Storage>,
+    config: Arc<NetConfig>,
+    events: broadcast::Sender<ConnStateEvent>,
+    updates: broadcast::Sender<Update>,
+) {
     let mut client: Option<net::Client> = None;
     let mut interval: Option<Interval> = None;
 
     loop {
         match &mut interval {
-            None => {
-                if let Ok(Action::Start) = rx.recv().await {
+            None => match rx.recv().await {
+                Ok(Action::Start) => {
                     if client.is_some() {
                         info!("The client is running and does not need to be started again.");
                     } else {
                         info!("Start client...");
                         interval = Some(time::interval(Duration::from_secs(1)));
-                        client = Some(net::Client::new(addrs.clone()));
+                        let mut c = net::Client::new(dcs.clone(), home_dc, store.clone(), storage.clone(), config.clone(), events.clone(), updates.clone());
+                        if let Err(e) = c.connect() {
+                            error!("{}", e);
+                        }
+                        client = Some(c);
                     }
                 }
-            }
+                Ok(Action::ExportSession(result_tx)) => {
+                    // 未启动时只读取 storage 中保存的状态
+                    let c = net::Client::new(dcs.clone(), home_dc, store.clone(), storage.clone(), config.clone(), events.clone(), updates.clone());
+                    result_tx.send(c.export_session()).ok();
+                }
+                Ok(Action::Lock(result_tx)) => {
+                    result_tx.send(()).ok();
+                }
+                Ok(Action::Release) | Err(_) => break,
+                Ok(_) => {}
+            },
             Some(timer) => {
+                let signals = client.as_ref().map(|c| c.signals());
+                let deadline = client.as_ref().and_then(|c| c.next_deadline());
                 tokio::select! {
                     _ = timer.tick() => {
                         if let Some(client) = &mut client {
-                            if let Err(e) = client.select().await {
-                                error!("{}", e);
-                            }
+                            client.select();
+                        }
+                    }
+                    Some(signal) = recv_signal(signals) => {
+                        if let Some(client) = &mut client {
+                            client.on_signal(signal);
                         }
                     }
-                    Ok(ev) = rx.recv() => match ev {
-                        Action::SendMsg(msg, result_tx) => {
+                    _ = sleep_until(deadline) => {
+                        if let Some(client) = &mut client {
+                            client.process_request_queue();
+                        }
+                    }
+                    ev = rx.recv() => match ev {
+                        Ok(Action::SendMsg(msg, options, result_tx)) => {
+                            if let Some(client) = &mut client {
+                                client.send_msg(msg, options, result_tx);
+                            }
+                        }
+                        Ok(Action::Pause) => {
                             if let Some(client) = &mut client {
-                                let result = client.send_msg(msg).await;
-                                result_tx.send(result).unwrap();
+                                client.pause();
                             }
                         }
-                        Action::Stop => {
+                        Ok(Action::Lock(result_tx)) => {
+                            if let Some(client) = &mut client {
+                                client.lock();
+                            }
+                            result_tx.send(()).ok();
+                        }
+                        Ok(Action::Background) => {
+                            if let Some(client) = &mut client {
+                                client.background();
+                            }
+                        }
+                        Ok(Action::Resume) => {
+                            if let Some(client) = &mut client {
+                                client.resume();
+                            }
+                        }
+                        Ok(Action::RttStats(result_tx)) => {
+                            result_tx.send(client.as_ref().map(|c| c.rtt_stats()).unwrap_or_default()).ok();
+                        }
+                        Ok(Action::ExportSession(result_tx)) => {
+                            if let Some(client) = &client {
+                                result_tx.send(client.export_session()).ok();
+                            }
+                        }
+                        Ok(Action::Stop) => {
                             if let Some(mut client) = client.take() {
                                 info!("Stop client...");
-                                client.destroy().await;
+                                client.destroy();
                                 interval.take();
                                 info!("Client stopped.");
                             }
                         }
-                        Action::Release => {
+                        // 客户端被 drop 时同样释放
+                        Ok(Action::Release) | Err(_) => {
                             if let Some(mut client) = client.take() {
                                 info!("Stop client...");
-                                client.destroy().await;
+                                client.destroy();
                                 interval.take();
                                 info!("Client stopped.");
                             }
                             info!("Release client runtime!!!");
                             break;
                         }
-                        _ => {}
+                        Ok(Action::Start) => {}
                     }
                 }
             }
@@ -147,21 +348,477 @@ async fn run_client(mut rx: Receiver<Action>, addrs: Addrs, ) {
     }
 }
 
+/// 等待连接读写任务的通知, 客户端未启动时一直等待
+async fn recv_signal(rx: Option<Receiver<Signal>>) -> Option<Signal> {
+    match rx {
+        Some(rx) => rx.recv().await.ok(),
+        None => future::pending().await,
+    }
+}
+
+/// 等待到请求超时, 没有请求时一直等待
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => time::sleep_until(deadline).await,
+        None => future::pending().await,
+    }
+}
+
+/// 客户端构造器
+///
+/// # Examples
+/// ```rust,no_run
+/// use imx_core::{Client, DcConfig, SocketConfig, TlsConfig, TransportConfig};
+/// use imx_core::proto::transport::TransportType;
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let transport = TransportConfig::new(TransportType::Abridged)
+///     .fallback(TransportType::PaddedIntermediate);
+/// let socket = SocketConfig::new()
+///     .tls(TlsConfig::new().alpn("mtproto"));
+/// let client = Client::builder("quic://127.0.0.1:443")
+///     .transport(transport)
+///     .socket(socket)
+///     .dc(DcConfig::new(2).addrs("quic://127.0.0.2:443"))
+///     .build()?;
+/// # Ok(())
+/// # }
+/// ```
+pub struct ClientBuilder {
+    /// [Client::new] 传入的地址, 属于 [DEFAULT_DC_ID]
+    seed: DcConfig,
+    dcs: HashMap<i32, DcConfig>,
+    home_dc: i32,
+    /// 没有指定时保存到 [storage]
+    store: Option<Arc<dyn DcStore>>,
+    /// 没有指定时保存在内存中
+    storage: Option<Arc<dyn Storage>>,
+    /// 设置后加密 [storage] 中的所有记录
+    passcode: Option<Zeroizing<String>>,
+    /// 从口令派生密钥时 PBKDF2 的迭代次数
+    passcode_iterations: u32,
+    /// 创建时写入 [storage] 的会话
+    session: Option<ExportedSession>,
+    /// 没有指定时使用 [Environment::rsa_keys]
+    rsa_keys: Option<RsaKeys>,
+    /// 每个 updates 订阅者的缓冲区大小
+    update_buffer: usize,
+    /// 运行后台任务的运行时
+    runtime: Option<Handle>,
+    config: NetConfig,
+}
+
+impl ClientBuilder {
+    pub fn new<T>(addrs: T) -> Self where T: Into<Addrs> {
+        Self {
+            seed: DcConfig::new(DEFAULT_DC_ID).addrs(addrs),
+            dcs: HashMap::new(),
+            home_dc: DEFAULT_DC_ID,
+            store: None,
+            storage: None,
+            passcode: None,
+            passcode_iterations: PASSCODE_KDF_ITERATIONS,
+            session: None,
+            rsa_keys: None,
+            update_buffer: UPDATE_BUFFER_SIZE,
+            runtime: None,
+            config: NetConfig::default(),
+        }
+    }
+
+    /// 由 [ClientConfig] 创建, 服务器公钥无法解析时返回 [ConfigError::RsaKey], 其他取值在 `build` 时检查
+    pub fn from_config(config: ClientConfig) -> Result<Self> {
+        let ClientConfig {
+            addrs, media_addrs, home_dc, dcs, environment, transport, pfs, request_timeout_ms, connect_timeout_ms,
+            read_buffer_size, proxy, reconnect, pool, keepalive, rsa_keys, app, update_buffer,
+        } = config;
+
+        let mut socket = SocketConfig::new()
+            .connect_timeout(Duration::from_millis(connect_timeout_ms))
+            .read_buffer_size(read_buffer_size);
+        if let Some(proxy) = proxy {
+            socket = socket.proxy(proxy);
+        }
+        let mut builder = Self::new(addrs)
+            .media_addrs(media_addrs)
+            .home_dc(home_dc)
+            .environment(environment)
+            .transport(transport)
+            .socket(socket)
+            .pfs(pfs)
+            .request_timeout(Duration::from_millis(request_timeout_ms))
+            .reconnect(reconnect)
+            .pool(pool)
+            .keepalive(keepalive)
+            .app(app)
+            .update_buffer(update_buffer);
+        for dc in dcs {
+            builder = builder.dc(dc.into());
+        }
+        if !rsa_keys.is_empty() {
+            let keys = rsa_keys.iter()
+                .try_fold(RsaKeys::new(), |keys, pem| keys.pem(pem))
+                .map_err(|e| ConfigError::RsaKey(e.to_string()))?;
+            builder = builder.rsa_keys(keys);
+        }
+        Ok(builder)
+    }
+
+    /// 传输协议和 obfuscation 密钥, 可按 DataCenter 或 [ConnType](crate::ConnType) 分别配置
+    pub fn transport<O>(mut self, transport: O) -> Self where O: Into<TransportOptions> {
+        self.config.transport = transport.into();
+        self
+    }
+
+    /// 底层 socket 配置, 如 QUIC 使用的证书和 ALPN
+    pub fn socket(mut self, socket: SocketConfig) -> Self {
+        self.config.socket = socket;
+        self
+    }
+
+    /// 断线重连的退避策略
+    pub fn reconnect(mut self, reconnect: ReconnectConfig) -> Self {
+        self.config.reconnect = reconnect;
+        self
+    }
+
+    /// 只用于媒体连接 (上传下载文件) 的地址, 不设置时使用 [Client::new] 传入的地址
+    pub fn media_addrs<T>(mut self, addrs: T) -> Self where T: Into<Addrs> {
+        self.seed = self.seed.media(addrs);
+        self
+    }
+
+    /// 添加 DataCenter, 收到 `*_MIGRATE_X` 错误时请求会重发到对应的 DataCenter.
+    /// 与 [Client::new] 传入的地址同为 [DEFAULT_DC_ID] 时合并
+    pub fn dc(mut self, dc: DcConfig) -> Self {
+        self.dcs.insert(dc.id(), dc);
+        self
+    }
+
+    /// 没有指定 DataCenter 的请求发送到的 DC, 默认为 [DEFAULT_DC_ID]
+    pub fn home_dc(mut self, dc_id: i32) -> Self {
+        self.home_dc = dc_id;
+        self
+    }
+
+    /// 各类型连接的数量和空闲关闭时间
+    pub fn pool(mut self, pool: PoolConfig) -> Self {
+        self.config.pool = pool;
+        self
+    }
+
+    /// 是否开启 [PFS](https://core.telegram.org/api/pfs), 默认开启. 开启后永久密钥只用于绑定临时密钥,
+    /// 所有请求都使用临时密钥加密, 临时密钥过期前自动更换
+    pub fn pfs(mut self, enabled: bool) -> Self {
+        self.config.pfs = enabled;
+        self
+    }
+
+    /// 服务器环境, 默认为 [Environment::Production]. 测试服的 DC id 加 10000, 默认信任 [RsaKeys::test]
+    pub fn environment(mut self, environment: Environment) -> Self {
+        self.config.environment = environment;
+        self
+    }
+
+    /// 握手时信任的服务器公钥, 默认为 [Environment::rsa_keys]. 自建服务器用 [RsaKeys::pem] 加载自己的公钥
+    pub fn rsa_keys(mut self, keys: RsaKeys) -> Self {
+        self.rsa_keys = Some(keys);
+        self
+    }
+
+    /// 保存从服务器获取的 DataCenter 地址, 默认保存到 [ClientBuilder::storage].
+    /// 使用 [FileDcStore](crate::FileDcStore) 等持久化后, 下次启动时直接使用最新的地址
+    pub fn dc_store<S: DcStore>(mut self, store: S) -> Self {
+        self.store = Some(Arc::new(store));
+        self
+    }
+
+    /// 使用口令加密 [ClientBuilder::storage] 中的所有记录, 第一次使用时生成数据密钥.
+    /// 口令错误时 `build` 返回 [SecurityError::WrongPasscode], 运行时可以通过 [Client::lock] 锁定
+    /// [ClientBuilder::storage] 中已有的明文记录在第一次使用时加密保存
+    pub fn passcode<P: Into<String>>(mut self, passcode: P) -> Self {
+        self.passcode = Some(Zeroizing::new(passcode.into()));
+        self
+    }
+
+    /// 设置新口令时 PBKDF2 的迭代次数, 默认为 [PASSCODE_KDF_ITERATIONS]. 只用于测试, 减少派生密钥的时间
+    #[cfg(test)]
+    pub(crate) fn passcode_iterations(mut self, iterations: u32) -> Self {
+        self.passcode_iterations = iterations;
+        self
+    }
+
+    /// 保存各个 DataCenter 的密钥, 会话和地址, 默认只保存在内存中.
+    /// 使用 [SledStorage](crate::storage::SledStorage) 等持久化后, 重新启动时不需要再次握手
+    pub fn storage<S: Storage>(mut self, storage: S) -> Self {
+        self.storage = Some(Arc::new(storage));
+        self
+    }
+
+    /// 导入 [Client::export_session] 导出的会话, 覆盖 [ClientBuilder::storage] 中相同 DataCenter 的密钥.
+    /// 会话中的 home DC 在地址已知时生效
+    pub fn session(mut self, session: ExportedSession) -> Self {
+        self.session = Some(session);
+        self
+    }
+
+    /// 请求的默认超时时间, 默认 30 秒, 可以通过 [RequestOptions::timeout] 单独设置
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.config.request_timeout = timeout;
+        self
+    }
+
+    /// 连接的 keepalive 间隔和断开判断
+    pub fn keepalive(mut self, keepalive: KeepaliveConfig) -> Self {
+        self.config.keepalive = keepalive;
+        self
+    }
+
+    /// 初始化连接时上报的设备和应用信息
+    pub fn app(mut self, app: AppInfo) -> Self {
+        self.config.app = app;
+        self
+    }
+
+    /// 每个 [Client::updates] 订阅者最多缓冲的 updates 数量, 默认为 [UPDATE_BUFFER_SIZE].
+    /// 超过后丢弃最早的 updates, 订阅者收到 [Lagged](crate::Lagged)
+    pub fn update_buffer(mut self, size: usize) -> Self {
+        self.update_buffer = size;
+        self
+    }
+
+    /// 在 [handle] 对应的运行时上运行后台任务. 没有指定时使用当前所在的运行时,
+    /// 不在运行时中调用 [ClientBuilder::build] 时创建新的运行时
+    pub fn runtime(mut self, handle: Handle) -> Self {
+        self.runtime = Some(handle);
+        self
+    }
+
+    /// 创建客户端, 配置的取值不合法时返回 [ConfigError]
+    pub fn build(mut self) -> Result<Client> {
+        match self.runtime.take().or_else(|| Handle::try_current().ok()) {
+            Some(handle) => self.spawn(None, &handle),
+            None => self.build_with_runtime(),
+        }
+    }
+
+    /// 创建 [blocking::Client](crate::blocking::Client), 总是使用客户端自己的运行时, 忽略 [ClientBuilder::runtime].
+    /// 不能在异步上下文中调用
+    pub fn build_blocking(self) -> Result<crate::blocking::Client> {
+        self.build_with_runtime().map(crate::blocking::Client::from_async)
+    }
+
+    fn build_with_runtime(self) -> Result<Client> {
+        let rt = Builder::new_multi_thread()
+            .thread_name("client-worker")
+            .enable_all()
+            .build()?;
+        let handle = rt.handle().clone();
+        self.spawn(Some(rt), &handle)
+    }
+
+    fn spawn(self, rt: Option<Runtime>, handle: &Handle) -> Result<Client> {
+        let Self {
+            seed, mut dcs, home_dc, store, storage, passcode, passcode_iterations, session, rsa_keys, update_buffer, runtime: _, mut config,
+        } = self;
+        config.rsa_keys = rsa_keys.unwrap_or_else(|| config.environment.rsa_keys());
+        if !seed.is_empty() {
+            let dc = dcs.remove(&seed.id()).unwrap_or_else(|| DcConfig::new(seed.id()));
+            dcs.insert(seed.id(), dc.merge(seed));
+        }
+        if dcs.get(&home_dc).map_or(true, |dc| dc.is_empty()) { return Err(ConfigError::EmptyHomeDc(home_dc).into()); }
+        for dc in dcs.values() {
+            dc.validate()?;
+        }
+        config.validate()?;
+        if update_buffer == 0 { return Err(ConfigError::Zero("update_buffer").into()); }
+
+        let storage = storage.unwrap_or_else(|| Arc::new(MemoryStorage::new()));
+        let (storage, encrypted): (Arc<dyn Storage>, _) = match passcode {
+            Some(passcode) => {
+                // 之前没有加密时, 已有的记录是明文, 第一次解锁时加密保存
+                let mut dc_ids: BTreeSet<_> = dcs.keys().copied().collect();
+                if let Ok(Some(table)) = StorageDcStore(storage.clone()).load() {
+                    dc_ids.extend(table.dcs.iter().map(|dc| dc.id()));
+                }
+                let encrypted = EncryptedStorage::with_iterations(storage, passcode_iterations);
+                encrypted.unlock_and_migrate(&passcode, client_keys(dc_ids))?;
+                (Arc::new(encrypted.clone()), Some(encrypted))
+            }
+            None => (storage, None),
+        };
+        let store = store.unwrap_or_else(|| Arc::new(StorageDcStore(storage.clone())));
+        if let Some(session) = session {
+            session.import(storage.as_ref(), store.as_ref())?;
+        }
+
+        let (tx, rx) = unbounded();
+        let (events, _) = broadcast::channel(64);
+        let (updates, _) = broadcast::channel(update_buffer);
+
+        let config = Arc::new(config);
+        let (events2, weak_updates) = (events.clone(), updates.downgrade());
+        handle.spawn(async move {
+            run_client(rx, dcs, home_dc, store, storage, config, events2, updates).await;
+        });
+
+        Ok(Client { rt, tx, events, updates: weak_updates, storage: encrypted })
+    }
+}
+
+impl Debug for ClientBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ClientBuilder")
+            .field("seed", &self.seed)
+            .field("dcs", &self.dcs)
+            .field("home_dc", &self.home_dc)
+            .field("rsa_keys", &self.rsa_keys)
+            .field("update_buffer", &self.update_buffer)
+            .field("runtime", &self.runtime)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
 /// 外部调用方与异步运行时之间交互的事件
 enum Action {
     Start,
     Stop,
-    SendMsg(Bytes, oneshot::Sender<Result<Bytes>>),
+    Pause,
+    Background,
+    Resume,
+    SendMsg(Bytes, RequestOptions, oneshot::Sender<Result<Bytes>>),
+    RttStats(oneshot::Sender<Vec<RttStats>>),
+    ExportSession(oneshot::Sender<ExportedSession>),
+    /// 挂起连接并释放内存中的密钥, 完成后通知
+    Lock(oneshot::Sender<()>),
     Release,
 }
 
 impl Debug for Action {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match *self {
-            Action::SendMsg(ref bytes, _) => { write!(f, "SendMsg({:?})", bytes) }
+            #[cfg(feature = "trace")]
+            Action::SendMsg(ref bytes, _, _) => { write!(f, "SendMsg({:?})", bytes) }
+            #[cfg(not(feature = "trace"))]
+            Action::SendMsg(ref bytes, _, _) => { write!(f, "SendMsg(len={})", bytes.len()) }
+            Action::RttStats(_) => write!(f, "RttStats"),
+            Action::ExportSession(_) => write!(f, "ExportSession"),
+            Action::Lock(_) => write!(f, "Lock"),
             Action::Start => write!(f, "Start client..."),
             Action::Stop => write!(f, "Stop client!"),
+            Action::Pause => write!(f, "Pause"),
+            Action::Background => write!(f, "Background"),
+            Action::Resume => write!(f, "Resume"),
             Action::Release => write!(f, "Release!!!"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::net::AuthKey;
+    use crate::storage::{dc_state_key, DcState};
+
+    use super::*;
+
+    #[tokio::test]
+    async fn spawn_on_current_runtime() {
+        let client = Client::new("127.0.0.1:1").unwrap();
+        assert!(client.rt.is_none());
+        assert!(client.rtt_stats().await.is_empty());
+        // 在运行时中 drop 不会 panic, 后台任务随之退出
+        drop(client);
+    }
+
+    #[test]
+    fn blocking_client() {
+        let client = crate::blocking::Client::new("127.0.0.1:1").unwrap();
+        assert!(client.rtt_stats().is_empty());
+        client.release();
+    }
+
+    #[test]
+    fn updates_end_after_release() {
+        let client = crate::blocking::Client::new("127.0.0.1:1").unwrap();
+        let mut updates = client.updates();
+        client.release();
+        assert!(updates.next().is_none());
+        // 释放后订阅的同样立即结束
+        assert!(client.updates().next().is_none());
+    }
+
+    #[tokio::test]
+    async fn update_stream_ends_after_release() {
+        use tokio_stream::StreamExt;
+
+        let client = Client::new("127.0.0.1:1").unwrap();
+        let mut updates = client.updates();
+        client.release();
+        assert!(updates.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn build_from_config() {
+        let config = ClientConfig { addrs: vec!["127.0.0.1:1".into()], ..Default::default() };
+        assert!(ClientBuilder::from_config(config.clone()).unwrap().build().is_ok());
+
+        let e = ClientBuilder::from_config(ClientConfig { rsa_keys: vec!["invalid".into()], ..config.clone() }).unwrap_err();
+        assert!(matches!(e.downcast::<ConfigError>().unwrap(), ConfigError::RsaKey(_)));
+
+        let e = ClientBuilder::from_config(ClientConfig { home_dc: 2, ..config.clone() }).unwrap().build().err().unwrap();
+        assert_eq!(e.downcast::<ConfigError>().unwrap(), ConfigError::EmptyHomeDc(2));
+
+        let e = ClientBuilder::from_config(ClientConfig { request_timeout_ms: 0, ..config.clone() }).unwrap().build().err().unwrap();
+        assert_eq!(e.downcast::<ConfigError>().unwrap(), ConfigError::Zero("request_timeout"));
+
+        let transport = serde_json::from_str(r#"{ "transports": [] }"#).unwrap();
+        let e = ClientBuilder::from_config(ClientConfig { transport, ..config }).unwrap().build().err().unwrap();
+        assert_eq!(e.downcast::<ConfigError>().unwrap(), ConfigError::NoTransport);
+    }
+
+    #[tokio::test]
+    async fn lock_with_passcode() {
+        let storage = Arc::new(MemoryStorage::new());
+        let builder = || Client::builder("127.0.0.1:1").storage(storage.clone()).passcode_iterations(10);
+        let client = builder().passcode("1234").build().unwrap();
+        client.lock().await.unwrap();
+        assert!(client.unlock("0000").is_err());
+        client.unlock("1234").unwrap();
+        client.change_passcode("1234", "5678").unwrap();
+
+        let e = builder().passcode("1234").build().err().unwrap();
+        assert_eq!(e.downcast::<SecurityError>().unwrap(), SecurityError::WrongPasscode);
+
+        let client = Client::new("127.0.0.1:1").unwrap();
+        assert_eq!(client.lock().await.unwrap_err().downcast::<SecurityError>().unwrap(), SecurityError::NotEncrypted);
+    }
+
+    #[tokio::test]
+    async fn import_and_export_session() {
+        let state = DcState {
+            perm_key: AuthKey::from_bytes([1; 256]),
+            temp_key: None,
+            session_id: 3,
+            server_salt: 4,
+            time_diff: 5,
+        };
+        let session = ExportedSession { home_dc: 2, user_id: 42, dcs: vec![(1, state.clone())] };
+        let storage = Arc::new(MemoryStorage::new());
+        let builder = || Client::builder("127.0.0.1:1").dc(DcConfig::new(2).addrs("127.0.0.2:1")).storage(storage.clone());
+
+        let client = builder().session(session.to_string().parse().unwrap()).build().unwrap();
+        assert_eq!(client.export_session().await.unwrap(), session);
+        // 重新创建时从 storage 恢复
+        let client = builder().build().unwrap();
+        assert_eq!(client.export_session().await.unwrap(), session);
+
+        let client = Client::new("127.0.0.1:1").unwrap();
+        assert!(client.export_session().await.is_err());
+
+        // 开启加密时已有的明文记录加密保存
+        let client = builder().passcode("1234").passcode_iterations(10).build().unwrap();
+        assert_eq!(client.export_session().await.unwrap(), session);
+        assert_ne!(storage.get(&dc_state_key(1)).unwrap(), Some(state.encode()));
+    }
+}
@@ -4,16 +4,34 @@ pub use debug::*;
 pub use release::*;
 
 pub const PFS_ENABLED: bool = true;
+/// 客户端使用的 API layer, 随新会话的第一个请求发送
+pub const LAYER: i32 = 185;
 /// 接收网络数据的缓冲区大小
 pub const READ_BUFFER_SIZE: usize = 1024 * 1024 * 2;
 pub const TEMP_AUTH_KEY_EXPIRE_TIME: i32 = 24 * 60 * 60;
+/// 临时密钥在过期前多久更换, 单位: 秒
+pub const TEMP_AUTH_KEY_ROTATE_BEFORE: i32 = 10 * 60;
+/// `ping_delay_disconnect` 的 disconnect_delay, 服务器在该时间内没有收到下一个 ping 时断开连接, 单位: 秒
+pub const PING_DISCONNECT_DELAY: i32 = 35;
+/// 后台模式的 disconnect_delay, 需要大于 [BACKGROUND_PING_DURATION]
+pub const BACKGROUND_PING_DISCONNECT_DELAY: i32 = 90;
+/// 每个 updates 订阅者最多缓冲的 updates 数量, 超过后丢弃最早的
+pub const UPDATE_BUFFER_SIZE: usize = 256;
+/// 连续多少次没有收到 pong 时认为连接已断开
+pub const MAX_MISSED_PONGS: u32 = 2;
+/// 从口令派生本地数据加密密钥时, PBKDF2 的迭代次数
+pub const PASSCODE_KDF_ITERATIONS: u32 = 600_000;
+/// 保存的数据密钥记录中允许的最大迭代次数, 被篡改的记录不能让解锁长时间占用 CPU
+pub const PASSCODE_KDF_MAX_ITERATIONS: u32 = 10_000_000;
 
 #[cfg(debug_assertions)]
 mod debug {
     pub const PING_DURATION: i64 = 5000;
+    pub const BACKGROUND_PING_DURATION: i64 = 15000;
 }
 
 #[cfg(not(debug_assertions))]
 mod release {
     pub const PING_DURATION: i64 = 19000;
+    pub const BACKGROUND_PING_DURATION: i64 = 60000;
 }
@@ -1,14 +1,18 @@
 extern crate core;
 
-pub use client::Client;
-pub use net::{Addr, Addrs};
+pub use client::{Client, ClientBuilder};
+pub use net::{Addr, Addrs, AppInfo, ClientConfig, ConfigError, ConnState, ConnStateEvent, ConnType, DcConfig, DcEntry, DcStore, DcTable, DEFAULT_DC_ID, Environment, FileDcStore, KeepaliveConfig, MemoryDcStore, PoolConfig, ProxyAuth, ProxyConfig, ReconnectConfig, RequestError, RequestOptions, ResolveFuture, Resolver, RsaKeys, RttStats, SocketConfig, StaticResolver, SystemResolver, TlsConfig, TransportConfig, TransportOptions, Lagged, UpdateStream};
+pub use proto::Update;
 
 #[macro_use]
 mod macros;
 
+pub mod blocking;
 mod client;
 pub mod defines;
 mod net;
 pub mod proto;
+pub mod security;
+pub mod storage;
 
 pub mod util;
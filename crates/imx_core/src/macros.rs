@@ -9,10 +9,19 @@ macro_rules! cfg_net_tcp {
     }
 }
 
+macro_rules! cfg_net_tls {
+    ($($item:item)*) => {
+        $(
+            #[cfg(feature = "tls")]
+            $item
+        )*
+    }
+}
+
 macro_rules! cfg_net_quic {
     ($($item:item)*) => {
         $(
-            #[cfg(all(feature = "quic", not(feature = "tcp")))]
+            #[cfg(feature = "quic")]
             $item
         )*
     }
@@ -21,8 +30,27 @@ macro_rules! cfg_net_quic {
 macro_rules! cfg_net_ws {
     ($($item:item)*) => {
         $(
-            #[cfg(all(feature = "ws", not(feature = "tcp"), not(feature = "quic")))]
+            #[cfg(feature = "ws")]
             $item
         )*
     }
-}
\ No newline at end of file
+}
+/// 需要 rustls 的 socket: tls, quic
+macro_rules! cfg_net_rustls {
+    ($($item:item)*) => {
+        $(
+            #[cfg(any(feature = "tls", feature = "quic"))]
+            $item
+        )*
+    }
+}
+
+/// 基于 tcp 流的 socket: tcp, tls, ws
+macro_rules! cfg_net_stream {
+    ($($item:item)*) => {
+        $(
+            #[cfg(any(feature = "tcp", feature = "tls", feature = "ws", not(feature = "quic")))]
+            $item
+        )*
+    }
+}
@@ -1,8 +1,8 @@
+use core::fmt::{Display, Formatter};
 use core::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 use core::ops::Index;
 use core::str::FromStr;
-use std::borrow::Borrow;
-use std::net::{AddrParseError, IpAddr};
+use std::net::AddrParseError;
 
 /// An internet address, like [SocketAddr]
 #[derive(Debug, Clone)]
@@ -13,7 +13,7 @@ pub enum Addr {
     Custom(String),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct Addrs(Vec<Addr>);
 
 impl PartialEq for Addr {
@@ -26,12 +26,68 @@ impl PartialEq for Addr {
     }
 }
 
+impl Addr {
+    /// 地址前缀指定的连接协议, 例如 `ws://example.com/apiws` 返回 `ws`, 没有前缀时为 `tcp`
+    pub fn scheme(&self) -> &str {
+        match self {
+            Addr::SocketAddr(_) => "tcp",
+            Addr::Custom(s) => match s.find("://") {
+                Some(i) => &s[..i],
+                None => "tcp",
+            }
+        }
+    }
+
+    /// 是否为 IPv6 地址, 域名返回 `false`
+    pub fn is_ipv6(&self) -> bool {
+        match self {
+            Addr::SocketAddr(addr) => addr.is_ipv6(),
+            Addr::Custom(s) => strip_scheme(s).starts_with('['),
+        }
+    }
+
+    /// 去掉协议前缀之后的部分
+    pub fn without_scheme(&self) -> String {
+        match self {
+            Addr::SocketAddr(addr) => addr.to_string(),
+            Addr::Custom(s) => strip_scheme(s).to_owned(),
+        }
+    }
+}
+
+impl Display for Addr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Addr::SocketAddr(addr) => addr.fmt(f),
+            Addr::Custom(s) => f.write_str(s),
+        }
+    }
+}
+
 impl Addrs {
     #[inline]
     pub fn is_empty(&self) -> bool { self.0.is_empty() }
 
     #[inline]
     pub fn len(&self) -> usize { self.0.len() }
+
+    #[inline]
+    pub fn iter(&self) -> impl Iterator<Item = &Addr> { self.0.iter() }
+}
+
+impl Extend<Addr> for Addrs {
+    fn extend<I: IntoIterator<Item = Addr>>(&mut self, iter: I) {
+        self.0.extend(iter)
+    }
+}
+
+impl IntoIterator for Addrs {
+    type Item = Addr;
+    type IntoIter = std::vec::IntoIter<Addr>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
 }
 
 impl Index<usize> for Addrs {
@@ -48,11 +104,32 @@ impl TryFrom<Addr> for SocketAddr {
     fn try_from(value: Addr) -> Result<Self, Self::Error> {
         match value {
             Addr::SocketAddr(s) => Ok(s),
-            Addr::Custom(s) => SocketAddr::from_str(s.as_str())
+            Addr::Custom(s) => SocketAddr::from_str(strip_scheme(&s))
         }
     }
 }
 
+/// 拆分 `host:port` 或 `[ipv6]:port`, 地址中的路径部分会被忽略
+pub(crate) fn split_host_port(s: &str) -> Option<(&str, u16)> {
+    let s = strip_scheme(s);
+    let s = match s.find('/') {
+        Some(i) => &s[..i],
+        None => s,
+    };
+    let i = s.rfind(':')?;
+    let port = s[i + 1..].parse().ok()?;
+    let host = s[..i].trim_start_matches('[').trim_end_matches(']');
+    if host.is_empty() { return None; }
+    Some((host, port))
+}
+
+fn strip_scheme(s: &str) -> &str {
+    match s.find("://") {
+        Some(i) => &s[i + 3..],
+        None => s,
+    }
+}
+
 // =========== From ================
 impl From<SocketAddr> for Addr {
     fn from(addr: SocketAddr) -> Self {
@@ -69,6 +146,15 @@ impl From<&str> for Addr {
     }
 }
 
+impl From<String> for Addr {
+    fn from(value: String) -> Self {
+        match SocketAddr::from_str(&value) {
+            Ok(addr) => Addr::SocketAddr(addr),
+            _ => Addr::Custom(value)
+        }
+    }
+}
+
 impl<T: AsRef<str>> From<(T, u16)> for Addr {
     fn from(value: (T, u16)) -> Self {
         let ip = value.0.as_ref();
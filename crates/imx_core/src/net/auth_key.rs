@@ -1,9 +1,18 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::Mutex;
+
+use tokio::sync::MutexGuard;
+use zeroize::Zeroizing;
+
+use crate::defines::TEMP_AUTH_KEY_ROTATE_BEFORE;
+use crate::net::Session;
 use crate::sha1;
 
-#[derive(Debug, Copy, Clone)]
+/// 与服务器协商的 2048 位密钥, drop 时清零. [Debug] 只输出 [AuthKey::id]
+#[derive(Clone, PartialEq, Eq)]
 pub struct AuthKey {
     pub id: i64,
-    bytes: [u8; 256],
+    bytes: Zeroizing<[u8; 256]>,
 }
 
 impl AuthKey {
@@ -14,10 +23,10 @@ impl AuthKey {
             arr.copy_from_slice(&sha[12..]);
             i64::from_le_bytes(arr)
         };
-        Self { id, bytes, }
+        Self { id, bytes: Zeroizing::new(bytes) }
     }
 
-    pub fn to_bytes(&self) -> [u8; 256] { self.bytes }
+    pub fn as_bytes(&self) -> &[u8; 256] { &self.bytes }
 }
 
 impl From<[u8; 256]> for AuthKey {
@@ -26,3 +35,132 @@ impl From<[u8; 256]> for AuthKey {
     }
 }
 
+impl Debug for AuthKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AuthKey")
+            .field("id", &self.id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// PFS 的临时密钥
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct TempKey {
+    pub key: AuthKey,
+    /// 服务器时间, 单位: 秒
+    pub expires_at: i32,
+}
+
+impl TempKey {
+    /// 是否需要更换, 提前 [TEMP_AUTH_KEY_ROTATE_BEFORE] 秒
+    pub fn expiring(&self, session: &Session) -> bool {
+        self.expires_at - session.server_time() <= TEMP_AUTH_KEY_ROTATE_BEFORE
+    }
+}
+
+#[derive(Default)]
+struct Keys {
+    perm: Option<AuthKey>,
+    temp: Option<TempKey>,
+    media_temp: Option<TempKey>,
+}
+
+/// 同一 DataCenter 的连接共用的永久密钥和 PFS 临时密钥. 媒体连接使用单独的临时密钥.
+/// 同时只进行一个握手, 其他需要密钥的连接等待它结束后直接使用结果
+#[derive(Default)]
+pub(crate) struct AuthKeys {
+    keys: Mutex<Keys>,
+    handshake: tokio::sync::Mutex<()>,
+}
+
+impl AuthKeys {
+    pub fn perm(&self) -> Option<AuthKey> {
+        self.keys.lock().unwrap().perm.clone()
+    }
+
+    pub fn set_perm(&self, key: Option<AuthKey>) {
+        self.keys.lock().unwrap().perm = key;
+    }
+
+    pub fn temp(&self, media: bool) -> Option<TempKey> {
+        let keys = self.keys.lock().unwrap();
+        if media { keys.media_temp.clone() } else { keys.temp.clone() }
+    }
+
+    pub fn set_temp(&self, media: bool, key: Option<TempKey>) {
+        let mut keys = self.keys.lock().unwrap();
+        if media { keys.media_temp = key } else { keys.temp = key }
+    }
+
+    /// 返回永久密钥, 没有时返回握手的 guard, 调用方生成密钥并 [AuthKeys::set_perm] 后释放.
+    /// 其他连接正在握手时等待它结束
+    pub async fn perm_or_lock(&self) -> Result<AuthKey, MutexGuard<'_, ()>> {
+        self.get_or_lock(|| self.perm()).await
+    }
+
+    /// 返回未过期的临时密钥, 没有时返回握手的 guard, 与 [AuthKeys::perm_or_lock] 相同
+    pub async fn temp_or_lock(&self, media: bool, session: &Session) -> Result<TempKey, MutexGuard<'_, ()>> {
+        self.get_or_lock(|| self.temp(media).filter(|t| !t.expiring(session))).await
+    }
+
+    async fn get_or_lock<T>(&self, get: impl Fn() -> Option<T>) -> Result<T, MutexGuard<'_, ()>> {
+        if let Some(key) = get() {
+            return Ok(key);
+        }
+        let guard = self.handshake.lock().await;
+        get().ok_or(guard)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn debug_redacted() {
+        let key = AuthKey::from_bytes(std::array::from_fn(|i| i as u8));
+        let text = format!("{:?}", key);
+        assert_eq!(text, format!("AuthKey {{ id: {}, .. }}", key.id));
+        // 不包含任何一段密钥字节
+        let bytes = format!("{:?}", &key.as_bytes()[..8]);
+        assert!(!text.contains(bytes.trim_matches(['[', ']'])));
+    }
+
+    #[tokio::test]
+    async fn single_handshake() {
+        let keys = Arc::new(AuthKeys::default());
+        let handshakes = Arc::new(AtomicUsize::new(0));
+        let tasks: Vec<_> = (0..4).map(|_| {
+            let (keys, handshakes) = (keys.clone(), handshakes.clone());
+            tokio::spawn(async move {
+                match keys.perm_or_lock().await {
+                    Ok(key) => key,
+                    Err(_guard) => {
+                        handshakes.fetch_add(1, Ordering::Relaxed);
+                        tokio::time::sleep(Duration::from_millis(20)).await;
+                        let key = AuthKey::from_bytes([1; 256]);
+                        keys.set_perm(Some(key.clone()));
+                        key
+                    }
+                }
+            })
+        }).collect();
+        for task in tasks {
+            assert_eq!(task.await.unwrap(), AuthKey::from_bytes([1; 256]));
+        }
+        assert_eq!(handshakes.load(Ordering::Relaxed), 1);
+
+        // 临时密钥按类型分开, 快过期的需要重新生成
+        let session = Session::new();
+        let temp = TempKey { key: AuthKey::from_bytes([2; 256]), expires_at: session.server_time() + 3600 };
+        keys.set_temp(false, Some(temp.clone()));
+        assert_eq!(keys.temp_or_lock(false, &session).await.ok(), Some(temp));
+        assert!(keys.temp_or_lock(true, &session).await.is_err());
+        keys.set_temp(false, Some(TempKey { key: AuthKey::from_bytes([3; 256]), expires_at: session.server_time() }));
+        assert!(keys.temp_or_lock(false, &session).await.is_err());
+    }
+}
@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::net::ReconnectConfig;
+
+/// 指数退避, 每次调用 [Backoff::next_delay] 间隔按倍数增长, 直到上限
+#[derive(Debug, Clone)]
+pub(crate) struct Backoff {
+    config: ReconnectConfig,
+    attempts: u32,
+}
+
+impl Backoff {
+    pub fn new(config: ReconnectConfig) -> Self {
+        Self { config, attempts: 0 }
+    }
+
+    /// 连续失败的次数
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// 下一次重连前的等待时间, 超过最大重连次数时返回 `None`
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        if let Some(max) = self.config.max_attempts {
+            if self.attempts >= max { return None; }
+        }
+        let ReconnectConfig { initial_delay, max_delay, multiplier, jitter, .. } = self.config;
+
+        let exp = multiplier.powi(self.attempts.min(64) as i32);
+        let delay = initial_delay.as_secs_f64() * exp;
+        let delay = delay.min(max_delay.as_secs_f64());
+        let delay = if jitter > 0.0 {
+            delay * rand::thread_rng().gen_range(1.0 - jitter..=1.0 + jitter)
+        } else {
+            delay
+        };
+        self.attempts += 1;
+
+        Some(Duration::from_secs_f64(delay.min(max_delay.as_secs_f64())))
+    }
+
+    /// 连接成功后重置
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> ReconnectConfig {
+        ReconnectConfig::new()
+            .initial_delay(Duration::from_millis(100))
+            .max_delay(Duration::from_secs(1))
+            .multiplier(2.0)
+            .jitter(0.0)
+    }
+
+    #[test]
+    fn growth_and_cap() {
+        let mut backoff = Backoff::new(config());
+        let delays: Vec<_> = (0..6).map(|_| backoff.next_delay().unwrap().as_millis()).collect();
+        assert_eq!(delays, [100, 200, 400, 800, 1000, 1000]);
+        assert_eq!(backoff.attempts(), 6);
+
+        // 连接成功后从头开始
+        backoff.reset();
+        assert_eq!(backoff.attempts(), 0);
+        assert_eq!(backoff.next_delay(), Some(Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn jitter_bounds() {
+        let mut backoff = Backoff::new(config().jitter(0.5));
+        for _ in 0..100 {
+            backoff.reset();
+            let delay = backoff.next_delay().unwrap();
+            assert!((Duration::from_millis(50)..=Duration::from_millis(150)).contains(&delay), "{:?}", delay);
+        }
+        // 抖动之后仍然不超过上限
+        for _ in 0..100 {
+            let delay = backoff.next_delay().unwrap();
+            assert!(delay <= Duration::from_secs(1), "{:?}", delay);
+        }
+    }
+
+    #[test]
+    fn max_attempts() {
+        let mut backoff = Backoff::new(config().max_attempts(2));
+        assert!(backoff.next_delay().is_some());
+        assert!(backoff.next_delay().is_some());
+        assert_eq!(backoff.next_delay(), None);
+        backoff.reset();
+        assert!(backoff.next_delay().is_some());
+    }
+}
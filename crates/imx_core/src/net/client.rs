@@ -1,122 +1,674 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
 use std::time::Duration;
+
 use anyhow::{bail, Result};
+use async_channel::Receiver;
 use bytes::Bytes;
-use dashmap::DashMap;
-use log::{error, info};
-use tokio::time::sleep;
+use log::{debug, info, warn};
+use tokio::sync::{broadcast, oneshot};
+use tokio::sync::oneshot::error::TryRecvError;
+use tokio::time::Instant;
+
+use crate::net::{DataCenter, DcConfig, DcStore, DcTable, NetConfig, RequestError, RequestOptions, RttStats};
+use crate::net::connection::{ConnType, StateSender};
+use crate::net::dispatcher::{Dispatcher, Signal};
+use crate::proto;
+use crate::proto::{ExportAuthorization, ExportedAuthorization, GetConfig, ImportAuthorization};
+use crate::proto::auth::parse_exported_authorization;
+use crate::proto::config::{Config, parse_config};
+use crate::proto::Update;
+use crate::storage::{dc_state_key, DcState, ExportedSession, Storage, USER_ID_KEY};
 
-use crate::net::{Addrs, DataCenter};
-use crate::net::connection::{ConnType, Connection};
-use crate::net::error::Error;
-use crate::net::event::{Event, event_channel, EventReceiver, EventSender};
-use crate::net::socket::Socket;
-use crate::util::HashMap;
+/// 获取配置失败后重试的间隔
+const CONFIG_RETRY: Duration = Duration::from_secs(60);
+
+/// 网络模式, 移动端切到后台时减少连接和 ping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Foreground,
+    /// 只保留 home DC 的 push 连接, 请求等待恢复后发送
+    Background,
+    /// 挂起所有连接
+    Paused,
+}
 
 /// 网络消息客户端
 pub(crate) struct Client {
+    /// 已建立的 DataCenter, 在第一次发送请求时创建
     data_centers: HashMap<i32, DataCenter>,
-    cur_dc_id: i32,
+    /// [ClientBuilder](crate::ClientBuilder) 传入的地址
+    seeds: HashMap<i32, DcConfig>,
+    /// 从服务器获取的地址, 优先于 [seeds]
+    fetched: HashMap<i32, DcConfig>,
+    store: Arc<dyn DcStore>,
+    /// 保存各个 DataCenter 的密钥和会话
+    storage: Arc<dyn Storage>,
+    /// 最近一次保存的状态, 没有变化时不再写入
+    saved: HashMap<i32, DcState>,
+    /// 等待中的 `help.getConfig` 请求
+    config_rx: Option<oneshot::Receiver<Result<Bytes>>>,
+    /// 下次获取配置的时间
+    config_at: Option<Instant>,
+    /// 正在从 home DC 导入授权的 DataCenter
+    importing: HashSet<i32>,
+    mode: Mode,
     cur_user_id: i64,
-    sending_push_ping: bool,
-    sending_ping: bool,
+    config: Arc<NetConfig>,
+    events: StateSender,
+    dispatcher: Arc<Dispatcher>,
 }
 
 impl Client {
 
-    pub fn new(addrs: Addrs) -> Self {
-        let len = addrs.len();
-        let mut data_centers = HashMap::default();
-        for i in 0..len {
-            let addr = addrs[i].clone();
-            let dc = DataCenter::new(i as i32, addr);
-            data_centers.insert(i as i32, dc);
-        }
-
+    /// [seeds] 为各个 DataCenter 的初始地址, 没有指定 DataCenter 的请求发送到 [home_dc].
+    /// [store] 中保存了上次获取的地址和 home DC 时优先使用, [storage] 中保存了密钥时连接后不需要握手
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        seeds: HashMap<i32, DcConfig>,
+        home_dc: i32,
+        store: Arc<dyn DcStore>,
+        storage: Arc<dyn Storage>,
+        config: Arc<NetConfig>,
+        events: StateSender,
+        updates: broadcast::Sender<Update>,
+    ) -> Self {
+        debug_assert!(seeds.contains_key(&home_dc));
+        let (fetched, home_dc) = match store.load() {
+            Ok(Some(table)) => {
+                let fetched: HashMap<_, _> = table.dcs.into_iter().map(|dc| (dc.id(), dc)).collect();
+                let home_dc = if fetched.contains_key(&table.home_dc) || seeds.contains_key(&table.home_dc) {
+                    table.home_dc
+                } else {
+                    home_dc
+                };
+                (fetched, home_dc)
+            }
+            Ok(None) => (HashMap::new(), home_dc),
+            Err(e) => {
+                warn!("Load dc options failed: {}", e);
+                (HashMap::new(), home_dc)
+            }
+        };
+        let cur_user_id = match storage.get(USER_ID_KEY) {
+            Ok(Some(bytes)) => bytes.try_into().map(i64::from_le_bytes).unwrap_or_else(|_| {
+                warn!("Invalid user id in storage");
+                0
+            }),
+            Ok(None) => 0,
+            Err(e) => {
+                warn!("Load user id failed: {}", e);
+                0
+            }
+        };
         Self {
-            data_centers,
-            cur_dc_id: 0,
-            cur_user_id: 0,
-            sending_push_ping: false,
-            sending_ping: false,
+            data_centers: HashMap::new(),
+            seeds,
+            fetched,
+            store,
+            storage,
+            saved: HashMap::new(),
+            config_rx: None,
+            config_at: None,
+            importing: HashSet::new(),
+            mode: Mode::Foreground,
+            cur_user_id,
+            config,
+            events,
+            dispatcher: Arc::new(Dispatcher::new(updates, home_dc)),
         }
     }
 
+    /// 在后台连接 home DC 并获取最新的 DataCenter 配置, 失败时由 [Client::select] 按退避策略重连
+    pub fn connect(&mut self) -> Result<()> {
+        let home_dc = self.dispatcher.home_dc();
+        let Some(dc) = self.data_center(home_dc) else {
+            bail!(RequestError::UnknownDc(home_dc));
+        };
+        dc.connect();
+        self.fetch_config();
+        self.process_request_queue();
+        Ok(())
+    }
+
     /// 每秒执行一次
-    pub async fn select(&mut self) -> Result<()> {
-        let cur_dc_id = self.cur_dc_id;
-        debug_assert!(self.data_centers.contains_key(&cur_dc_id));
-        let mut dc = self.data_centers.get_mut(&cur_dc_id).unwrap();
-
-        let conn = dc.generic_conn().await?;
-
-        // 处理 events
-        let rx = conn.socket.receiver();
-        let ev_count = rx.len();
-        info!("events count: {}", ev_count);
-        for _ in 0..ev_count {
-            match rx.recv().await {
-                Event::OnReceivedData(_) => {}
-                Event::OnSocketError(e) => {
-                    error!("{}", e);
+    pub fn select(&mut self) {
+        for dc in self.data_centers.values_mut() {
+            // 断开后按时重连
+            dc.tick();
+
+            let requests = self.dispatcher.requests();
+            dc.close_idle(|token| requests.in_flight(token));
+        }
+        self.poll_config();
+        self.process_request_queue();
+        self.save_states();
+    }
+
+    pub fn destroy(&mut self) {
+        self.save_states();
+        for dc in self.data_centers.values_mut() {
+            dc.close();
+        }
+        self.dispatcher.requests().fail_all(RequestError::Stopped);
+    }
+
+    /// 各个连接读写任务发出的通知
+    pub fn signals(&self) -> Receiver<Signal> {
+        self.dispatcher.signals()
+    }
+
+    pub fn on_signal(&mut self, signal: Signal) {
+        match signal {
+            Signal::Disconnected { dc_id, conn_type, token } => {
+                debug!("(dc{} {:?}) Connection lost", dc_id, conn_type);
+                if let Some(dc) = self.data_centers.get_mut(&dc_id) {
+                    dc.on_lost(token);
+                }
+            }
+            Signal::Dialed { dc_id } => {
+                let connected = self.data_centers.get_mut(&dc_id).is_some_and(|dc| dc.on_dialed());
+                // 新的连接可能带来新的密钥, 等待连接的请求可以发送
+                if connected {
+                    self.save_states();
+                    self.process_request_queue();
+                }
+            }
+            Signal::Resend => self.process_request_queue(),
+            Signal::Migrate { dc_id } => {
+                if self.has_dc(dc_id) {
+                    info!("Switch home dc {} -> {}", self.dispatcher.home_dc(), dc_id);
+                    self.dispatcher.set_home_dc(dc_id);
+                    self.save_dc_table();
+                } else {
+                    warn!("Migrate to unknown dc {}", dc_id);
+                }
+                self.process_request_queue();
+            }
+            Signal::Unauthorized { dc_id } => {
+                if self.importing.insert(dc_id) {
+                    info!("(dc{}) Import authorization from dc{}", dc_id, self.dispatcher.home_dc());
+                    let dispatcher = self.dispatcher.clone();
+                    let timeout = self.config.request_timeout;
+                    tokio::spawn(async move {
+                        // 任务出错, panic 或被取消时都需要通知, 否则发送到 dc_id 的请求一直等待
+                        let _imported = SignalOnDrop(&dispatcher, Signal::Imported { dc_id });
+                        if let Err(e) = transfer_authorization(&dispatcher, dc_id, timeout).await {
+                            warn!("(dc{}) Import authorization failed: {}", dc_id, e);
+                        }
+                    });
                 }
-                Event::OnIntercepted => {
-                    dc.close().await;
-                    bail!(Error::Intercepted);
+                self.process_request_queue();
+            }
+            Signal::Imported { dc_id } => {
+                self.importing.remove(&dc_id);
+                self.process_request_queue();
+            }
+            Signal::Authorized { user_id } => {
+                if user_id == self.cur_user_id { return; }
+                info!("Authorized as user {}", user_id);
+                self.cur_user_id = user_id;
+                if let Err(e) = self.storage.set(USER_ID_KEY, &user_id.to_le_bytes()) {
+                    warn!("Save user id failed: {}", e);
                 }
             }
         }
+    }
+
+    /// 挂起所有连接, 请求在 [Client::resume] 后发送
+    pub fn pause(&mut self) {
+        info!("Pause network");
+        self.mode = Mode::Paused;
+        for dc in self.data_centers.values_mut() {
+            dc.suspend(false);
+        }
+    }
+
+    /// 锁定本地存储前调用: 挂起所有连接, 保存最新的状态后释放所有 DataCenter,
+    /// 内存中不再保留密钥和会话. 解锁后 [Client::resume] 从 storage 重新加载
+    pub fn lock(&mut self) {
+        self.pause();
+        self.save_states();
+        for dc in self.data_centers.values_mut() {
+            dc.close();
+        }
+        self.data_centers.clear();
+        self.saved.clear();
+    }
+
+    /// 后台模式: 关闭 push 以外的连接, 保持 home DC 的 push 连接并延长 keepalive 间隔
+    pub fn background(&mut self) {
+        info!("Enter background");
+        self.mode = Mode::Background;
+        let home_dc = self.dispatcher.home_dc();
+        for dc in self.data_centers.values_mut() {
+            dc.set_background(true);
+            dc.suspend(dc.id == home_dc);
+        }
+        if let Some(dc) = self.data_center(home_dc) {
+            dc.set_background(true);
+            dc.resume(true);
+            dc.prepare(ConnType::Push, 1);
+        }
+    }
+
+    /// 恢复前台模式, 重新连接挂起的连接并发送等待中的请求
+    pub fn resume(&mut self) {
+        if self.mode == Mode::Foreground { return; }
+        info!("Resume network");
+        self.mode = Mode::Foreground;
+        // 锁定时释放了所有 DataCenter, 重新建立 home DC 的连接
+        let home_dc = self.dispatcher.home_dc();
+        if !self.data_centers.contains_key(&home_dc) {
+            if let Some(dc) = self.data_center(home_dc) {
+                dc.connect();
+            }
+        }
+        for dc in self.data_centers.values_mut() {
+            dc.set_background(false);
+            dc.resume(false);
+        }
+        self.process_request_queue();
+    }
 
-        // ping
-        conn.ping().await?;
+    /// 请求加入队列, 结果通过 [tx] 返回
+    pub fn send_msg(&mut self, msg: Bytes, options: RequestOptions, tx: oneshot::Sender<Result<Bytes>>) {
+        let id = self.dispatcher.requests().push(msg, options, self.config.request_timeout, tx);
+        debug!("Request {} queued", id);
+        self.process_request_queue();
+    }
 
-        Ok(())
+    /// 所有已收到 pong 的连接的 RTT
+    pub fn rtt_stats(&self) -> Vec<RttStats> {
+        self.data_centers.values().flat_map(|dc| dc.rtt_stats()).collect()
+    }
+
+    /// 最早的请求超时时间
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.dispatcher.requests().next_deadline()
     }
 
-    pub async fn destroy(&mut self) {
-        for dc in &mut self.data_centers {
-            dc.close().await;
+    /// 移除超时的请求, 按请求的 DataCenter 和 [ConnType] 在对应的连接上发送 (或重发) 等待中的请求
+    pub fn process_request_queue(&mut self) {
+        let home_dc = self.dispatcher.home_dc();
+
+        let mut demand = HashMap::<(i32, ConnType), usize>::new();
+        {
+            let mut requests = self.dispatcher.requests();
+            requests.expire(Instant::now());
+            for p in requests.pending(home_dc, |token| self.is_live(token)) {
+                if !self.has_dc(p.dc_id) {
+                    requests.fail(p.id, RequestError::UnknownDc(p.dc_id));
+                } else if p.without_auth || !self.importing.contains(&p.dc_id) {
+                    *demand.entry((p.dc_id, p.conn_type)).or_default() += 1;
+                }
+            }
+        }
+        // 暂停或后台时只移除超时的请求, 恢复后再发送
+        if demand.is_empty() || self.mode != Mode::Foreground { return; }
+
+        // 按需建立 DataCenter 和连接, 放弃重连的连接在有请求时重新连接
+        for ((dc_id, conn_type), count) in demand {
+            if let Some(dc) = self.data_center(dc_id) {
+                dc.prepare(conn_type, count);
+            }
+        }
+
+        // 发送期间持有请求队列, 保证响应到达前已经记录了 msg_id.
+        // 等待导入授权的请求在导入完成后发送
+        let mut requests = self.dispatcher.requests();
+        for p in requests.pending(home_dc, |token| self.is_live(token)) {
+            if !p.without_auth && self.importing.contains(&p.dc_id) { continue; }
+            let Some(dc) = self.data_centers.get_mut(&p.dc_id) else { continue; };
+            match dc.send(p.conn_type, &p.body) {
+                Ok((msg_id, token)) => requests.sent(p.id, p.dc_id, msg_id, token),
+                Err(e) => debug!("Request {} is waiting: {}", p.id, e),
+            }
         }
     }
 
-    pub async fn send_msg(&self, msg: Bytes) -> Result<Bytes> {
-        info!("send: {:?}", msg);
-        todo!()
+    /// [token] 是否为未断开的连接
+    fn is_live(&self, token: u32) -> bool {
+        self.data_centers.values().any(|dc| dc.is_live(token))
     }
 
-    async fn send_ping(&mut self, dc: &DataCenter, use_push: bool) {
+    fn has_dc(&self, dc_id: i32) -> bool {
+        self.fetched.contains_key(&dc_id) || self.seeds.contains_key(&dc_id)
+    }
 
+    /// [dc_id] 的地址, 从服务器获取的地址在前, 初始地址作为备用
+    fn dc_config(&self, dc_id: i32) -> Option<DcConfig> {
+        match (self.fetched.get(&dc_id), self.seeds.get(&dc_id)) {
+            (Some(fetched), Some(seed)) => Some(fetched.clone().merge(seed.clone())),
+            (fetched, seed) => fetched.or(seed).cloned(),
+        }
     }
 
-    async fn process_request_queue(&self, _conn_type: ConnType, _dc_id: usize) {
-        info!("process_request_queue");
+    /// [dc_id] 对应的 DataCenter, 第一次使用时创建
+    fn data_center(&mut self, dc_id: i32) -> Option<&mut DataCenter> {
+        if !self.data_centers.contains_key(&dc_id) {
+            let addrs = self.dc_config(dc_id)?;
+            let mut dc = DataCenter::new(addrs, self.config.clone(), self.events.clone(), self.dispatcher.clone());
+            if let Some(state) = self.load_state(dc_id) {
+                debug!("(dc{}) Restore auth key {}", dc_id, state.perm_key.id);
+                self.saved.insert(dc_id, state.clone());
+                dc.restore(state);
+            }
+            self.data_centers.insert(dc_id, dc);
+        }
+        self.data_centers.get_mut(&dc_id)
     }
 
-    pub async fn on_connection_connected(&mut self, dc_id: usize, conn_type: ConnType) -> Result<()> {
-        // let dc = self.data_centers[dc_id].clone();
-        //
-        // if (conn_type == ConnType::Generic || conn_type == ConnType::GenericMedia)
-        //     && dc.is_handshaking_any() {
-        //     return dc.on_handshake_connection_connected(conn_type).await;
-        // }
-        //
-        // if dc.has_auth_key(conn_type, true) {
-        //     if conn_type == ConnType::Push {
-        //         // sendingPushPing = false;
-        //         // lastPushPingTime = getCurrentTimeMonotonicMillis();
-        //         self.send_ping(&dc, true).await;
-        //     } else {
-        //         if conn_type == ConnType::Generic && dc_id == self.cur_dc_id {
-        //             // sendingPing = false;
-        //         }
-        //         // if (networkPaused && lastPauseTime != 0) {
-        //         //    lastPauseTime = getCurrentTimeMonotonicMillis();
-        //         // }
-        //         self.process_request_queue(conn_type, dc_id).await;
-        //     }
-        // }
-        
-        Ok(())
+    /// 向 home DC 发送 `help.getConfig`, 结果在 [Client::select] 中处理
+    fn fetch_config(&mut self) {
+        if self.config_rx.is_some() { return; }
+        let body = match proto::to_bytes(&GetConfig {}) {
+            Ok(body) => body,
+            Err(e) => {
+                warn!("Encode help.getConfig failed: {}", e);
+                return;
+            }
+        };
+        let (tx, rx) = oneshot::channel();
+        self.dispatcher.requests().push(body, RequestOptions::new(), self.config.request_timeout, tx);
+        self.config_rx = Some(rx);
+        self.config_at = None;
     }
 
+    /// 处理 `help.getConfig` 的结果, 配置过期后重新获取
+    fn poll_config(&mut self) {
+        let Some(rx) = &mut self.config_rx else {
+            if self.config_at.is_some_and(|at| at <= Instant::now()) {
+                self.fetch_config();
+            }
+            return;
+        };
+        let res = match rx.try_recv() {
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Closed) => Err(RequestError::Stopped.into()),
+            Ok(res) => res.and_then(|body| parse_config(&body)),
+        };
+        self.config_rx = None;
+        match res {
+            Ok(config) => self.apply_config(config),
+            Err(e) => {
+                warn!("Fetch config failed: {}", e);
+                self.config_at = Some(Instant::now() + CONFIG_RETRY);
+            }
+        }
+    }
+
+    /// 用服务器返回的 `dc_options` 替换对应 DataCenter 的地址, 并保存到 [DcStore]
+    fn apply_config(&mut self, config: Config) {
+        let ids: BTreeSet<_> = config.dc_options.iter().map(|o| o.id).collect();
+        info!("Got config from dc{}, {} options for dc {:?}", config.this_dc, config.dc_options.len(), ids);
+        for id in ids {
+            let fetched = DcConfig::from_options(id, &config.dc_options);
+            if fetched.is_empty() { continue; }
+            self.fetched.insert(id, fetched);
+            if let Some(addrs) = self.dc_config(id) {
+                if let Some(dc) = self.data_centers.get_mut(&id) {
+                    dc.set_addrs(addrs);
+                }
+            }
+        }
+        let ttl = (config.expires - config.date).max(CONFIG_RETRY.as_secs() as i32);
+        self.config_at = Some(Instant::now() + Duration::from_secs(ttl as u64));
+        self.save_dc_table();
+    }
+
+    /// 导出所有已知 DataCenter 的密钥和会话, 已建立的 DataCenter 使用当前状态
+    pub fn export_session(&self) -> ExportedSession {
+        let ids: BTreeSet<_> = self.seeds.keys().chain(self.fetched.keys()).chain(self.data_centers.keys())
+            .copied()
+            .collect();
+        let dcs = ids.into_iter()
+            .filter_map(|id| {
+                let state = match self.data_centers.get(&id) {
+                    Some(dc) => dc.state(),
+                    None => self.load_state(id),
+                };
+                state.map(|state| (id, state))
+            })
+            .collect();
+        ExportedSession { home_dc: self.dispatcher.home_dc(), user_id: self.cur_user_id, dcs }
+    }
+
+    fn load_state(&self, dc_id: i32) -> Option<DcState> {
+        let res = self.storage.get(&dc_state_key(dc_id))
+            .and_then(|bytes| bytes.map(|b| DcState::decode(&b)).transpose());
+        res.unwrap_or_else(|e| {
+            warn!("(dc{}) Load state failed: {}", dc_id, e);
+            None
+        })
+    }
+
+    /// 保存发生变化的 DataCenter 状态 (新的密钥, salt 等)
+    fn save_states(&mut self) {
+        for (&dc_id, dc) in &self.data_centers {
+            let Some(state) = dc.state() else { continue; };
+            if self.saved.get(&dc_id) == Some(&state) { continue; }
+            match self.storage.set(&dc_state_key(dc_id), &state.encode()) {
+                Ok(()) => { self.saved.insert(dc_id, state); }
+                Err(e) => warn!("(dc{}) Save state failed: {}", dc_id, e),
+            }
+        }
+    }
+
+    fn save_dc_table(&self) {
+        let mut dcs: Vec<_> = self.fetched.values().cloned().collect();
+        dcs.sort_by_key(|dc| dc.id());
+        let table = DcTable { home_dc: self.dispatcher.home_dc(), dcs };
+        if let Err(e) = self.store.save(&table) {
+            warn!("Save dc options failed: {}", e);
+        }
+    }
+}
+
+
+/// 从 home DC 导出授权并导入到 [dc_id], 见 [User Authorization](https://core.telegram.org/api/datacenter#authorization-transfer)
+async fn transfer_authorization(dispatcher: &Dispatcher, dc_id: i32, timeout: Duration) -> Result<()> {
+    let export = proto::to_bytes(&ExportAuthorization { dc_id })?;
+    let exported = request(dispatcher, export, RequestOptions::new(), timeout).await?;
+    let ExportedAuthorization { id, bytes } = parse_exported_authorization(&exported)?;
+
+    let import = proto::to_bytes(&ImportAuthorization { id, bytes })?;
+    let options = RequestOptions { without_auth: true, ..RequestOptions::new().dc_id(dc_id) };
+    request(dispatcher, import, options, timeout).await?;
+    info!("(dc{}) Authorization imported", dc_id);
+    Ok(())
 }
 
+/// 在读写任务之外发送请求, 由 [Signal::Resend] 通知 [Client] 处理队列
+async fn request(dispatcher: &Dispatcher, body: Bytes, options: RequestOptions, timeout: Duration) -> Result<Bytes> {
+    let (tx, rx) = oneshot::channel();
+    dispatcher.requests().push(body, options, timeout, tx);
+    dispatcher.signal(Signal::Resend);
+    rx.await.map_err(|_| RequestError::Stopped)?
+}
+
+/// drop 时发出 [Signal]
+struct SignalOnDrop<'a>(&'a Dispatcher, Signal);
+
+impl Drop for SignalOnDrop<'_> {
+    fn drop(&mut self) {
+        self.0.signal(self.1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time;
+
+    use crate::net::dispatcher::Source;
+    use crate::net::{AuthKey, MemoryDcStore, Session};
+    use crate::net::request::Pending;
+    use crate::proto::ByteBuffer;
+    use crate::proto::service::Incoming;
+    use crate::storage::MemoryStorage;
+
+    use super::*;
+
+    const EXPORT_AUTHORIZATION: u32 = 0xe5bfffcd;
+    const IMPORT_AUTHORIZATION: u32 = 0xa57a7dad;
+
+    fn client() -> Client {
+        client_with(Arc::new(MemoryStorage::new()))
+    }
+
+    fn client_with(storage: Arc<dyn Storage>) -> Client {
+        let seeds = HashMap::from([
+            (1, DcConfig::new(1).addrs("127.0.0.1:1")),
+            (4, DcConfig::new(4).addrs("127.0.0.1:1")),
+        ]);
+        let (events, _) = broadcast::channel(16);
+        let (updates, _) = broadcast::channel(16);
+        let config = Arc::new(NetConfig::default());
+        Client::new(seeds, 1, Arc::new(MemoryDcStore::new()), storage, config, events, updates)
+    }
+
+    /// 处理收到的通知, 直到出现以 [crc] 开头的请求, 当作已经以 [msg_id] 发送
+    async fn wait_request(client: &mut Client, crc: u32, msg_id: i64) -> Pending {
+        let signals = client.signals();
+        loop {
+            {
+                let mut requests = client.dispatcher.requests();
+                let found = requests.pending(client.dispatcher.home_dc(), |_| false).into_iter()
+                    .find(|p| p.body[..4] == crc.to_le_bytes());
+                if let Some(p) = found {
+                    requests.sent(p.id, p.dc_id, msg_id, 1);
+                    return p;
+                }
+            }
+            let signal = time::timeout(Duration::from_secs(5), signals.recv()).await.unwrap().unwrap();
+            client.on_signal(signal);
+        }
+    }
+
+    async fn wait_signal(client: &mut Client, expected: Signal) {
+        let signals = client.signals();
+        loop {
+            let signal = time::timeout(Duration::from_secs(5), signals.recv()).await.unwrap().unwrap();
+            client.on_signal(signal);
+            if signal == expected { return; }
+        }
+    }
+
+    fn reply(client: &Client, dc_id: i32, msg: Incoming) {
+        let session = Session::new();
+        let source = Source { dc_id, conn_type: ConnType::Generic, session: &session };
+        client.dispatcher.dispatch(&source, msg);
+    }
+
+    #[tokio::test]
+    async fn migrate_and_import_authorization() {
+        let mut client = client();
+        let (tx, _rx) = oneshot::channel();
+        let mut body = ByteBuffer::new();
+        body.put_u32(0x12345678);
+        client.send_msg(body.to_bytes(), RequestOptions::new(), tx);
+
+        // 文件在 dc4, 在 dc4 上还没有授权
+        wait_request(&mut client, 0x12345678, 100).await;
+        reply(&client, 1, Incoming::RpcError { req_msg_id: 100, code: 303, message: "FILE_MIGRATE_4".into() });
+        let p = wait_request(&mut client, 0x12345678, 104).await;
+        assert_eq!(p.dc_id, 4);
+        reply(&client, 4, Incoming::RpcError { req_msg_id: 104, code: 401, message: "AUTH_KEY_UNREGISTERED".into() });
+        wait_signal(&mut client, Signal::Unauthorized { dc_id: 4 }).await;
+        assert!(client.importing.contains(&4));
+
+        // 从 home DC 导出, 导入到 dc4
+        let p = wait_request(&mut client, EXPORT_AUTHORIZATION, 108).await;
+        assert_eq!(p.dc_id, 1);
+        let mut exported = ByteBuffer::new();
+        exported.put_u32(0xb434e2b8);
+        exported.put_i64(7);
+        exported.put_u8(3);
+        exported.put_all(&[1, 2, 3]);
+        reply(&client, 1, Incoming::RpcResult { req_msg_id: 108, result: exported.to_bytes() });
+
+        let p = wait_request(&mut client, IMPORT_AUTHORIZATION, 112).await;
+        assert!(p.dc_id == 4 && p.without_auth);
+        assert_eq!(p.body[4..], [7, 0, 0, 0, 0, 0, 0, 0, 3, 1, 2, 3]);
+        reply(&client, 4, Incoming::RpcResult { req_msg_id: 112, result: Bytes::from_static(&[0; 4]) });
+        wait_signal(&mut client, Signal::Imported { dc_id: 4 }).await;
+        assert!(client.importing.is_empty());
+
+        // 导出的结果无法解析时同样结束导入
+        client.on_signal(Signal::Unauthorized { dc_id: 4 });
+        wait_request(&mut client, EXPORT_AUTHORIZATION, 116).await;
+        reply(&client, 1, Incoming::RpcResult { req_msg_id: 116, result: Bytes::from_static(&[0; 4]) });
+        wait_signal(&mut client, Signal::Imported { dc_id: 4 }).await;
+        assert!(client.importing.is_empty());
+        client.destroy();
+    }
+
+    #[tokio::test]
+    async fn lock_releases_keys() {
+        let storage = Arc::new(MemoryStorage::new());
+        let state = DcState {
+            perm_key: AuthKey::from_bytes([1; 256]),
+            temp_key: None,
+            session_id: 3,
+            server_salt: 4,
+            time_diff: 5,
+        };
+        storage.set(&dc_state_key(1), &state.encode()).unwrap();
+        let mut client = client_with(storage.clone());
+        client.connect().unwrap();
+        client.data_centers[&1].generic_conn.session().set_server_salt(9);
+
+        // 锁定前保存最新的 salt, 之后内存中不再有密钥
+        client.lock();
+        assert!(client.data_centers.is_empty());
+        let saved = DcState::decode(&storage.get(&dc_state_key(1)).unwrap().unwrap()).unwrap();
+        assert_eq!(saved.server_salt, 9);
+
+        // 恢复时从 storage 重新加载
+        client.resume();
+        assert_eq!(client.data_centers[&1].state(), Some(DcState { server_salt: 9, ..state }));
+        client.destroy();
+    }
+
+    /// 新登录的账号 (storage 中没有用户 id) 导出后可以在其他客户端导入
+    #[tokio::test]
+    async fn export_after_login() {
+        let storage = Arc::new(MemoryStorage::new());
+        let state = DcState {
+            perm_key: AuthKey::from_bytes([1; 256]),
+            temp_key: None,
+            session_id: 3,
+            server_salt: 4,
+            time_diff: 5,
+        };
+        storage.set(&dc_state_key(1), &state.encode()).unwrap();
+        let mut client = client_with(storage);
+        assert_eq!(client.export_session().user_id, 0);
+
+        // auth.signIn 返回 auth.authorization
+        let (tx, _rx) = oneshot::channel();
+        let mut body = ByteBuffer::new();
+        body.put_u32(0x8d52a951);
+        client.send_msg(body.to_bytes(), RequestOptions::new(), tx);
+        wait_request(&mut client, 0x8d52a951, 100).await;
+        let mut authorization = ByteBuffer::new();
+        authorization.put_u32(0x2ea2c0d4);
+        authorization.put_u32(0);
+        authorization.put_u32(0x215c4438);
+        authorization.put_u32(0);
+        authorization.put_u32(0);
+        authorization.put_i64(42);
+        reply(&client, 1, Incoming::RpcResult { req_msg_id: 100, result: authorization.to_bytes() });
+        wait_signal(&mut client, Signal::Authorized { user_id: 42 }).await;
+
+        let exported = client.export_session();
+        assert_eq!(exported.user_id, 42);
+        client.destroy();
+
+        let imported = Arc::new(MemoryStorage::new());
+        let store = MemoryDcStore::new();
+        exported.to_string().parse::<ExportedSession>().unwrap().import(imported.as_ref(), &store).unwrap();
+        let client = client_with(imported);
+        assert_eq!(client.export_session(), ExportedSession { home_dc: 1, user_id: 42, dcs: vec![(1, state)] });
+    }
+}
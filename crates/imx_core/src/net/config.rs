@@ -0,0 +1,1033 @@
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(any(feature = "tls", feature = "quic"))]
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::defines::{BACKGROUND_PING_DISCONNECT_DELAY, BACKGROUND_PING_DURATION, MAX_MISSED_PONGS, PFS_ENABLED, PING_DISCONNECT_DELAY, PING_DURATION, READ_BUFFER_SIZE, UPDATE_BUFFER_SIZE};
+use crate::net::{Addr, Addrs, RsaKeys};
+use crate::net::connection::ConnType;
+use crate::net::socket::{self, Resolver, SystemResolver};
+use crate::proto::config::DcOption;
+use crate::proto::transport::TransportType;
+use crate::util::hex;
+
+/// 传输层配置
+///
+/// 可配置多个 [TransportType], 按顺序尝试, 前一个连接失败 (例如被网络屏蔽) 时回退到下一个
+///
+/// # Examples
+/// ```rust
+/// use imx_core::TransportConfig;
+/// use imx_core::proto::transport::TransportType;
+///
+/// let config = TransportConfig::new(TransportType::Abridged)
+///     .fallback(TransportType::PaddedIntermediate)
+///     .secret([0xdd; 17]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TransportConfig {
+    transports: Vec<TransportType>,
+    /// 序列化为十六进制字符串
+    #[serde(with = "hex::option")]
+    secret: Option<Vec<u8>>,
+}
+
+impl TransportConfig {
+    pub fn new(typo: TransportType) -> Self {
+        Self { transports: vec![typo], secret: None }
+    }
+
+    /// 追加一个回退的传输协议
+    pub fn fallback(mut self, typo: TransportType) -> Self {
+        if !self.transports.contains(&typo) {
+            self.transports.push(typo);
+        }
+        self
+    }
+
+    /// 设置 obfuscation 密钥, 例如 MTProxy 的 `dd` 开头的 17 字节密钥
+    pub fn secret<S: Into<Vec<u8>>>(mut self, secret: S) -> Self {
+        self.secret = Some(secret.into());
+        self
+    }
+
+    /// 按优先级排列的传输协议
+    pub fn transports(&self) -> &[TransportType] {
+        &self.transports
+    }
+
+    pub fn get_secret(&self) -> Option<&[u8]> {
+        self.secret.as_deref()
+    }
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self::new(TransportType::Abridged)
+    }
+}
+
+/// 按 DataCenter 或 [ConnType] 选择 [TransportConfig]
+///
+/// 优先级: [ConnType] > DataCenter > 默认配置
+#[derive(Debug, Clone, Default)]
+pub struct TransportOptions {
+    default: TransportConfig,
+    data_centers: HashMap<i32, TransportConfig>,
+    conn_types: HashMap<ConnType, TransportConfig>,
+}
+
+impl TransportOptions {
+    pub fn new(default: TransportConfig) -> Self {
+        Self {
+            default,
+            data_centers: HashMap::new(),
+            conn_types: HashMap::new(),
+        }
+    }
+
+    /// 为指定 DataCenter 设置传输层配置
+    pub fn data_center(mut self, dc_id: i32, config: TransportConfig) -> Self {
+        self.data_centers.insert(dc_id, config);
+        self
+    }
+
+    /// 为指定连接类型设置传输层配置
+    pub fn conn_type(mut self, conn_type: ConnType, config: TransportConfig) -> Self {
+        self.conn_types.insert(conn_type, config);
+        self
+    }
+
+    /// 所有的配置
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &TransportConfig> {
+        [&self.default].into_iter().chain(self.data_centers.values()).chain(self.conn_types.values())
+    }
+
+    /// 获取指定 DataCenter 和连接类型使用的配置
+    pub fn get(&self, dc_id: i32, conn_type: ConnType) -> &TransportConfig {
+        self.conn_types.get(&conn_type)
+            .or_else(|| self.data_centers.get(&dc_id))
+            .unwrap_or(&self.default)
+    }
+}
+
+impl From<TransportConfig> for TransportOptions {
+    fn from(value: TransportConfig) -> Self {
+        Self::new(value)
+    }
+}
+
+/// TLS 配置, 用于 tls 和 QUIC 等加密连接
+///
+/// 证书均为 DER 格式. 未添加根证书时使用内置的 webpki 根证书;
+/// 设置了固定证书或公钥时, 只接受与之匹配的服务器证书, 不再校验证书链和域名
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TlsConfig {
+    pub(crate) root_certs: Vec<Vec<u8>>,
+    pub(crate) pinned_certs: Vec<Vec<u8>>,
+    pub(crate) pinned_spki: Vec<[u8; 32]>,
+    pub(crate) server_name: Option<String>,
+    pub(crate) alpn: Vec<Vec<u8>>,
+}
+
+impl TlsConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 添加信任的根证书
+    pub fn root_cert<C: Into<Vec<u8>>>(mut self, der: C) -> Self {
+        self.root_certs.push(der.into());
+        self
+    }
+
+    /// 添加固定的服务器证书. 设置后替代根证书校验, 不再检查证书链, 有效期和服务器名称
+    pub fn pinned_cert<C: Into<Vec<u8>>>(mut self, der: C) -> Self {
+        self.pinned_certs.push(der.into());
+        self
+    }
+
+    /// 固定服务器证书的公钥, 参数为 DER 格式 SubjectPublicKeyInfo 的 SHA-256,
+    /// 证书更换时只要公钥不变仍然可以通过校验. 与 [TlsConfig::pinned_cert] 一样替代根证书校验
+    pub fn pinned_spki(mut self, sha256: [u8; 32]) -> Self {
+        self.pinned_spki.push(sha256);
+        self
+    }
+
+    /// 覆盖握手使用的服务器名称 (SNI), 默认取地址中的主机名
+    pub fn server_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.server_name = Some(name.into());
+        self
+    }
+
+    /// 添加 ALPN 协议, 按优先级顺序
+    pub fn alpn<P: Into<Vec<u8>>>(mut self, protocol: P) -> Self {
+        self.alpn.push(protocol.into());
+        self
+    }
+}
+
+/// 代理服务器配置, 连接服务器之前先通过代理建立隧道
+///
+/// 只支持基于 tcp 的连接 (tcp, tls, ws). 不支持 SOCKS5 `UDP ASSOCIATE`, 配置了代理时
+/// QUIC 连接直接返回 `ProxyError::Unsupported` 错误, 不会绕过代理直连
+///
+/// # Examples
+/// ```rust
+/// use imx_core::ProxyConfig;
+///
+/// let socks5 = ProxyConfig::socks5("127.0.0.1:1080").auth("user", "password");
+/// let http = ProxyConfig::http("127.0.0.1:8080");
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ProxyConfig {
+    /// SOCKS5 代理, 支持用户名/密码认证
+    Socks5 {
+        addr: String,
+        #[serde(default)]
+        auth: Option<ProxyAuth>,
+    },
+    /// HTTP 代理, 通过 `CONNECT` 方法建立隧道, 认证使用 Basic 方式
+    Http {
+        addr: String,
+        #[serde(default)]
+        auth: Option<ProxyAuth>,
+    },
+}
+
+/// 代理认证信息
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProxyAuth {
+    pub username: String,
+    pub password: String,
+}
+
+impl ProxyConfig {
+    pub fn socks5<S: Into<String>>(addr: S) -> Self {
+        Self::Socks5 { addr: addr.into(), auth: None }
+    }
+
+    pub fn http<S: Into<String>>(addr: S) -> Self {
+        Self::Http { addr: addr.into(), auth: None }
+    }
+
+    /// 设置代理认证的用户名和密码
+    pub fn auth<U: Into<String>, P: Into<String>>(self, username: U, password: P) -> Self {
+        let auth = Some(ProxyAuth { username: username.into(), password: password.into() });
+        match self {
+            Self::Socks5 { addr, .. } => Self::Socks5 { addr, auth },
+            Self::Http { addr, .. } => Self::Http { addr, auth },
+        }
+    }
+
+    /// 代理服务器地址
+    pub fn addr(&self) -> &str {
+        match self {
+            Self::Socks5 { addr, .. } | Self::Http { addr, .. } => addr,
+        }
+    }
+}
+
+/// 底层 socket 配置
+#[derive(Clone)]
+pub struct SocketConfig {
+    pub(crate) tls: TlsConfig,
+    pub(crate) proxy: Option<ProxyConfig>,
+    pub(crate) resolver: Arc<dyn Resolver>,
+    pub(crate) connect_timeout: Duration,
+    pub(crate) read_buffer_size: usize,
+    /// 由 [TlsConfig] 生成, 在多次连接之间共享 TLS 会话缓存, 用于会话恢复
+    #[cfg(feature = "tls")]
+    pub(crate) rustls: Arc<OnceLock<Arc<rustls::ClientConfig>>>,
+    /// 由 [TlsConfig] 生成, 在多次连接之间共享 TLS 会话缓存, 用于 0-RTT 重连
+    #[cfg(feature = "quic")]
+    pub(crate) quic: Arc<OnceLock<quinn::ClientConfig>>,
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        Self {
+            tls: TlsConfig::default(),
+            proxy: None,
+            resolver: Arc::new(SystemResolver),
+            connect_timeout: Duration::from_secs(10),
+            read_buffer_size: READ_BUFFER_SIZE,
+            #[cfg(feature = "tls")]
+            rustls: Default::default(),
+            #[cfg(feature = "quic")]
+            quic: Default::default(),
+        }
+    }
+}
+
+impl SocketConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = tls;
+        // 配置变化后不能再使用之前的缓存
+        #[cfg(feature = "tls")]
+        { self.rustls = Default::default(); }
+        #[cfg(feature = "quic")]
+        { self.quic = Default::default(); }
+        self
+    }
+
+    /// 通过代理连接服务器, 对 tcp, tls, ws 生效, QUIC 不支持代理
+    pub fn proxy(mut self, proxy: ProxyConfig) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// 域名解析, 默认使用系统解析. 使用代理时目标地址由代理服务器解析
+    pub fn resolver<R: Resolver>(mut self, resolver: R) -> Self {
+        self.resolver = Arc::new(resolver);
+        self
+    }
+
+    /// 建立连接 (包括握手) 的超时时间, 默认 10 秒, 超时后尝试下一个地址
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// 接收数据的缓冲区大小, 默认为 [READ_BUFFER_SIZE]
+    pub fn read_buffer_size(mut self, size: usize) -> Self {
+        self.read_buffer_size = size;
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), ConfigError> {
+        if self.connect_timeout.is_zero() { return Err(ConfigError::Zero("connect_timeout")); }
+        if self.read_buffer_size == 0 { return Err(ConfigError::Zero("read_buffer_size")); }
+        Ok(())
+    }
+}
+
+impl Debug for SocketConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SocketConfig")
+            .field("tls", &self.tls)
+            .field("proxy", &self.proxy)
+            .field("connect_timeout", &self.connect_timeout)
+            .field("read_buffer_size", &self.read_buffer_size)
+            .finish()
+    }
+}
+
+/// 断线重连配置, 重连间隔按指数增长, 并加入随机抖动, 避免大量客户端同时重连
+///
+/// # Examples
+/// ```rust
+/// use std::time::Duration;
+/// use imx_core::ReconnectConfig;
+///
+/// let config = ReconnectConfig::new()
+///     .initial_delay(Duration::from_millis(200))
+///     .max_delay(Duration::from_secs(10))
+///     .max_attempts(20);
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ReconnectConfig {
+    #[serde(rename = "initial_delay_ms", with = "millis")]
+    pub(crate) initial_delay: Duration,
+    #[serde(rename = "max_delay_ms", with = "millis")]
+    pub(crate) max_delay: Duration,
+    pub(crate) multiplier: f64,
+    pub(crate) jitter: f64,
+    pub(crate) max_attempts: Option<u32>,
+}
+
+impl ReconnectConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 第一次重连前的等待时间
+    pub fn initial_delay(mut self, delay: Duration) -> Self {
+        self.initial_delay = delay;
+        self
+    }
+
+    /// 重连间隔的上限
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// 每次重连失败后, 间隔的增长倍数
+    pub fn multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// 随机抖动的比例, 取值 `[0, 1]`, 实际间隔在 `delay * (1 ± jitter)` 之间
+    pub fn jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// 连续重连失败的次数上限, 超过后不再自动重连, 直到有新的请求. 默认不限制
+    pub fn max_attempts(mut self, attempts: u32) -> Self {
+        self.max_attempts = Some(attempts);
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), ConfigError> {
+        if self.initial_delay > self.max_delay { return Err(ConfigError::ReconnectDelay); }
+        if self.multiplier.is_nan() || self.multiplier < 1.0 { return Err(ConfigError::OutOfRange { field: "reconnect.multiplier", value: self.multiplier }); }
+        if !(0.0..=1.0).contains(&self.jitter) { return Err(ConfigError::OutOfRange { field: "reconnect.jitter", value: self.jitter }); }
+        Ok(())
+    }
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+}
+
+/// DataCenter 中各类型连接的数量和空闲关闭时间.
+/// 除了 [ConnType::Generic] 以外的连接都在第一次使用时建立
+///
+/// # Examples
+/// ```rust
+/// use std::time::Duration;
+/// use imx_core::PoolConfig;
+///
+/// let config = PoolConfig::new()
+///     .upload(4)
+///     .download(2)
+///     .idle_timeout(Duration::from_secs(30));
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PoolConfig {
+    pub(crate) upload: usize,
+    pub(crate) download: usize,
+    #[serde(rename = "idle_timeout_ms", with = "millis")]
+    pub(crate) idle_timeout: Duration,
+}
+
+impl PoolConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 并行上传的连接数, 至少为 1
+    pub fn upload(mut self, count: usize) -> Self {
+        self.upload = count.max(1);
+        self
+    }
+
+    /// 并行下载的连接数, 至少为 1
+    pub fn download(mut self, count: usize) -> Self {
+        self.download = count.max(1);
+        self
+    }
+
+    /// 没有请求的时间超过该值后关闭连接, [ConnType::Generic] 和 [ConnType::Push] 连接不会被关闭
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// 某种类型的连接数量
+    pub(crate) fn size(&self, conn_type: ConnType) -> usize {
+        match conn_type {
+            ConnType::Upload => self.upload,
+            ConnType::Download => self.download,
+            _ => 1,
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), ConfigError> {
+        if self.upload == 0 { return Err(ConfigError::Zero("pool.upload")); }
+        if self.download == 0 { return Err(ConfigError::Zero("pool.download")); }
+        Ok(())
+    }
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            upload: 4,
+            download: 2,
+            idle_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+/// 连接的 keepalive, 定时发送 `ping_delay_disconnect` 并测量 RTT.
+/// 连续 [KeepaliveConfig::max_missed] 次没有收到 pong 时按断开处理并重连
+///
+/// # Examples
+/// ```rust
+/// use std::time::Duration;
+/// use imx_core::KeepaliveConfig;
+///
+/// let config = KeepaliveConfig::new()
+///     .interval(Duration::from_secs(10))
+///     .disconnect_delay(Duration::from_secs(25));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeepaliveConfig {
+    #[serde(rename = "interval_ms", with = "millis")]
+    pub(crate) interval: Duration,
+    #[serde(rename = "disconnect_delay_ms", with = "millis")]
+    pub(crate) disconnect_delay: Duration,
+    #[serde(rename = "background_interval_ms", with = "millis")]
+    pub(crate) background_interval: Duration,
+    #[serde(rename = "background_disconnect_delay_ms", with = "millis")]
+    pub(crate) background_disconnect_delay: Duration,
+    pub(crate) max_missed: u32,
+}
+
+impl KeepaliveConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 发送 ping 的间隔
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// 服务器在该时间内没有收到下一个 ping 时断开连接, 需要大于 [KeepaliveConfig::interval], 精确到秒
+    pub fn disconnect_delay(mut self, delay: Duration) -> Self {
+        self.disconnect_delay = delay;
+        self
+    }
+
+    /// 后台模式 ([Client::background](crate::Client::background)) 发送 ping 的间隔
+    pub fn background_interval(mut self, interval: Duration) -> Self {
+        self.background_interval = interval;
+        self
+    }
+
+    /// 后台模式的 disconnect_delay, 需要大于 [KeepaliveConfig::background_interval]
+    pub fn background_disconnect_delay(mut self, delay: Duration) -> Self {
+        self.background_disconnect_delay = delay;
+        self
+    }
+
+    /// 连续多少次没有收到 pong 时认为连接已断开
+    pub fn max_missed(mut self, count: u32) -> Self {
+        self.max_missed = count;
+        self
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), ConfigError> {
+        if self.interval.is_zero() { return Err(ConfigError::Zero("keepalive.interval")); }
+        if self.background_interval.is_zero() { return Err(ConfigError::Zero("keepalive.background_interval")); }
+        if self.max_missed == 0 { return Err(ConfigError::Zero("keepalive.max_missed")); }
+        if self.interval >= self.disconnect_delay { return Err(ConfigError::PingInterval("interval")); }
+        if self.background_interval >= self.background_disconnect_delay { return Err(ConfigError::PingInterval("background_interval")); }
+        Ok(())
+    }
+}
+
+impl Default for KeepaliveConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_millis(PING_DURATION as u64),
+            disconnect_delay: Duration::from_secs(PING_DISCONNECT_DELAY as u64),
+            background_interval: Duration::from_millis(BACKGROUND_PING_DURATION as u64),
+            background_disconnect_delay: Duration::from_secs(BACKGROUND_PING_DISCONNECT_DELAY as u64),
+            max_missed: MAX_MISSED_PONGS,
+        }
+    }
+}
+
+/// 初始化连接时上报给服务器的设备和应用信息
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppInfo {
+    pub api_id: i32,
+    pub api_hash: String,
+    pub device_model: String,
+    pub system_version: String,
+    pub app_version: String,
+    pub system_lang_code: String,
+    pub lang_pack: String,
+    pub lang_code: String,
+}
+
+impl Default for AppInfo {
+    fn default() -> Self {
+        Self {
+            api_id: 0,
+            api_hash: String::new(),
+            device_model: std::env::consts::ARCH.to_owned(),
+            system_version: std::env::consts::OS.to_owned(),
+            app_version: env!("CARGO_PKG_VERSION").to_owned(),
+            system_lang_code: "en".to_owned(),
+            lang_pack: String::new(),
+            lang_code: "en".to_owned(),
+        }
+    }
+}
+
+/// 没有指定时使用的 home DC id
+pub const DEFAULT_DC_ID: i32 = 1;
+
+/// 连接的服务器环境, 决定握手和 obfuscation header 中 DC id 的编码, 以及默认信任的 [RsaKeys]
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Environment {
+    /// 正式服
+    #[default]
+    Production,
+    /// 测试服, DC id 加 10000
+    Test,
+}
+
+impl Environment {
+    /// 该环境默认信任的服务器公钥
+    pub fn rsa_keys(&self) -> RsaKeys {
+        match self {
+            Self::Production => RsaKeys::production(),
+            Self::Test => RsaKeys::test(),
+        }
+    }
+
+    /// 发给服务器的 DC id: 测试服加 10000, 连接 [media] 地址时取负数
+    pub(crate) fn raw_dc_id(&self, dc_id: i32, media: bool) -> i32 {
+        let id = match self {
+            Self::Production => dc_id,
+            Self::Test => dc_id + 10000,
+        };
+        if media { -id } else { id }
+    }
+}
+
+/// 一个 DataCenter 的地址, 按 id 区分
+///
+/// # Examples
+/// ```rust
+/// use imx_core::DcConfig;
+///
+/// let dc = DcConfig::new(2)
+///     .addrs(["149.154.167.50:443", "[2001:67c:4e8:f002::a]:443"])
+///     .media("149.154.167.151:443")
+///     .secret("149.154.167.151:443", [0xdd; 17]);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DcConfig {
+    pub(crate) id: i32,
+    pub(crate) ipv4: Addrs,
+    pub(crate) ipv6: Addrs,
+    pub(crate) media: Addrs,
+    pub(crate) cdn: Addrs,
+    /// 地址 (不含协议前缀) -> obfuscation 密钥, 优先于 [TransportConfig::secret]
+    pub(crate) secrets: HashMap<String, Vec<u8>>,
+}
+
+impl DcConfig {
+    pub fn new(id: i32) -> Self {
+        Self { id, ..Default::default() }
+    }
+
+    pub fn id(&self) -> i32 {
+        self.id
+    }
+
+    /// 添加地址, 按 IPv4 (包括域名) 和 IPv6 分开保存
+    pub fn addrs<T>(mut self, addrs: T) -> Self where T: Into<Addrs> {
+        let (ipv6, ipv4): (Vec<_>, Vec<_>) = addrs.into().into_iter().partition(|a| a.is_ipv6());
+        self.ipv4.extend(ipv4);
+        self.ipv6.extend(ipv6);
+        self
+    }
+
+    /// 只用于媒体连接 ([ConnType::GenericMedia], [ConnType::Download]) 的地址
+    pub fn media<T>(mut self, addrs: T) -> Self where T: Into<Addrs> {
+        self.media.extend(addrs.into());
+        self
+    }
+
+    /// CDN 地址. CDN DataCenter 需要单独的密钥 (`help.getCdnConfig`), 还不支持, 目前只保存不连接
+    pub fn cdn<T>(mut self, addrs: T) -> Self where T: Into<Addrs> {
+        self.cdn.extend(addrs.into());
+        self
+    }
+
+    /// 连接 [addr] 时使用的 obfuscation 密钥, 为空时混淆但不使用密钥
+    pub fn secret<A, S>(mut self, addr: A, secret: S) -> Self where A: Into<Addr>, S: Into<Vec<u8>> {
+        self.secrets.insert(addr.into().without_scheme(), secret.into());
+        self
+    }
+
+    /// 由服务器返回的 `dc_options` 生成, `static` 地址排在后面作为备用.
+    /// `tcpo_only` 的地址只能通过混淆连接, 没有 secret 时使用空密钥混淆
+    pub(crate) fn from_options<'a>(id: i32, options: impl IntoIterator<Item = &'a DcOption>) -> Self {
+        let mut options: Vec<_> = options.into_iter().filter(|o| o.id == id).collect();
+        options.sort_by_key(|o| o.is_static);
+
+        let mut dc = Self::new(id);
+        for option in options {
+            let addr = Addr::from((option.ip_address.as_str(), option.port as u16));
+            if let Some(secret) = &option.secret {
+                dc.secrets.insert(addr.without_scheme(), secret.to_vec());
+            } else if option.tcpo_only {
+                dc.secrets.insert(addr.without_scheme(), vec![]);
+            }
+            let list = if option.cdn {
+                &mut dc.cdn
+            } else if option.media_only {
+                &mut dc.media
+            } else if option.ipv6 {
+                &mut dc.ipv6
+            } else {
+                &mut dc.ipv4
+            };
+            list.extend([addr]);
+        }
+        dc
+    }
+
+    /// 合并 [other] 的地址, 已有的地址排在前面, 重复的地址忽略
+    pub(crate) fn merge(mut self, other: DcConfig) -> Self {
+        fn append(to: &mut Addrs, from: Addrs) {
+            for addr in from {
+                if !to.iter().any(|a| *a == addr) {
+                    to.extend([addr]);
+                }
+            }
+        }
+        append(&mut self.ipv4, other.ipv4);
+        append(&mut self.ipv6, other.ipv6);
+        append(&mut self.media, other.media);
+        append(&mut self.cdn, other.cdn);
+        for (addr, secret) in other.secrets {
+            self.secrets.entry(addr).or_insert(secret);
+        }
+        self
+    }
+
+    /// 是否没有可以连接的地址, 不包括 CDN 地址
+    pub fn is_empty(&self) -> bool {
+        self.ipv4.is_empty() && self.ipv6.is_empty() && self.media.is_empty()
+    }
+
+    /// [conn_type] 类型的连接使用的地址, 依次尝试. CDN 地址不使用, 见 [DcConfig::cdn]
+    pub(crate) fn addrs_for(&self, conn_type: ConnType) -> Addrs {
+        let mut addrs = Addrs::default();
+        if conn_type.is_media_type() {
+            addrs.extend(self.media.iter().cloned());
+        }
+        addrs.extend(self.ipv4.iter().cloned());
+        addrs.extend(self.ipv6.iter().cloned());
+        addrs
+    }
+
+    /// 检查所有地址的协议是否有对应的 socket 实现
+    pub(crate) fn validate(&self) -> Result<(), ConfigError> {
+        let unsupported = [&self.ipv4, &self.ipv6, &self.media, &self.cdn].into_iter()
+            .flat_map(|addrs| addrs.iter())
+            .find(|addr| !socket::is_supported(addr.scheme()));
+        match unsupported {
+            Some(addr) => Err(ConfigError::UnsupportedScheme(addr.to_string())),
+            None => Ok(()),
+        }
+    }
+
+    /// [addr] 是否为只用于媒体连接的地址, obfuscation header 中的 DC id 按地址取负数
+    pub(crate) fn is_media_addr(&self, addr: &Addr) -> bool {
+        self.media.iter().any(|a| a == addr)
+    }
+
+    /// 连接 [addr] 时使用的 obfuscation 密钥, 没有时使用 [TransportConfig::secret]
+    pub(crate) fn secret_for(&self, addr: &Addr) -> Option<&[u8]> {
+        self.secrets.get(&addr.without_scheme()).map(Vec::as_slice)
+    }
+}
+
+/// 网络层的全部配置, 由 [ClientBuilder](crate::ClientBuilder) 生成, 在各个连接之间共享
+#[derive(Debug, Clone)]
+pub(crate) struct NetConfig {
+    pub transport: TransportOptions,
+    pub socket: SocketConfig,
+    pub reconnect: ReconnectConfig,
+    pub pool: PoolConfig,
+    pub keepalive: KeepaliveConfig,
+    pub app: AppInfo,
+    /// 请求的默认超时时间
+    pub request_timeout: Duration,
+    /// 是否使用临时密钥 ([PFS](https://core.telegram.org/api/pfs))
+    pub pfs: bool,
+    pub environment: Environment,
+    /// 握手时信任的服务器公钥
+    pub rsa_keys: RsaKeys,
+}
+
+impl Default for NetConfig {
+    fn default() -> Self {
+        Self {
+            transport: Default::default(),
+            socket: Default::default(),
+            reconnect: Default::default(),
+            pool: Default::default(),
+            keepalive: Default::default(),
+            app: Default::default(),
+            request_timeout: Duration::from_secs(30),
+            pfs: PFS_ENABLED,
+            environment: Environment::Production,
+            rsa_keys: RsaKeys::production(),
+        }
+    }
+}
+
+impl NetConfig {
+    /// 检查配置的取值, 在 [ClientBuilder::build](crate::ClientBuilder::build) 时调用
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.request_timeout.is_zero() { return Err(ConfigError::Zero("request_timeout")); }
+        if self.transport.iter().any(|t| t.transports.is_empty()) { return Err(ConfigError::NoTransport); }
+        if self.rsa_keys.is_empty() { return Err(ConfigError::NoRsaKey); }
+        self.socket.validate()?;
+        self.reconnect.validate()?;
+        self.pool.validate()?;
+        self.keepalive.validate()
+    }
+}
+
+/// 配置错误, 由 [ClientBuilder::build](crate::ClientBuilder::build) 或
+/// [ClientBuilder::from_config](crate::ClientBuilder::from_config) 返回
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// home DC 没有地址
+    #[error("addrs of home dc {0} is empty")]
+    EmptyHomeDc(i32),
+    /// 地址的协议没有对应的 socket 实现, 可能是没有开启相应的 feature
+    #[error("unsupported scheme of addr {0}")]
+    UnsupportedScheme(String),
+    /// [TransportConfig] 中没有传输协议
+    #[error("no transport configured")]
+    NoTransport,
+    /// 没有信任的服务器公钥
+    #[error("no trusted rsa key")]
+    NoRsaKey,
+    /// 无法解析的服务器公钥
+    #[error("invalid rsa key: {0}")]
+    RsaKey(String),
+    /// 取值必须大于 0
+    #[error("{0} must be greater than zero")]
+    Zero(&'static str),
+    /// 超出取值范围
+    #[error("{field} out of range: {value}")]
+    OutOfRange { field: &'static str, value: f64 },
+    /// 重连的初始间隔大于最大间隔
+    #[error("reconnect initial delay is greater than max delay")]
+    ReconnectDelay,
+    /// ping 间隔不小于对应的 disconnect_delay, 服务器会在下一个 ping 之前断开连接
+    #[error("keepalive {0} must be shorter than its disconnect delay")]
+    PingInterval(&'static str),
+}
+
+/// 一个 DataCenter 的地址, 用于 [ClientConfig]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DcEntry {
+    pub id: i32,
+    pub addrs: Vec<String>,
+    pub media: Vec<String>,
+    pub cdn: Vec<String>,
+    /// 地址 -> obfuscation 密钥, 序列化为十六进制字符串
+    #[serde(with = "hex::map")]
+    pub secrets: HashMap<String, Vec<u8>>,
+}
+
+impl From<DcEntry> for DcConfig {
+    fn from(value: DcEntry) -> Self {
+        let mut dc = DcConfig::new(value.id)
+            .addrs(value.addrs)
+            .media(value.media)
+            .cdn(value.cdn);
+        for (addr, secret) in value.secrets {
+            dc = dc.secret(addr.as_str(), secret);
+        }
+        dc
+    }
+}
+
+/// 客户端的结构化配置, 可以从 TOML, JSON 等格式反序列化, 没有出现的字段使用默认值.
+/// 时间均以毫秒为单位, 由 [ClientBuilder::from_config](crate::ClientBuilder::from_config) 转换为 [ClientBuilder](crate::ClientBuilder),
+/// 取值在 `build` 时检查
+///
+/// # Examples
+/// ```rust
+/// use imx_core::{ClientBuilder, ClientConfig};
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let config: ClientConfig = serde_json::from_str(r#"{
+///     "addrs": ["127.0.0.1:443"],
+///     "transport": { "transports": ["abridged", "padded_intermediate"] },
+///     "keepalive": { "interval_ms": 10000, "disconnect_delay_ms": 25000 },
+///     "proxy": { "type": "socks5", "addr": "127.0.0.1:1080" }
+/// }"#)?;
+/// let builder = ClientBuilder::from_config(config)?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClientConfig {
+    /// home DC 的地址
+    pub addrs: Vec<String>,
+    /// home DC 只用于媒体连接的地址
+    pub media_addrs: Vec<String>,
+    pub home_dc: i32,
+    pub dcs: Vec<DcEntry>,
+    pub environment: Environment,
+    pub transport: TransportConfig,
+    pub pfs: bool,
+    pub request_timeout_ms: u64,
+    pub connect_timeout_ms: u64,
+    pub read_buffer_size: usize,
+    pub proxy: Option<ProxyConfig>,
+    pub reconnect: ReconnectConfig,
+    pub pool: PoolConfig,
+    pub keepalive: KeepaliveConfig,
+    /// 握手时信任的服务器公钥 (PEM), 为空时使用 [Environment::rsa_keys]
+    pub rsa_keys: Vec<String>,
+    pub app: AppInfo,
+    /// 每个 updates 订阅者的缓冲区大小
+    pub update_buffer: usize,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        let net = NetConfig::default();
+        Self {
+            addrs: vec![],
+            media_addrs: vec![],
+            home_dc: DEFAULT_DC_ID,
+            dcs: vec![],
+            environment: net.environment,
+            transport: TransportConfig::default(),
+            pfs: net.pfs,
+            request_timeout_ms: net.request_timeout.as_millis() as u64,
+            connect_timeout_ms: net.socket.connect_timeout.as_millis() as u64,
+            read_buffer_size: net.socket.read_buffer_size,
+            proxy: None,
+            reconnect: net.reconnect,
+            pool: net.pool,
+            keepalive: net.keepalive,
+            rsa_keys: vec![],
+            app: net.app,
+            update_buffer: UPDATE_BUFFER_SIZE,
+        }
+    }
+}
+
+/// 以毫秒数序列化 [Duration]
+mod millis {
+    use std::time::Duration;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(duration: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(duration.as_millis() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        u64::deserialize(deserializer).map(Duration::from_millis)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn raw_dc_id() {
+        assert_eq!(Environment::Production.raw_dc_id(2, false), 2);
+        assert_eq!(Environment::Production.raw_dc_id(2, true), -2);
+        assert_eq!(Environment::Test.raw_dc_id(2, false), 10002);
+        assert_eq!(Environment::Test.raw_dc_id(2, true), -10002);
+    }
+
+    #[test]
+    fn client_config_from_json() {
+        let config: ClientConfig = serde_json::from_str(r#"{
+            "addrs": ["127.0.0.1:443"],
+            "home_dc": 2,
+            "dcs": [{ "id": 2, "addrs": ["tls://10.0.0.2:443"], "secrets": { "10.0.0.2:443": "dd00" } }],
+            "environment": "test",
+            "transport": { "transports": ["abridged", "padded_intermediate"], "secret": "ee00" },
+            "request_timeout_ms": 5000,
+            "reconnect": { "initial_delay_ms": 200, "max_attempts": 3 },
+            "keepalive": { "interval_ms": 10000 },
+            "proxy": { "type": "socks5", "addr": "127.0.0.1:1080", "auth": { "username": "u", "password": "p" } },
+            "app": { "api_id": 42, "lang_code": "zh" }
+        }"#).unwrap();
+
+        assert_eq!(config.home_dc, 2);
+        assert_eq!(config.environment, Environment::Test);
+        assert_eq!(config.transport, TransportConfig::new(TransportType::Abridged).fallback(TransportType::PaddedIntermediate).secret([0xee, 0x00]));
+        assert_eq!(config.reconnect, ReconnectConfig::new().initial_delay(Duration::from_millis(200)).max_attempts(3));
+        assert_eq!(config.keepalive, KeepaliveConfig::new().interval(Duration::from_secs(10)));
+        assert_eq!(config.proxy, Some(ProxyConfig::socks5("127.0.0.1:1080").auth("u", "p")));
+        assert_eq!((config.app.api_id, config.app.lang_code.as_str(), config.app.system_lang_code.as_str()), (42, "zh", "en"));
+        // 没有出现的字段使用默认值
+        assert_eq!(config.pool, PoolConfig::default());
+        assert_eq!(config.connect_timeout_ms, ClientConfig::default().connect_timeout_ms);
+
+        let dc = DcConfig::from(config.dcs[0].clone());
+        assert_eq!(dc.secret_for(&Addr::from("tls://10.0.0.2:443")), Some(&[0xdd, 0x00][..]));
+
+        let json = serde_json::to_string(&config).unwrap();
+        assert_eq!(serde_json::from_str::<ClientConfig>(&json).unwrap(), config);
+    }
+
+    #[test]
+    fn validate() {
+        assert_eq!(NetConfig::default().validate(), Ok(()));
+
+        let config = NetConfig { keepalive: KeepaliveConfig::new().interval(Duration::from_secs(40)), ..Default::default() };
+        assert_eq!(config.validate(), Err(ConfigError::PingInterval("interval")));
+
+        let config = NetConfig { reconnect: serde_json::from_str(r#"{ "jitter": 1.5 }"#).unwrap(), ..Default::default() };
+        assert_eq!(config.validate(), Err(ConfigError::OutOfRange { field: "reconnect.jitter", value: 1.5 }));
+
+        let config = NetConfig { socket: SocketConfig::new().read_buffer_size(0), ..Default::default() };
+        assert_eq!(config.validate(), Err(ConfigError::Zero("read_buffer_size")));
+
+        let config = NetConfig { transport: serde_json::from_str::<TransportConfig>(r#"{ "transports": [] }"#).unwrap().into(), ..Default::default() };
+        assert_eq!(config.validate(), Err(ConfigError::NoTransport));
+
+        let dc = DcConfig::new(2).addrs("unknown://10.0.0.2:443");
+        assert_eq!(dc.validate(), Err(ConfigError::UnsupportedScheme("unknown://10.0.0.2:443".into())));
+    }
+
+    #[test]
+    fn addrs_for() {
+        let dc = DcConfig::new(2).addrs("10.0.0.1:443").media("10.0.0.2:443").cdn("10.0.0.3:443");
+        assert_eq!(dc.addrs_for(ConnType::Generic), Addrs::from("10.0.0.1:443"));
+        // CDN 使用单独的密钥, 下载连接不使用 CDN 地址
+        assert_eq!(dc.addrs_for(ConnType::Download), Addrs::from(["10.0.0.2:443", "10.0.0.1:443"]));
+        assert!(DcConfig::new(2).cdn("10.0.0.3:443").is_empty());
+
+        // 媒体连接没有 media 地址时使用普通地址, DC id 不取负数
+        assert!(dc.is_media_addr(&Addr::from("10.0.0.2:443")));
+        assert!(!dc.is_media_addr(&Addr::from("10.0.0.1:443")));
+    }
+}
@@ -1,23 +1,36 @@
-use core::time::Duration;
-use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use bytes::Bytes;
-use crossbeam::scope;
-use log::{error, info};
-use num::abs;
+use log::{info, warn};
+use tokio::sync::{broadcast, mpsc, oneshot};
+use tokio::sync::oneshot::error::TryRecvError;
+use tokio::task::JoinHandle;
 use tokio::time;
+use tokio::time::Instant;
 
-use crate::defines::PING_DURATION;
-use crate::net::{Addr, AuthKey, handshake, Session};
-use crate::net::event::{Event, event_channel, EventReceiver, EventSender};
+use crate::defines::{LAYER, TEMP_AUTH_KEY_EXPIRE_TIME};
+use crate::net::{Addr, AuthKey, DcConfig, handshake, KeepaliveConfig, RequestError, Session, SocketConfig};
+use crate::net::auth_key::{AuthKeys, TempKey};
+use crate::net::backoff::Backoff;
+use crate::net::config::NetConfig;
+use crate::net::dispatcher::{Dispatcher, Signal, Source};
+use crate::net::error;
+use crate::net::event::Event;
+use crate::net::handshake::HandshakeType;
+use crate::net::ping::{Ping, RttStats};
 use crate::net::socket::{Error, Socket, SocketImpl};
-use crate::net::time_sync::TimeSync;
-use crate::proto::{ByteBuffer, MtDe, MtRpc, MtSer, PingDelayDisconnect};
+use crate::proto::{ByteBuffer, MtRpc, MtSer, PingDelayDisconnect};
+use crate::proto::{invoke, service};
+use crate::proto::auth::parse_bool;
+use crate::proto::handshake::{parse_res_pq, parse_server_dh_params, parse_set_client_dh_params_answer};
+use crate::proto::service::Incoming;
 use crate::proto::msg::{Encrypted, MsgWrap, Unencrypted};
-use crate::proto::transport::{Abridged, PaddedIntermediate, Transport};
+use crate::proto::transport;
+use crate::proto::transport::{Transport, TransportImpl, TransportType};
 
-#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum ConnType {
     None = 0,
     All = 1 | 2 | 4,
@@ -30,62 +43,144 @@ pub enum ConnType {
     GenericMedia = 64,
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+/// 连接状态
+///
+/// ```text
+///            +-----------------------------+
+///            v                             |
+/// Idle -> Connecting -> Connected -> Reconnecting
+///            ^   |                         |
+///            |   +-------------------------+
+///            +---- Suspended
+/// ```
+/// 任意状态都可以关闭回到 `Idle`, 或挂起到 `Suspended`
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
 pub enum ConnState {
+    /// 未连接, 不会自动重连
     Idle,
+    /// 正在连接
     Connecting,
+    /// 连接断开或连接失败, 等待重连
     Reconnecting,
+    /// 已连接
     Connected,
+    /// 已挂起, 恢复之前不会自动重连
     Suspended,
 }
 
-pub(crate) struct Connection<T, W> {
-    dc_id: i32,
-    conn_type: ConnType,
-    pub(crate) socket: SocketImpl,
+impl ConnState {
+    /// 是否允许从当前状态切换到 [next]
+    pub fn can_transition_to(self, next: ConnState) -> bool {
+        use ConnState::*;
+        matches!(
+            (self, next),
+            (_, Idle) | (_, Suspended)
+                | (Idle, Connecting) | (Reconnecting, Connecting) | (Suspended, Connecting)
+                | (Connecting, Connected) | (Connecting, Reconnecting)
+                | (Connected, Reconnecting)
+        )
+    }
+}
+
+/// 连接状态变化事件
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ConnStateEvent {
+    pub dc_id: i32,
+    pub conn_type: ConnType,
+    pub state: ConnState,
+}
+
+pub(crate) type StateSender = broadcast::Sender<ConnStateEvent>;
+
+/// 所有连接共用, 每次连接成功后分配新的 token
+static NEXT_TOKEN: AtomicU32 = AtomicU32::new(1);
+
+/// 消息的打包和解包: rpc <-> 加密/非加密消息 <-> 传输协议数据包
+pub(crate) struct Codec<T, W> {
     transport: T,
     pub(crate) msg_wrap: W,
+    /// 收到但还不足一个完整数据包的数据
+    recv_buf: Vec<u8>,
 }
 
-impl<T: Transport, W: MsgWrap> Connection<T, W> {
-    pub async fn connect(addr: Addr, dc_id: i32, conn_type: ConnType, transport: T, msg_wrap: W) -> Result<Self> {
-        let socket = SocketImpl::connect(addr).await?;
+impl<T: Transport, W: MsgWrap> Codec<T, W> {
+    fn new(transport: T, msg_wrap: W) -> Self {
+        Self { transport, msg_wrap, recv_buf: vec![] }
+    }
 
-        Ok(Self {
-            dc_id,
-            conn_type,
-            socket,
-            transport,
-            msg_wrap,
-        })
+    /// 更换消息层, 例如握手完成后改为加密消息. 已收到的数据保留
+    fn with_msg_wrap<W2: MsgWrap>(self, msg_wrap: W2) -> Codec<T, W2> {
+        Codec { transport: self.transport, msg_wrap, recv_buf: self.recv_buf }
     }
 
-    pub async fn send_rpc<Rpc: MtRpc>(&mut self, rpc: &Rpc) -> Result<Rpc::Return> {
-        let data = rpc.to_bytes()?;
+    /// 打包, 返回 msg_id 和可以直接发送的数据
+    fn encode(&mut self, data: &[u8]) -> Result<(i64, Bytes)> {
         // 将原始 rpc 包装成加密/非加密消息
-        let (msg_id, msg) = self.msg_wrap.wrap(&data)?;
+        let (msg_id, msg) = self.msg_wrap.wrap(data)?;
         let mut buf = ByteBuffer::new();
 
         self.transport.pack(&msg, &mut buf);
+        Ok((msg_id, buf.to_bytes()))
+    }
 
-        self.socket.send(&buf).await?;
+    /// 解包收到的数据, 返回其中所有完整的消息 (msg_id, body). 不完整的数据包留到下次收到数据时继续解析
+    fn decode(&mut self, data: &[u8]) -> Result<Vec<(i64, Bytes)>> {
+        self.recv_buf.extend_from_slice(data);
+        let mut messages = vec![];
+        loop {
+            let mut buf = ByteBuffer::new();
+            match self.transport.unpack(&self.recv_buf, &mut buf) {
+                Ok(used) => {
+                    self.recv_buf.drain(..used);
+                    messages.push(self.msg_wrap.unwrap(&buf)?);
+                }
+                Err(e) if e.downcast_ref() == Some(&transport::Error::MissingBytes) => return Ok(messages),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
 
-        buf.clear();
+/// 一次 socket 连接, 断开后不能再使用, 由 [Connection] 重新建立
+pub(crate) struct Link<T, W> {
+    dc_id: i32,
+    conn_type: ConnType,
+    pub(crate) socket: SocketImpl,
+    pub(crate) codec: Codec<T, W>,
+}
+
+impl<T: Transport, W: MsgWrap> Link<T, W> {
+    pub async fn connect(
+        addr: Addr,
+        socket: &SocketConfig,
+        dc_id: i32,
+        conn_type: ConnType,
+        transport: T,
+        msg_wrap: W,
+    ) -> Result<Self> {
+        let socket = SocketImpl::connect(addr, socket).await?;
+
+        Ok(Self {
+            dc_id,
+            conn_type,
+            socket,
+            codec: Codec::new(transport, msg_wrap),
+        })
+    }
+
+    /// 发送 rpc 并等待响应, 由 [parse] 解析, 只在握手时使用. 非加密消息没有 req_msg_id, 收到的第一个消息即为响应
+    async fn send_rpc<Rpc: MtRpc, R>(&mut self, rpc: &Rpc, parse: fn(&[u8]) -> Result<R>) -> Result<R> {
+        let (_, data) = self.codec.encode(&rpc.to_bytes()?)?;
+        self.socket.send(&data).await.map_err(Error::Send)?;
 
         loop {
-            // todo 设置超时
             match self.socket.receiver().recv().await {
                 Event::OnReceivedData(data) => {
-                    let recv_count = self.transport.unpack(&data, &mut buf)?;
-                    if recv_count == 0 { continue; }
-
-                    let (res_msg_id, res) = self.msg_wrap.unwrap(&buf)?;
-                    if msg_id != res_msg_id { continue; }
-
-                    return Rpc::Return::from_bytes(&res);
+                    let Some((_, res)) = self.codec.decode(&data)?.into_iter().next() else { continue; };
+                    return parse(&res);
                 }
                 Event::OnSocketError(e) => {
-                    error!("{}", e);
+                    bail!(e);
                 }
                 Event::OnIntercepted => {
                     bail!(Error::Intercepted);
@@ -93,54 +188,205 @@ impl<T: Transport, W: MsgWrap> Connection<T, W> {
             }
         }
     }
-
-    pub async fn close(mut self) {
-        self.socket.close().await;
-    }
 }
 
-impl<T: Transport> Connection<T, Unencrypted> {
-    async fn handshake(mut self) -> Result<Connection<T, Encrypted>> {
-        info!("Handshake step1...");
-        let (rpc, step) = handshake::step1(self.dc_id)?;
-        let res = self.send_rpc(&rpc).await?;
+impl<T: Transport> Link<T, Unencrypted> {
+    async fn handshake(mut self, handshake_type: HandshakeType, config: &NetConfig) -> Result<Link<T, Encrypted>> {
+        info!("Handshake step1 ({:?})...", handshake_type);
+        let dc_id = config.environment.raw_dc_id(self.dc_id, handshake_type == HandshakeType::MediaTemp);
+        let (rpc, step) = handshake::step1(dc_id, handshake_type)?;
+        let res = self.send_rpc(&rpc, parse_res_pq).await?;
         info!("Handshake step2...");
-        let (rpc, step) = handshake::step2(step, res)?;
-        let res = self.send_rpc(&rpc).await?;
+        let (rpc, step) = handshake::step2(step, res, &config.rsa_keys)?;
+        let res = self.send_rpc(&rpc, parse_server_dh_params).await?;
         info!("Handshake step3...");
         let (rpc, step) = handshake::step3(step, res)?;
-        let res = self.send_rpc(&rpc).await?;
+        let res = self.send_rpc(&rpc, parse_set_client_dh_params_answer).await?;
         info!("Handshake complete!");
         let c = handshake::complete(step, res)?;
 
-        let mut session = Session::new();
-        session.first_salt = c.first_salt;
+        let session = self.codec.msg_wrap.session.clone();
+        session.set_server_salt(c.first_salt);
         session.set_time_diff(c.time_diff);
 
-        let msg_wrap = Encrypted::new(session.clone(), c.auth_key.into());
+        let msg_wrap = Encrypted::new(session.clone(), c.auth_key);
 
-        let Self { dc_id, conn_type, socket, transport, .. } = self;
-        Ok(Connection { dc_id, conn_type, socket, transport, msg_wrap })
+        let Self { dc_id, conn_type, socket, codec } = self;
+        Ok(Link { dc_id, conn_type, socket, codec: codec.with_msg_wrap(msg_wrap) })
     }
 }
 
-impl<T: Transport> Connection<T, Encrypted> {
-    pub async fn ping(&mut self) -> Result<()> {
-        let session = self.msg_wrap.session.clone();
-        let mut state = session.get_ping_state();
-        let now = TimeSync::local_millis();
-        if abs(now - state.last_check_ping_time) >= PING_DURATION {
-            state.last_check_ping_time = now;
-            state.last_ping_id += 1;
+impl<T: Transport> Link<T, Encrypted> {
+    /// [PFS](https://core.telegram.org/api/pfs): 用永久密钥绑定当前使用的临时密钥, 绑定完成前不能发送其他请求
+    async fn bind(&mut self, perm_key: &AuthKey, expires_at: i32) -> Result<()> {
+        let session = self.codec.msg_wrap.session.clone();
+        for _ in 0..3 {
+            let msg_id = session.new_msg_id();
+            let rpc = handshake::bind_temp_auth_key(perm_key, &self.codec.msg_wrap.auth_key, &session, msg_id, expires_at)?;
+            let data = self.codec.msg_wrap.wrap_with_msg_id(msg_id, &rpc.to_bytes()?)?;
+            let mut buf = ByteBuffer::new();
+            self.codec.transport.pack(&data, &mut buf);
+            self.socket.send(&buf.to_bytes()).await.map_err(Error::Send)?;
 
-            let mut req = PingDelayDisconnect::default();
-            req.ping_id = state.last_ping_id;
-            req.disconnect_delay = 35;
+            match self.recv_result(msg_id).await? {
+                Incoming::RpcResult { result, .. } => {
+                    if !parse_bool(&result)? { bail!("bind temp auth key failed"); }
+                    info!("(dc{} {:?}) Temp auth key bound, expires at {}", self.dc_id, self.conn_type, expires_at);
+                    return Ok(());
+                }
+                Incoming::RpcError { code, message, .. } => bail!(RequestError::Rpc { code, message }),
+                Incoming::BadServerSalt { new_server_salt, .. } => session.set_server_salt(new_server_salt),
+                Incoming::BadMsgNotification { msg_id, error_code: 16 | 17, .. } => session.sync_time(msg_id),
+                Incoming::BadMsgNotification { error_code, .. } => bail!(RequestError::BadMsg(error_code)),
+                _ => unreachable!(),
+            }
+        }
+        bail!(RequestError::TooManyResends)
+    }
 
-            let res = self.send_rpc(&req).await?;
-            // todo
+    /// 等待 [msg_id] 的响应或错误通知, 其他消息被丢弃
+    async fn recv_result(&mut self, msg_id: i64) -> Result<Incoming> {
+        loop {
+            match self.socket.receiver().recv().await {
+                Event::OnReceivedData(data) => {
+                    let mut incoming = vec![];
+                    for (res_msg_id, body) in self.codec.decode(&data)? {
+                        service::parse(res_msg_id, body, &mut incoming)?;
+                    }
+                    let found = incoming.into_iter().find(|msg| match msg {
+                        Incoming::RpcResult { req_msg_id, .. } | Incoming::RpcError { req_msg_id, .. } => *req_msg_id == msg_id,
+                        Incoming::BadServerSalt { bad_msg_id, .. } | Incoming::BadMsgNotification { bad_msg_id, .. } => *bad_msg_id == msg_id,
+                        _ => false,
+                    });
+                    if let Some(msg) = found { return Ok(msg); }
+                }
+                Event::OnSocketError(e) => bail!(e),
+                Event::OnIntercepted => bail!(Error::Intercepted),
+            }
         }
-        Ok(())
+    }
+}
+
+impl Link<TransportImpl, Encrypted> {
+    /// 拆分为读写两个任务, 之后可以同时发送多个请求, 收到的消息交给 [Dispatcher] 分发
+    /// [background] 时 keepalive 使用更长的间隔
+    fn split(self, token: u32, dispatcher: Arc<Dispatcher>, keepalive: KeepaliveConfig, background: bool) -> Channel {
+        let Self { dc_id, conn_type, socket, codec } = self;
+        let session = codec.msg_wrap.session.clone();
+        let codec = Arc::new(Mutex::new(codec));
+        let mut ping = Ping::new(dc_id, conn_type, keepalive);
+        ping.set_background(background);
+        let ping = Arc::new(Mutex::new(ping));
+        let disconnected = Signal::Disconnected { dc_id, conn_type, token };
+
+        let events = socket.receiver();
+        let reader = tokio::spawn({
+            let codec = codec.clone();
+            let ping = ping.clone();
+            let dispatcher = dispatcher.clone();
+            async move {
+                let source = Source { dc_id, conn_type, session: &session };
+                loop {
+                    match events.recv().await {
+                        Event::OnReceivedData(data) => {
+                            // 解包或解密失败后无法继续使用这个连接, 断开重连
+                            let messages = match codec.lock().unwrap().decode(&data) {
+                                Ok(messages) => messages,
+                                Err(e) => {
+                                    warn!("(dc{} {:?}) Decode failed: {}", dc_id, conn_type, e);
+                                    break;
+                                }
+                            };
+                            let mut incoming = vec![];
+                            for (msg_id, body) in messages {
+                                if let Err(e) = service::parse(msg_id, body, &mut incoming) {
+                                    warn!("(dc{} {:?}) Parse message {} failed: {}", dc_id, conn_type, msg_id, e);
+                                }
+                            }
+                            for msg in incoming {
+                                if let Incoming::Pong { ping_id, .. } = msg {
+                                    ping.lock().unwrap().on_pong(ping_id, Instant::now());
+                                }
+                                dispatcher.dispatch(&source, msg);
+                            }
+                        }
+                        Event::OnSocketError(e) => {
+                            warn!("(dc{} {:?}) {}", dc_id, conn_type, e);
+                            break;
+                        }
+                        Event::OnIntercepted => break,
+                    }
+                }
+                dispatcher.signal(disconnected);
+            }
+        });
+
+        let (writer, mut frames) = mpsc::unbounded_channel::<Bytes>();
+        tokio::spawn({
+            let dispatcher = dispatcher.clone();
+            async move {
+                let mut socket = socket;
+                while let Some(frame) = frames.recv().await {
+                    if let Err(e) = socket.send(&frame).await {
+                        warn!("(dc{} {:?}) Send failed: {}", dc_id, conn_type, e);
+                        dispatcher.signal(disconnected);
+                        break;
+                    }
+                }
+                socket.close().await;
+            }
+        });
+
+        // 按 Ping::interval 发送 ping_delay_disconnect, 连续收不到 pong 时按断开处理
+        let keepalive = tokio::spawn({
+            let (codec, writer, ping) = (codec.clone(), writer.clone(), ping.clone());
+            async move {
+                loop {
+                    let (req, interval) = {
+                        let mut ping = ping.lock().unwrap();
+                        let ping_id = ping.start(Instant::now());
+                        if ping.is_dead() {
+                            warn!("(dc{} {:?}) No pong for {} pings, disconnect", dc_id, conn_type, keepalive.max_missed);
+                            break;
+                        }
+                        (PingDelayDisconnect { ping_id, disconnect_delay: ping.disconnect_delay() }, ping.interval())
+                    };
+                    let frame = req.to_bytes().and_then(|data| codec.lock().unwrap().encode(&data));
+                    match frame {
+                        Ok((_, frame)) => if writer.send(frame).is_err() { return; },
+                        Err(e) => warn!("(dc{} {:?}) Encode ping failed: {}", dc_id, conn_type, e),
+                    }
+                    time::sleep(interval).await;
+                }
+                dispatcher.signal(disconnected);
+            }
+        });
+
+        Channel { codec, writer, reader, keepalive, ping }
+    }
+}
+
+/// 握手完成后的连接, 读写分别在独立的任务中进行
+struct Channel {
+    codec: Arc<Mutex<Codec<TransportImpl, Encrypted>>>,
+    writer: mpsc::UnboundedSender<Bytes>,
+    reader: JoinHandle<()>,
+    keepalive: JoinHandle<()>,
+    ping: Arc<Mutex<Ping>>,
+}
+
+impl Channel {
+    /// 打包后交给写任务发送, 返回 msg_id
+    fn send(&self, data: &[u8]) -> Result<i64> {
+        let (msg_id, frame) = self.codec.lock().unwrap().encode(data)?;
+        self.writer.send(frame).map_err(|_| Error::Send(anyhow!("writer closed")))?;
+        Ok(msg_id)
+    }
+
+    /// 停止读任务和 keepalive, 写任务发送完已有的数据后关闭 socket
+    fn close(self) {
+        self.reader.abort();
+        self.keepalive.abort();
     }
 }
 
@@ -150,26 +396,716 @@ impl ConnType {
     }
 }
 
-/// [secret] 用于 obfuscation, 当使用 WebSokcet 的时候需要
+/// [transport] 可以开启 obfuscation, 当使用 WebSokcet 的时候需要.
+/// 没有 [auth_key] 时按 [handshake_type] 握手生成, 连接和握手的总时间不超过 [SocketConfig::connect_timeout]
+#[allow(clippy::too_many_arguments)]
 pub async fn connect(
     addr: Addr,
+    config: &NetConfig,
     dc_id: i32,
     conn_type: ConnType,
     session: Session,
     auth_key: Option<AuthKey>,
-    secret: Option<String>,
-) -> Result<Connection<Abridged, Encrypted>> {
-    match auth_key {
-        Some(auth_key) => {
-            let transport = Abridged::new().obfuscation(secret);
-            let wrap = Encrypted::new(session.clone(), auth_key);
-            Connection::connect(addr, dc_id, conn_type, transport, wrap).await
-        }
-        None => {
-            let transport = Abridged::new().obfuscation(secret.clone());
-            let wrap = Unencrypted::new(session.clone());
-            let conn = Connection::connect(addr, dc_id, conn_type, transport, wrap).await?;
-            conn.handshake().await
+    handshake_type: HandshakeType,
+    transport: TransportImpl,
+) -> Result<Link<TransportImpl, Encrypted>> {
+    let connecting = async {
+        match auth_key {
+            Some(auth_key) => {
+                let wrap = Encrypted::new(session.clone(), auth_key);
+                Link::connect(addr, &config.socket, dc_id, conn_type, transport, wrap).await
+            }
+            None => {
+                let wrap = Unencrypted::new(session.clone());
+                let link = Link::connect(addr, &config.socket, dc_id, conn_type, transport, wrap).await?;
+                link.handshake(handshake_type, config).await
+            }
+        }
+    };
+    time::timeout(config.socket.connect_timeout, connecting).await
+        .map_err(|_| anyhow!("connect timed out after {:?}", config.socket.connect_timeout))?
+}
+
+/// 后台进行的连接尝试
+struct Dial {
+    task: JoinHandle<()>,
+    rx: oneshot::Receiver<Result<Dialed>>,
+}
+
+/// 连接成功后需要写回 [Connection] 的状态
+struct Dialed {
+    link: Link<TransportImpl, Encrypted>,
+    addr_index: usize,
+    transport_index: usize,
+    /// 连接使用的临时密钥
+    temp_key: Option<TempKey>,
+}
+
+/// [Connection] 发起连接时的状态快照, 在后台任务中依次尝试所有地址和传输协议
+struct Dialer {
+    dc_id: i32,
+    conn_type: ConnType,
+    dc: DcConfig,
+    config: Arc<NetConfig>,
+    session: Session,
+    keys: Arc<AuthKeys>,
+    addr_index: usize,
+    transport_index: usize,
+}
+
+impl Dialer {
+    /// 依次尝试所有地址和传输协议, 从最近一次连接成功的组合开始
+    async fn open(self) -> Result<Dialed> {
+        let config = self.config.clone();
+        let transport = config.transport.get(self.dc_id, self.conn_type);
+        let transports = transport.transports();
+        let addrs = self.dc.addrs_for(self.conn_type);
+        let (addr_count, transport_count) = (addrs.len(), transports.len());
+        // 配置检查之后仍可能为空, 例如 DataCenter 只有 CDN 地址
+        if addr_count == 0 || transport_count == 0 {
+            bail!("dc {} has no address or transport for {:?}", self.dc_id, self.conn_type);
         }
+
+        let mut last_err = None;
+        for i in 0..addr_count * transport_count {
+            let addr_index = (self.addr_index + i / transport_count) % addr_count;
+            let transport_index = (self.transport_index + i % transport_count) % transport_count;
+            let addr = addrs[addr_index].clone();
+            let transport_type = transports[transport_index];
+
+            let secret = self.dc.secret_for(&addr).or(transport.get_secret()).map(<[u8]>::to_vec);
+            let res = self.open_link(addr.clone(), transport_type, secret).await;
+
+            match res {
+                Ok((link, temp_key)) => return Ok(Dialed { link, addr_index, transport_index, temp_key }),
+                Err(e) => {
+                    warn!("({:?}) Connect dc {} via {:?} failed: {}", transport_type, self.dc_id, addr, e);
+                    last_err = Some(e);
+                }
+            }
+        }
+        Err(last_err.unwrap_or_else(|| anyhow!("no address or transport configured")))
+    }
+
+    /// 连接到 [addr], 返回连接和使用的临时密钥. 没有密钥时先握手, 生成的密钥写入共用的 [AuthKeys]. 开启 PFS 时:
+    /// 1. 没有永久密钥时在单独的连接上生成
+    /// 2. 没有临时密钥或临时密钥快过期时生成新的临时密钥, 并用永久密钥绑定
+    async fn open_link(&self, addr: Addr, transport_type: TransportType, secret: Option<Vec<u8>>) -> Result<(Link<TransportImpl, Encrypted>, Option<TempKey>)> {
+        let config = self.config.clone();
+        let (dc_id, conn_type) = (self.dc_id, self.conn_type);
+        let media = conn_type.is_media_type();
+        let raw_dc_id = config.environment.raw_dc_id(dc_id, self.dc.is_media_addr(&addr));
+        let transport = || TransportImpl::new(transport_type, secret.clone(), raw_dc_id as i16);
+
+        if !config.pfs {
+            let link = match self.keys.perm_or_lock().await {
+                Ok(key) => connect(addr, &config, dc_id, conn_type, self.session.clone(), Some(key), HandshakeType::Perm, transport()).await?,
+                Err(_guard) => {
+                    let link = connect(addr, &config, dc_id, conn_type, self.session.clone(), None, HandshakeType::Perm, transport()).await?;
+                    self.keys.set_perm(Some(link.codec.msg_wrap.auth_key.clone()));
+                    link
+                }
+            };
+            return Ok((link, None));
+        }
+
+        let perm_key = match self.keys.perm_or_lock().await {
+            Ok(key) => key,
+            Err(_guard) => {
+                let mut link = connect(addr.clone(), &config, dc_id, conn_type, Session::new(), None, HandshakeType::Perm, transport()).await?;
+                let key = link.codec.msg_wrap.auth_key.clone();
+                link.socket.close().await;
+                self.keys.set_perm(Some(key.clone()));
+                key
+            }
+        };
+
+        let handshake_type = if media { HandshakeType::MediaTemp } else { HandshakeType::Temp };
+        match self.keys.temp_or_lock(media, &self.session).await {
+            Ok(temp_key) => {
+                let link = connect(addr, &config, dc_id, conn_type, self.session.clone(), Some(temp_key.key.clone()), handshake_type, transport()).await?;
+                Ok((link, Some(temp_key)))
+            }
+            Err(_guard) => {
+                let mut link = connect(addr, &config, dc_id, conn_type, self.session.clone(), None, handshake_type, transport()).await?;
+                let expires_at = self.session.server_time() + TEMP_AUTH_KEY_EXPIRE_TIME;
+                link.bind(&perm_key, expires_at).await?;
+                let temp_key = TempKey { key: link.codec.msg_wrap.auth_key.clone(), expires_at };
+                self.keys.set_temp(media, Some(temp_key.clone()));
+                Ok((link, Some(temp_key)))
+            }
+        }
+    }
+}
+
+/// DataCenter 中某种类型的连接, 维护连接状态, 断开后按 [ReconnectConfig](crate::ReconnectConfig) 自动重连.
+/// 重连时依次尝试 DataCenter 的所有地址和配置的传输协议
+pub(crate) struct Connection {
+    dc_id: i32,
+    conn_type: ConnType,
+    /// 所属 DataCenter 的地址, 可能在收到新的配置后更新
+    dc: DcConfig,
+    config: Arc<NetConfig>,
+    session: Session,
+    /// 所属 DataCenter 的密钥, 所有连接共用
+    keys: Arc<AuthKeys>,
+    /// 当前连接使用的 PFS 临时密钥, 快过期时重连
+    temp_key: Option<TempKey>,
+    state: ConnState,
+    events: StateSender,
+    dispatcher: Arc<Dispatcher>,
+    backoff: Backoff,
+    /// 下次重连的时间
+    retry_at: Option<Instant>,
+    /// 最近一次连接成功的地址和传输协议下标, 下次连接优先使用
+    addr_index: usize,
+    transport_index: usize,
+    /// 每次连接成功后重新分配, 用于判断请求是否在当前连接上发送过
+    token: u32,
+    /// 最近一次发送请求的时间
+    last_used: Instant,
+    /// 后台模式, keepalive 使用更长的间隔
+    background: bool,
+    /// 正在进行的连接尝试
+    dial: Option<Dial>,
+    channel: Option<Channel>,
+    /// 连接后还没有发送过请求, 第一个请求需要包装 `initConnection`
+    need_init: bool,
+}
+
+impl Connection {
+    pub fn new(
+        dc: DcConfig,
+        conn_type: ConnType,
+        config: Arc<NetConfig>,
+        session: Session,
+        keys: Arc<AuthKeys>,
+        events: StateSender,
+        dispatcher: Arc<Dispatcher>,
+    ) -> Self {
+        let backoff = Backoff::new(config.reconnect.clone());
+        Self {
+            dc_id: dc.id(),
+            conn_type,
+            dc,
+            config,
+            session,
+            keys,
+            temp_key: None,
+            state: ConnState::Idle,
+            events,
+            dispatcher,
+            backoff,
+            retry_at: None,
+            addr_index: 0,
+            transport_index: 0,
+            token: 0,
+            last_used: Instant::now(),
+            background: false,
+            dial: None,
+            channel: None,
+            need_init: false,
+        }
+    }
+
+    pub fn dc_id(&self) -> i32 {
+        self.dc_id
+    }
+
+    pub fn conn_type(&self) -> ConnType {
+        self.conn_type
+    }
+
+    pub fn state(&self) -> ConnState {
+        self.state
+    }
+
+    pub fn token(&self) -> u32 {
+        self.token
+    }
+
+    /// [token] 是否为当前未断开的连接
+    pub fn is_live(&self, token: u32) -> bool {
+        self.channel.is_some() && self.token == token
+    }
+
+    /// 更新地址, 下次连接时生效
+    pub fn set_addrs(&mut self, dc: DcConfig) {
+        self.dc = dc;
+        self.addr_index = 0;
+    }
+
+    pub fn last_used(&self) -> Instant {
+        self.last_used
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    /// 恢复保存的会话, 下次连接时生效
+    pub fn restore(&mut self, session: Session) {
+        self.session = session;
+    }
+
+    /// 在后台任务中建立连接, 不阻塞调用方. 结束后通过 [Signal::Dialed] 通知,
+    /// 由 [Connection::poll_dial] 更新状态. 已连接或正在连接时忽略
+    pub fn connect(&mut self) {
+        if self.state == ConnState::Connected || self.dial.is_some() { return; }
+        self.set_state(ConnState::Connecting);
+
+        let dialer = Dialer {
+            dc_id: self.dc_id,
+            conn_type: self.conn_type,
+            dc: self.dc.clone(),
+            config: self.config.clone(),
+            session: self.session.clone(),
+            keys: self.keys.clone(),
+            addr_index: self.addr_index,
+            transport_index: self.transport_index,
+        };
+        let (tx, rx) = oneshot::channel();
+        let dispatcher = self.dispatcher.clone();
+        let dc_id = self.dc_id;
+        let task = tokio::spawn(async move {
+            tx.send(dialer.open().await).ok();
+            dispatcher.signal(Signal::Dialed { dc_id });
+        });
+        self.dial = Some(Dial { task, rx });
+    }
+
+    /// 处理已经结束的连接尝试, 还在进行中时返回 `None`. 失败时进入 [ConnState::Reconnecting] 等待重连
+    pub fn poll_dial(&mut self) -> Option<Result<()>> {
+        let dial = self.dial.as_mut()?;
+        let res = match dial.rx.try_recv() {
+            Err(TryRecvError::Empty) => return None,
+            Err(TryRecvError::Closed) => Err(anyhow!("connect task aborted")),
+            Ok(res) => res,
+        };
+        self.dial = None;
+
+        match res {
+            Ok(dialed) => {
+                self.addr_index = dialed.addr_index;
+                self.transport_index = dialed.transport_index;
+                self.temp_key = dialed.temp_key;
+                self.token = NEXT_TOKEN.fetch_add(1, Ordering::Relaxed);
+                let channel = dialed.link.split(self.token, self.dispatcher.clone(), self.config.keepalive, self.background);
+                self.channel = Some(channel);
+                self.need_init = true;
+                self.backoff.reset();
+                self.retry_at = None;
+                self.set_state(ConnState::Connected);
+                Some(Ok(()))
+            }
+            Err(e) => {
+                self.schedule_reconnect();
+                Some(Err(e))
+            }
+        }
+    }
+
+    /// 定时调用, 到达重连时间后重连, 临时密钥快过期时更换
+    pub fn tick(&mut self) {
+        if self.state == ConnState::Connected && self.temp_key.as_ref().is_some_and(|t| t.expiring(&self.session)) {
+            info!("(dc{} {:?}) Temp auth key is expiring, reconnect with a new one", self.dc_id, self.conn_type);
+            self.temp_key = None;
+            if let Some(channel) = self.channel.take() {
+                channel.close();
+            }
+            self.set_state(ConnState::Reconnecting);
+            self.retry_at = None;
+        }
+        if self.state == ConnState::Reconnecting && self.retry_at.map_or(true, |at| at <= Instant::now()) {
+            self.connect();
+        }
+    }
+
+    /// 发送消息, 不等待响应, 返回 msg_id. 响应由 [Dispatcher] 分发.
+    /// 连接后的第一个请求包装在 `invokeWithLayer(initConnection(...))` 中, 响应不变
+    pub fn send(&mut self, data: &[u8]) -> Result<i64> {
+        let Some(channel) = &self.channel else {
+            bail!(error::Error::NotConnected(self.state));
+        };
+        self.last_used = Instant::now();
+        if !self.need_init {
+            return channel.send(data);
+        }
+        let msg_id = channel.send(&invoke::init_connection(LAYER, &self.config.app, data))?;
+        self.need_init = false;
+        Ok(msg_id)
+    }
+
+    /// 读写任务发现连接断开, [token] 不是当前连接时忽略
+    pub fn on_lost(&mut self, token: u32) {
+        if self.is_live(token) {
+            self.on_disconnected();
+        }
+    }
+
+    /// 当前连接的 RTT, 未连接或还没有收到 pong 时返回 `None`
+    pub fn rtt(&self) -> Option<RttStats> {
+        self.channel.as_ref().and_then(|c| c.ping.lock().unwrap().stats())
+    }
+
+    /// 切换后台模式, 已建立的连接在下一次 ping 后生效
+    pub fn set_background(&mut self, background: bool) {
+        self.background = background;
+        if let Some(channel) = &self.channel {
+            channel.ping.lock().unwrap().set_background(background);
+        }
+    }
+
+    /// 挂起连接, 在 [Connection::resume] 之前不会自动重连
+    pub fn suspend(&mut self) {
+        if self.state == ConnState::Suspended { return; }
+        self.cancel_dial();
+        if let Some(channel) = self.channel.take() {
+            channel.close();
+        }
+        self.retry_at = None;
+        self.backoff.reset();
+        self.set_state(ConnState::Suspended);
+    }
+
+    /// 重新连接挂起的连接, 失败时进入 [ConnState::Reconnecting] 等待重连
+    pub fn resume(&mut self) {
+        if self.state != ConnState::Suspended { return; }
+        self.connect();
+    }
+
+    /// 关闭连接, 不再自动重连
+    pub fn close(&mut self) {
+        self.cancel_dial();
+        if let Some(channel) = self.channel.take() {
+            channel.close();
+        }
+        self.retry_at = None;
+        self.backoff.reset();
+        self.set_state(ConnState::Idle);
+    }
+
+    /// 停止正在进行的连接尝试
+    fn cancel_dial(&mut self) {
+        if let Some(dial) = self.dial.take() {
+            dial.task.abort();
+        }
+    }
+
+    fn on_disconnected(&mut self) {
+        if let Some(channel) = self.channel.take() {
+            channel.close();
+        }
+        self.schedule_reconnect();
+    }
+
+    /// 按退避策略安排下一次重连, 超过最大重连次数后回到 [ConnState::Idle]
+    fn schedule_reconnect(&mut self) {
+        match self.backoff.next_delay() {
+            Some(delay) => {
+                info!("(dc{} {:?}) Reconnect in {:?} (attempt {})", self.dc_id, self.conn_type, delay, self.backoff.attempts());
+                self.retry_at = Some(Instant::now() + delay);
+                self.set_state(ConnState::Reconnecting);
+            }
+            None => {
+                warn!("(dc{} {:?}) Give up reconnecting after {} attempts", self.dc_id, self.conn_type, self.backoff.attempts());
+                // 之后有新的请求时重新开始计数
+                self.backoff.reset();
+                self.retry_at = None;
+                self.set_state(ConnState::Idle);
+            }
+        }
+    }
+
+    fn set_state(&mut self, state: ConnState) {
+        if self.state == state { return; }
+        debug_assert!(self.state.can_transition_to(state), "{:?} -> {:?}", self.state, state);
+        info!("(dc{} {:?}) {:?} -> {:?}", self.dc_id, self.conn_type, self.state, state);
+
+        self.state = state;
+        // 没有订阅者时发送失败, 忽略
+        self.events.send(ConnStateEvent { dc_id: self.dc_id, conn_type: self.conn_type, state }).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use crate::defines::TEMP_AUTH_KEY_ROTATE_BEFORE;
+    use crate::net::{ReconnectConfig, TransportConfig};
+    use crate::net::handshake::TestServer;
+    use crate::proto::msg::{decrypt_client_message, encrypt_server_message};
+
+    use super::*;
+
+    #[test]
+    fn state_transitions() {
+        use ConnState::*;
+        for state in [Idle, Connecting, Reconnecting, Connected, Suspended] {
+            assert!(state.can_transition_to(Idle));
+            assert!(state.can_transition_to(Suspended));
+        }
+        assert!(Idle.can_transition_to(Connecting));
+        assert!(Connecting.can_transition_to(Connected));
+        assert!(Connected.can_transition_to(Reconnecting));
+        assert!(Reconnecting.can_transition_to(Connecting));
+        assert!(Suspended.can_transition_to(Connecting));
+
+        assert!(!Idle.can_transition_to(Connected));
+        assert!(!Idle.can_transition_to(Reconnecting));
+        assert!(!Reconnecting.can_transition_to(Connected));
+        assert!(!Connected.can_transition_to(Connecting));
+        assert!(!Suspended.can_transition_to(Connected));
+    }
+
+    /// 等待后台的连接尝试结束并更新状态
+    async fn dialed(conn: &mut Connection, dispatcher: &Dispatcher) -> Result<()> {
+        assert_eq!(dispatcher.signals().recv().await.unwrap(), Signal::Dialed { dc_id: conn.dc_id() });
+        conn.poll_dial().unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reconnect_until_give_up() {
+        let (events, mut events_rx) = broadcast::channel(16);
+        let (updates, _) = broadcast::channel(16);
+        let reconnect = ReconnectConfig::new()
+            .initial_delay(Duration::from_secs(1))
+            .jitter(0.0)
+            .max_attempts(2);
+        let config = Arc::new(NetConfig { reconnect, ..Default::default() });
+        let dispatcher = Arc::new(Dispatcher::new(updates, 2));
+        // 没有地址, 每次连接都失败
+        let mut conn = Connection::new(DcConfig::new(2), ConnType::Generic, config, Session::new(), Default::default(), events, dispatcher.clone());
+        let mut states = || {
+            let mut states = vec![];
+            while let Ok(event) = events_rx.try_recv() {
+                states.push(event.state);
+            }
+            states
+        };
+
+        conn.connect();
+        assert_eq!(conn.state(), ConnState::Connecting);
+        assert!(dialed(&mut conn, &dispatcher).await.is_err());
+        assert_eq!(states(), [ConnState::Connecting, ConnState::Reconnecting]);
+
+        // 未到重连时间
+        conn.tick();
+        assert_eq!(conn.state(), ConnState::Reconnecting);
+        assert!(states().is_empty());
+
+        time::advance(Duration::from_secs(1)).await;
+        conn.tick();
+        assert!(dialed(&mut conn, &dispatcher).await.is_err());
+        assert_eq!(states(), [ConnState::Connecting, ConnState::Reconnecting]);
+
+        // 超过最大重连次数后回到 Idle, 不再自动重连
+        time::advance(Duration::from_secs(2)).await;
+        conn.tick();
+        assert!(dialed(&mut conn, &dispatcher).await.is_err());
+        assert_eq!(states(), [ConnState::Connecting, ConnState::Idle]);
+        time::advance(Duration::from_secs(60)).await;
+        conn.tick();
+        assert_eq!(conn.state(), ConnState::Idle);
+
+        conn.suspend();
+        conn.resume();
+        assert!(dialed(&mut conn, &dispatcher).await.is_err());
+        conn.close();
+        assert_eq!(states(), [ConnState::Suspended, ConnState::Connecting, ConnState::Reconnecting, ConnState::Idle]);
+        assert!(matches!(conn.send(b"ping").map_err(|e| e.downcast::<error::Error>()), Err(Ok(error::Error::NotConnected(ConnState::Idle)))));
+    }
+
+    #[tokio::test]
+    async fn dial_without_transport() {
+        let (events, _) = broadcast::channel(16);
+        let (updates, _) = broadcast::channel(16);
+        let transport = serde_json::from_str::<TransportConfig>(r#"{ "transports": [] }"#).unwrap();
+        let config = Arc::new(NetConfig { transport: transport.into(), ..Default::default() });
+        let dispatcher = Arc::new(Dispatcher::new(updates, 2));
+        let dc = DcConfig::new(2).addrs("127.0.0.1:1");
+        let mut conn = Connection::new(dc, ConnType::Generic, config, Session::new(), Default::default(), events, dispatcher.clone());
+        conn.connect();
+        let e = dialed(&mut conn, &dispatcher).await.unwrap_err();
+        assert!(e.to_string().contains("no address or transport"));
+        assert_eq!(conn.state(), ConnState::Reconnecting);
+    }
+
+    #[tokio::test]
+    async fn close_while_dialing() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local = listener.local_addr().unwrap();
+        let (events, _) = broadcast::channel(16);
+        let (updates, _) = broadcast::channel(16);
+        let dispatcher = Arc::new(Dispatcher::new(updates, 2));
+        // 服务器不响应握手, 连接一直进行中
+        let dc = DcConfig::new(2).addrs(local);
+        let mut conn = Connection::new(dc, ConnType::Generic, Arc::new(NetConfig::default()), Session::new(), Default::default(), events, dispatcher);
+        conn.connect();
+        let _accepted = listener.accept().await.unwrap();
+        assert!(conn.poll_dial().is_none());
+
+        conn.close();
+        assert_eq!(conn.state(), ConnState::Idle);
+        assert!(conn.poll_dial().is_none());
+    }
+
+    #[tokio::test]
+    async fn rotate_expiring_temp_key() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local = listener.local_addr().unwrap();
+        let (events, _) = broadcast::channel(16);
+        let (updates, _) = broadcast::channel(16);
+        let dispatcher = Arc::new(Dispatcher::new(updates, 2));
+        let session = Session::new();
+        let keys = Arc::new(AuthKeys::default());
+        keys.set_perm(Some(AuthKey::from_bytes([1; 256])));
+        keys.set_temp(false, Some(TempKey { key: AuthKey::from_bytes([2; 256]), expires_at: session.server_time() + 3600 }));
+        let config = Arc::new(NetConfig { pfs: true, ..Default::default() });
+        let dc = DcConfig::new(2).addrs(local);
+        let mut conn = Connection::new(dc, ConnType::Generic, config, session.clone(), keys.clone(), events, dispatcher.clone());
+
+        // 已有未过期的临时密钥, 不需要握手
+        conn.connect();
+        let _first = listener.accept().await.unwrap();
+        dialed(&mut conn, &dispatcher).await.unwrap();
+        let token = conn.token();
+        // 只有连接后的第一个请求需要 initConnection
+        assert!(conn.need_init);
+        conn.send(b"first").unwrap();
+        assert!(!conn.need_init);
+        conn.tick();
+        assert_eq!(conn.state(), ConnState::Connected);
+
+        // 快过期时断开, 重连时生成新的临时密钥
+        let expiring = TempKey { key: AuthKey::from_bytes([2; 256]), expires_at: session.server_time() + TEMP_AUTH_KEY_ROTATE_BEFORE };
+        keys.set_temp(false, Some(expiring.clone()));
+        conn.temp_key = Some(expiring);
+        conn.tick();
+        assert!(!conn.is_live(token));
+        assert_eq!(conn.state(), ConnState::Connecting);
+        // 服务器不响应, 新的临时密钥握手一直进行中
+        let _second = listener.accept().await.unwrap();
+        assert!(conn.poll_dial().is_none());
+        conn.close();
+    }
+
+    async fn read_frame(stream: &mut tokio::net::TcpStream) -> Vec<u8> {
+        let len = stream.read_u32_le().await.unwrap();
+        let mut frame = vec![0; len as usize];
+        stream.read_exact(&mut frame).await.unwrap();
+        frame
+    }
+
+    async fn write_frame(stream: &mut tokio::net::TcpStream, frame: &[u8]) {
+        stream.write_all(&(frame.len() as u32).to_le_bytes()).await.unwrap();
+        stream.write_all(frame).await.unwrap();
+    }
+
+    /// PFS: 没有临时密钥时握手生成, 用永久密钥绑定后才算连接成功
+    #[tokio::test]
+    async fn handshake_and_bind_temp_key() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local = listener.local_addr().unwrap();
+        let mut handshake = TestServer::new();
+        let rsa_keys = handshake.rsa_keys();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            assert_eq!(stream.read_u32_le().await.unwrap(), 0xeeeeeeee);
+            let mut plain = Unencrypted::new(Session::new());
+            for step in 0..3 {
+                let (_, req) = plain.unwrap(&read_frame(&mut stream).await).unwrap();
+                let res = match step {
+                    0 => handshake.res_pq(&req),
+                    1 => handshake.server_dh_params(&req),
+                    _ => handshake.dh_gen(&req),
+                };
+                write_frame(&mut stream, &plain.wrap(&res).unwrap().1).await;
+            }
+
+            // auth.bindTempAuthKey 使用新的临时密钥加密, 返回 boolTrue
+            let temp_key = handshake.auth_key.unwrap();
+            let (session_id, msg_id, body) = decrypt_client_message(&temp_key, &read_frame(&mut stream).await);
+            assert_eq!(body[..4], 0xcdd42a05u32.to_le_bytes());
+            let mut result = ByteBuffer::new();
+            result.put_u32(0xf35c6d01);
+            result.put_i64(msg_id);
+            result.put_u32(0x997275b5);
+            write_frame(&mut stream, &encrypt_server_message(&temp_key, session_id, msg_id + 1, &result.to_bytes())).await;
+
+            let mut buf = [0; 256];
+            while stream.read(&mut buf).await.is_ok_and(|n| n > 0) {}
+            temp_key
+        });
+
+        let (events, _) = broadcast::channel(16);
+        let (updates, _) = broadcast::channel(16);
+        let dispatcher = Arc::new(Dispatcher::new(updates, 2));
+        let session = Session::new();
+        let keys = Arc::new(AuthKeys::default());
+        keys.set_perm(Some(AuthKey::from_bytes([1; 256])));
+        let transport = TransportConfig::new(TransportType::Intermediate).into();
+        let config = Arc::new(NetConfig { pfs: true, rsa_keys, transport, ..Default::default() });
+        let dc = DcConfig::new(2).addrs(local);
+        let mut conn = Connection::new(dc, ConnType::Generic, config, session.clone(), keys.clone(), events, dispatcher.clone());
+
+        conn.connect();
+        dialed(&mut conn, &dispatcher).await.unwrap();
+        assert_eq!(conn.state(), ConnState::Connected);
+        assert!((TestServer::TIME_DIFF - 1..=TestServer::TIME_DIFF + 1).contains(&session.time_diff()));
+        let temp_key = keys.temp(false).unwrap();
+        assert_eq!(conn.temp_key.as_ref().map(|k| &k.key), Some(&temp_key.key));
+
+        conn.close();
+        assert_eq!(server.await.unwrap(), temp_key.key);
+    }
+
+    #[test]
+    fn decode_partial_and_multiple_frames() {
+        let session = Session::new();
+        let mut codec = Codec::new(TransportImpl::new(TransportType::Intermediate, None, 2), Unencrypted::new(session.clone()));
+        // 服务器发出的 intermediate 数据包
+        let mut server = Unencrypted::new(session);
+        let mut frames = vec![];
+        for body in [&b"first"[..], b"second", b"third"] {
+            let (_, msg) = server.wrap(body).unwrap();
+            frames.extend((msg.len() as u32).to_le_bytes());
+            frames.extend_from_slice(&msg);
+        }
+        let bodies = |messages: Vec<(i64, Bytes)>| messages.into_iter().map(|(_, body)| body).collect::<Vec<_>>();
+
+        // 第一个完整的数据包和第二个的一部分
+        assert_eq!(bodies(codec.decode(&frames[..30]).unwrap()), [Bytes::from_static(b"first")]);
+        assert!(codec.decode(&frames[30..40]).unwrap().is_empty());
+        assert_eq!(bodies(codec.decode(&frames[40..]).unwrap()), [Bytes::from_static(b"second"), Bytes::from_static(b"third")]);
+    }
+
+    #[tokio::test]
+    async fn disconnect_on_bad_message() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            // 长度正确, 但不是使用当前密钥加密的消息
+            stream.write_all(&40u32.to_le_bytes()).await.unwrap();
+            stream.write_all(&[0; 40]).await.unwrap();
+            let mut buf = [0; 256];
+            while stream.read(&mut buf).await.is_ok_and(|n| n > 0) {}
+        });
+
+        let transport = TransportImpl::new(TransportType::Intermediate, None, 2);
+        let wrap = Encrypted::new(Session::new(), AuthKey::from_bytes([1; 256]));
+        let link = Link::connect(local.into(), &SocketConfig::new(), 2, ConnType::Generic, transport, wrap).await.unwrap();
+        let (updates, _) = broadcast::channel(16);
+        let dispatcher = Arc::new(Dispatcher::new(updates, 2));
+        let channel = link.split(7, dispatcher.clone(), KeepaliveConfig::default(), false);
+
+        let signal = dispatcher.signals().recv().await.unwrap();
+        assert_eq!(signal, Signal::Disconnected { dc_id: 2, conn_type: ConnType::Generic, token: 7 });
+        channel.close();
     }
 }
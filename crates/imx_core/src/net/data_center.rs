@@ -1,51 +1,246 @@
-use anyhow::Result;
-
-use crate::net::{AuthKey, Session};
-use crate::net::addr::Addr;
-use crate::net::connection::{connect, Connection, ConnType};
-use crate::proto::msg::Encrypted;
-use crate::proto::transport::Abridged;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anyhow::{bail, Result};
+use log::{info, warn};
+use tokio::time::Instant;
+
+use crate::net::{ConnState, Session};
+use crate::net::auth_key::{AuthKeys, TempKey};
+use crate::net::config::{DcConfig, NetConfig};
+use crate::net::connection::{Connection, ConnType, StateSender};
+use crate::net::dispatcher::Dispatcher;
+use crate::net::ping::RttStats;
+use crate::net::error::Error;
+use crate::storage::DcState;
+
+/// 同一类型的多个连接, 轮流使用
+#[derive(Default)]
+struct Pool {
+    conns: Vec<Connection>,
+    next: usize,
+}
 
 pub struct DataCenter {
     pub id: i32,
-    pub addr: Addr,
-    pub generic_conn: Option<Connection<Abridged, Encrypted>>,
-    pub session: Session,
-    auth_key: Option<AuthKey>,
+    addrs: DcConfig,
+    config: Arc<NetConfig>,
+    events: StateSender,
+    dispatcher: Arc<Dispatcher>,
+    /// 所有连接共用的密钥
+    keys: Arc<AuthKeys>,
+    pub(crate) generic_conn: Connection,
+    /// 其他类型的连接, 在第一次使用时建立, 空闲后关闭
+    pools: HashMap<ConnType, Pool>,
 }
 
 impl DataCenter {
-    pub fn new(id: i32, addr: Addr) -> Self {
-        Self {
-            id, addr,
-            generic_conn: None,
-            session: Session::new(),
-            auth_key: None,
+    pub(crate) fn new(
+        addrs: DcConfig,
+        config: Arc<NetConfig>,
+        events: StateSender,
+        dispatcher: Arc<Dispatcher>,
+    ) -> Self {
+        let id = addrs.id();
+        let keys = Arc::new(AuthKeys::default());
+        let generic_conn = Connection::new(
+            addrs.clone(), ConnType::Generic, config.clone(), Session::new(), keys.clone(), events.clone(), dispatcher.clone(),
+        );
+        Self { id, addrs, config, events, dispatcher, keys, generic_conn, pools: HashMap::new() }
+    }
+
+    /// 更新地址, 已建立的连接不受影响, 重连时使用新的地址
+    pub fn set_addrs(&mut self, addrs: DcConfig) {
+        for conn in self.conns_mut() {
+            conn.set_addrs(addrs.clone());
         }
+        self.addrs = addrs;
     }
 
-    pub async fn generic_conn(&mut self) -> Result<&mut Connection<Abridged, Encrypted>> {
-        if self.generic_conn.is_none() {
-            let conn = connect(
-                self.addr.clone(),
-                self.id,
-                ConnType::Generic,
-                self.session.clone(),
-                self.auth_key,
-                None, /* todo */
-            ).await?;
+    /// 在后台建立 generic 连接, 其他连接在使用时建立
+    pub fn connect(&mut self) {
+        info!("Connecting dc {} ...", self.id);
+        self.generic_conn.connect();
+    }
 
-            self.auth_key = Some(conn.msg_wrap.auth_key);
-            self.generic_conn = Some(conn);
+    /// 确保有可用的 [conn_type] 类型连接, [demand] 为等待发送的请求数,
+    /// 上传下载连接按需增加, 不超过 [PoolConfig](crate::PoolConfig) 的数量
+    pub fn prepare(&mut self, conn_type: ConnType, demand: usize) {
+        let conn_type = Self::route(conn_type);
+        if conn_type == ConnType::Generic {
+            Self::connect_idle(&mut self.generic_conn);
+            return;
         }
-        Ok(self.generic_conn.as_mut().unwrap())
+
+        let target = demand.clamp(1, self.config.pool.size(conn_type));
+        let count = self.pools.get(&conn_type).map_or(0, |p| p.conns.len());
+        let created: Vec<_> = (count..target).map(|_| self.new_conn(conn_type)).collect();
+
+        let pool = self.pools.entry(conn_type).or_default();
+        pool.conns.extend(created);
+        for conn in &mut pool.conns {
+            Self::connect_idle(conn);
+        }
+    }
+
+    /// 在 [conn_type] 类型的连接上发送, 返回 msg_id 和连接的 token
+    pub fn send(&mut self, conn_type: ConnType, data: &[u8]) -> Result<(i64, u32)> {
+        let conn_type = Self::route(conn_type);
+        let conn = if conn_type == ConnType::Generic {
+            Some(&mut self.generic_conn)
+        } else {
+            self.pools.get_mut(&conn_type).and_then(|pool| {
+                let len = pool.conns.len();
+                let index = (0..len)
+                    .map(|i| (pool.next + i) % len)
+                    .find(|&i| pool.conns[i].state() == ConnState::Connected)?;
+                pool.next = index + 1;
+                Some(&mut pool.conns[index])
+            })
+        };
+        let Some(conn) = conn.filter(|c| c.state() == ConnState::Connected) else {
+            bail!(Error::NoConnection(conn_type));
+        };
+        let msg_id = conn.send(data)?;
+        Ok((msg_id, conn.token()))
     }
 
-    pub async fn close(&mut self) {
-        if let Some(conn) = self.generic_conn.take() {
-            conn.close().await;
+    /// [token] 是否为未断开的连接
+    pub fn is_live(&self, token: u32) -> bool {
+        self.conns().any(|c| c.is_live(token))
+    }
+
+    /// 读写任务发现连接断开
+    pub fn on_lost(&mut self, token: u32) {
+        for conn in self.conns_mut() {
+            conn.on_lost(token);
+        }
+    }
+
+    /// 定时调用, 驱动各个连接的重连
+    pub fn tick(&mut self) {
+        for conn in self.conns_mut() {
+            conn.tick();
+        }
+    }
+
+    /// 处理已结束的连接尝试, 返回是否有新建立的连接
+    pub fn on_dialed(&mut self) -> bool {
+        let mut connected = false;
+        for conn in self.conns_mut() {
+            match conn.poll_dial() {
+                Some(Ok(())) => connected = true,
+                Some(Err(e)) => warn!("(dc{} {:?}) Connect failed: {}", conn.dc_id(), conn.conn_type(), e),
+                None => {}
+            }
+        }
+        connected
+    }
+
+    /// 各个连接的 RTT, 还没有测量结果的连接不包含在内
+    pub fn rtt_stats(&self) -> Vec<RttStats> {
+        self.conns().filter_map(|c| c.rtt()).collect()
+    }
+
+    /// 关闭空闲的连接, [in_flight] 判断连接上是否有等待响应的请求
+    pub fn close_idle(&mut self, in_flight: impl Fn(u32) -> bool) {
+        let idle_timeout = self.config.pool.idle_timeout;
+        let now = Instant::now();
+        for (&conn_type, pool) in self.pools.iter_mut() {
+            if conn_type == ConnType::Push { continue; }
+            pool.conns.retain_mut(|conn| {
+                let idle = now.duration_since(conn.last_used()) >= idle_timeout
+                    && conn.state() != ConnState::Connecting
+                    && !in_flight(conn.token());
+                if idle {
+                    info!("(dc{} {:?}) Close idle connection", conn.dc_id(), conn_type);
+                    conn.close();
+                }
+                !idle
+            });
+        }
+    }
+
+    /// 挂起连接, [keep_push] 时保留 push 连接
+    pub fn suspend(&mut self, keep_push: bool) {
+        for conn in self.conns_mut() {
+            if !(keep_push && conn.conn_type() == ConnType::Push) {
+                conn.suspend();
+            }
         }
-        // todo
+    }
+
+    /// 重新连接挂起的连接, [only_push] 时只恢复 push 连接
+    pub fn resume(&mut self, only_push: bool) {
+        for conn in self.conns_mut() {
+            if only_push && conn.conn_type() != ConnType::Push { continue; }
+            conn.resume();
+        }
+    }
+
+    /// 切换后台模式, 后台时 keepalive 使用更长的间隔
+    pub fn set_background(&mut self, background: bool) {
+        for conn in self.conns_mut() {
+            conn.set_background(background);
+        }
+    }
+
+    pub fn close(&mut self) {
+        self.generic_conn.close();
+        for (_, mut pool) in self.pools.drain() {
+            for conn in &mut pool.conns {
+                conn.close();
+            }
+        }
+    }
+
+    /// 需要保存的状态, 还没有永久密钥时返回 `None`
+    pub(crate) fn state(&self) -> Option<DcState> {
+        let session = self.generic_conn.session();
+        Some(DcState {
+            perm_key: self.keys.perm()?,
+            temp_key: self.keys.temp(false).map(|t| (t.key, t.expires_at)),
+            session_id: session.session_id(),
+            server_salt: session.server_salt(),
+            time_diff: session.time_diff(),
+        })
+    }
+
+    /// 恢复 [DataCenter::state] 保存的状态, 需要在建立连接之前调用
+    pub(crate) fn restore(&mut self, state: DcState) {
+        let session = Session::restore(state.session_id, state.server_salt, state.time_diff);
+        self.keys.set_perm(Some(state.perm_key));
+        self.keys.set_temp(false, state.temp_key.map(|(key, expires_at)| TempKey { key, expires_at }));
+        self.generic_conn.restore(session);
+    }
+
+    /// 不支持单独建立连接的类型使用 generic 连接
+    fn route(conn_type: ConnType) -> ConnType {
+        match conn_type {
+            ConnType::GenericMedia | ConnType::Download | ConnType::Upload | ConnType::Push => conn_type,
+            _ => ConnType::Generic,
+        }
+    }
+
+    fn new_conn(&self, conn_type: ConnType) -> Connection {
+        Connection::new(
+            self.addrs.clone(), conn_type, self.config.clone(), Session::new(), self.keys.clone(), self.events.clone(), self.dispatcher.clone(),
+        )
+    }
+
+    /// 未连接或放弃重连的连接重新建立, 失败时由 [DataCenter::tick] 重连
+    fn connect_idle(conn: &mut Connection) {
+        if conn.state() == ConnState::Idle {
+            conn.connect();
+        }
+    }
+
+    fn conns(&self) -> impl Iterator<Item = &Connection> {
+        std::iter::once(&self.generic_conn).chain(self.pools.values().flat_map(|p| &p.conns))
+    }
+
+    fn conns_mut(&mut self) -> impl Iterator<Item = &mut Connection> {
+        std::iter::once(&mut self.generic_conn).chain(self.pools.values_mut().flat_map(|p| &mut p.conns))
     }
 }
 
@@ -414,3 +609,87 @@ impl DataCenter {
     }
 
 }*/
+
+#[cfg(test)]
+mod tests {
+    use crate::net::AuthKey;
+    use crate::net::dispatcher::Signal;
+    use crate::PoolConfig;
+
+    use super::*;
+
+    #[test]
+    fn route_by_conn_type() {
+        assert_eq!(DataCenter::route(ConnType::Upload), ConnType::Upload);
+        assert_eq!(DataCenter::route(ConnType::Push), ConnType::Push);
+        assert_eq!(DataCenter::route(ConnType::Temp), ConnType::Generic);
+        assert_eq!(DataCenter::route(ConnType::All), ConnType::Generic);
+
+        let pool = PoolConfig::new().upload(0).download(3);
+        assert_eq!(pool.size(ConnType::Upload), 1);
+        assert_eq!(pool.size(ConnType::Download), 3);
+        assert_eq!(pool.size(ConnType::GenericMedia), 1);
+    }
+
+    #[tokio::test]
+    async fn suspend_and_resume() {
+        let (events, _) = tokio::sync::broadcast::channel(16);
+        let (updates, _) = tokio::sync::broadcast::channel(16);
+        let dispatcher = Arc::new(Dispatcher::new(updates, 2));
+        let mut dc = DataCenter::new(DcConfig::new(2), Arc::new(NetConfig::default()), events, dispatcher.clone());
+
+        let signals = dispatcher.signals();
+        let dialed = || async { assert_eq!(signals.recv().await.unwrap(), Signal::Dialed { dc_id: 2 }) };
+
+        // 没有地址, 连接失败后等待重连
+        dc.prepare(ConnType::Push, 1);
+        assert_eq!(dc.pools[&ConnType::Push].conns[0].state(), ConnState::Connecting);
+        dialed().await;
+        assert!(!dc.on_dialed());
+        let push_state = |dc: &DataCenter| dc.pools[&ConnType::Push].conns[0].state();
+        assert_eq!(push_state(&dc), ConnState::Reconnecting);
+
+        // 后台模式保留 push 连接
+        dc.suspend(true);
+        assert_eq!(dc.generic_conn.state(), ConnState::Suspended);
+        assert_eq!(push_state(&dc), ConnState::Reconnecting);
+
+        dc.suspend(false);
+        assert_eq!(push_state(&dc), ConnState::Suspended);
+        dc.resume(true);
+        dialed().await;
+        dc.on_dialed();
+        assert_eq!(dc.generic_conn.state(), ConnState::Suspended);
+        assert_eq!(push_state(&dc), ConnState::Reconnecting);
+
+        dc.resume(false);
+        dialed().await;
+        dc.on_dialed();
+        assert_eq!(dc.generic_conn.state(), ConnState::Reconnecting);
+    }
+
+    #[test]
+    fn save_and_restore() {
+        let (events, _) = tokio::sync::broadcast::channel(16);
+        let (updates, _) = tokio::sync::broadcast::channel(16);
+        let dispatcher = Arc::new(Dispatcher::new(updates, 2));
+        let new_dc = || DataCenter::new(DcConfig::new(2), Arc::new(NetConfig::default()), events.clone(), dispatcher.clone());
+
+        let dc = new_dc();
+        assert_eq!(dc.state(), None);
+
+        let state = DcState {
+            perm_key: AuthKey::from_bytes([1; 256]),
+            temp_key: Some((AuthKey::from_bytes([2; 256]), 100)),
+            session_id: 42,
+            server_salt: 7,
+            time_diff: -3,
+        };
+        let mut dc = new_dc();
+        dc.restore(state.clone());
+        assert_eq!(dc.state(), Some(state.clone()));
+        // 新建的连接共用密钥, 不复制
+        let _conn = dc.new_conn(ConnType::Download);
+        assert_eq!(Arc::strong_count(&dc.keys), 3);
+    }
+}
@@ -0,0 +1,195 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use anyhow::{anyhow, bail, Result};
+
+use crate::net::{Addr, DcConfig};
+use crate::util::hex;
+use crate::storage::{DC_TABLE_KEY, Storage};
+
+/// 从服务器获取的 DataCenter 地址和当前的 home DC
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DcTable {
+    pub home_dc: i32,
+    pub dcs: Vec<DcConfig>,
+}
+
+/// 保存 [DcTable], 下次启动时优先使用保存的地址, [ClientBuilder](crate::ClientBuilder) 传入的地址作为备用
+pub trait DcStore: Send + Sync + 'static {
+    fn load(&self) -> Result<Option<DcTable>>;
+
+    fn save(&self, table: &DcTable) -> Result<()>;
+}
+
+/// 保存在内存中, 只在同一个 [Client](crate::Client) 重新启动时有效
+#[derive(Debug, Default)]
+pub struct MemoryDcStore(Mutex<Option<DcTable>>);
+
+impl MemoryDcStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl DcStore for MemoryDcStore {
+    fn load(&self) -> Result<Option<DcTable>> {
+        Ok(self.0.lock().unwrap().clone())
+    }
+
+    fn save(&self, table: &DcTable) -> Result<()> {
+        *self.0.lock().unwrap() = Some(table.clone());
+        Ok(())
+    }
+}
+
+/// 保存到文件, 每行一条记录:
+/// ```text
+/// home 2
+/// ipv4 2 149.154.167.50:443
+/// media 2 149.154.167.151:443
+/// secret 2 149.154.167.151:443 dd000000...
+/// ```
+#[derive(Debug, Clone)]
+pub struct FileDcStore {
+    path: PathBuf,
+}
+
+impl FileDcStore {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl DcStore for FileDcStore {
+    fn load(&self) -> Result<Option<DcTable>> {
+        match fs::read_to_string(&self.path) {
+            Ok(text) => decode(&text).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn save(&self, table: &DcTable) -> Result<()> {
+        // 先写临时文件再替换, 避免写到一半时退出
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, encode(table))?;
+        fs::rename(&tmp, &self.path)?;
+        Ok(())
+    }
+}
+
+/// 保存到 [Storage] 中, 格式与 [FileDcStore] 相同.
+/// 设置了 [ClientBuilder::storage](crate::ClientBuilder::storage) 而没有设置 [DcStore] 时使用
+pub(crate) struct StorageDcStore(pub Arc<dyn Storage>);
+
+impl DcStore for StorageDcStore {
+    fn load(&self) -> Result<Option<DcTable>> {
+        match self.0.get(DC_TABLE_KEY)? {
+            Some(bytes) => decode(std::str::from_utf8(&bytes)?).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, table: &DcTable) -> Result<()> {
+        self.0.set(DC_TABLE_KEY, encode(table).as_bytes())
+    }
+}
+
+fn encode(table: &DcTable) -> String {
+    let mut text = format!("home {}\n", table.home_dc);
+    for dc in &table.dcs {
+        for (kind, addrs) in [("ipv4", &dc.ipv4), ("ipv6", &dc.ipv6), ("media", &dc.media), ("cdn", &dc.cdn)] {
+            for addr in addrs.iter() {
+                writeln!(text, "{} {} {}", kind, dc.id, addr).ok();
+            }
+        }
+        for (addr, secret) in &dc.secrets {
+            writeln!(text, "secret {} {} {}", dc.id, addr, hex::encode(secret)).ok();
+        }
+    }
+    text
+}
+
+fn decode(text: &str) -> Result<DcTable> {
+    let mut table = DcTable::default();
+    for line in text.lines().filter(|l| !l.trim().is_empty()) {
+        let fields: Vec<_> = line.split_whitespace().collect();
+        if let ["home", id] = fields[..] {
+            table.home_dc = id.parse()?;
+            continue;
+        }
+        let [kind, id, addr, rest @ ..] = &fields[..] else { bail!("invalid line: {}", line); };
+        let id: i32 = id.parse()?;
+        let index = match table.dcs.iter().position(|dc| dc.id == id) {
+            Some(i) => i,
+            None => {
+                table.dcs.push(DcConfig::new(id));
+                table.dcs.len() - 1
+            }
+        };
+        let dc = &mut table.dcs[index];
+        let addr = Addr::from(*addr);
+        match (*kind, rest) {
+            ("ipv4", []) => dc.ipv4.extend([addr]),
+            ("ipv6", []) => dc.ipv6.extend([addr]),
+            ("media", []) => dc.media.extend([addr]),
+            ("cdn", []) => dc.cdn.extend([addr]),
+            // 空密钥的行没有最后一列
+            ("secret", []) => {
+                dc.secrets.insert(addr.without_scheme(), vec![]);
+            }
+            ("secret", [secret]) => {
+                let secret = hex::decode(secret).ok_or_else(|| anyhow!("invalid secret: {}", line))?;
+                dc.secrets.insert(addr.without_scheme(), secret);
+            }
+            _ => bail!("invalid line: {}", line),
+        }
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::config::DcOption;
+    use crate::storage::MemoryStorage;
+
+    use super::*;
+
+    #[test]
+    fn file_round_trip() {
+        let options = [
+            DcOption { id: 2, ip_address: "149.154.167.51".into(), port: 443, is_static: true, ..Default::default() },
+            DcOption { id: 2, ip_address: "149.154.167.50".into(), port: 443, ..Default::default() },
+            DcOption { id: 2, ip_address: "2001:67c:4e8:f002::a".into(), port: 443, ipv6: true, ..Default::default() },
+            DcOption { id: 2, ip_address: "149.154.167.151".into(), port: 443, media_only: true, secret: Some([0xdd, 0x01].as_slice().into()), ..Default::default() },
+            DcOption { id: 2, ip_address: "149.154.167.99".into(), port: 443, tcpo_only: true, ..Default::default() },
+            DcOption { id: 4, ip_address: "149.154.167.91".into(), port: 443, ..Default::default() },
+        ];
+        let dc = DcConfig::from_options(2, &options);
+        // static 地址排在后面, 没有 secret 的 tcpo_only 地址使用空密钥混淆
+        assert_eq!(dc, DcConfig::new(2)
+            .addrs(["149.154.167.50:443", "149.154.167.99:443", "149.154.167.51:443", "[2001:67c:4e8:f002::a]:443"])
+            .media("149.154.167.151:443")
+            .secret("149.154.167.151:443", [0xdd, 0x01])
+            .secret("149.154.167.99:443", []));
+
+        let table = DcTable { home_dc: 2, dcs: vec![dc, DcConfig::from_options(4, &options)] };
+        let path = std::env::temp_dir().join(format!("imx-dc-store-{}", std::process::id()));
+        let store = FileDcStore::new(&path);
+        assert_eq!(store.load().unwrap(), None);
+        store.save(&table).unwrap();
+        assert_eq!(store.load().unwrap(), Some(table));
+        fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn storage_round_trip() {
+        let store = StorageDcStore(Arc::new(MemoryStorage::new()));
+        assert_eq!(store.load().unwrap(), None);
+        let table = DcTable { home_dc: 2, dcs: vec![DcConfig::new(2).addrs("149.154.167.50:443").secret("149.154.167.50:443", [0xee, 0x80, 0xff])] };
+        store.save(&table).unwrap();
+        assert_eq!(store.load().unwrap(), Some(table));
+    }
+}
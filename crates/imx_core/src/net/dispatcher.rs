@@ -0,0 +1,264 @@
+use std::sync::{Mutex, MutexGuard};
+use std::sync::atomic::{AtomicI32, Ordering};
+
+use async_channel::{Receiver, Sender, unbounded};
+use bytes::Bytes;
+use log::{debug, warn};
+use tokio::sync::broadcast;
+
+use crate::net::{ConnType, RequestError, Session};
+use crate::net::request::RequestQueue;
+use crate::proto::auth::parse_authorization;
+use crate::proto::service::Incoming;
+use crate::proto::Update;
+
+/// 读任务通知 [Client](crate::net::Client) 的事件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Signal {
+    /// 连接已断开, [token] 用于忽略已经被替换的连接
+    Disconnected { dc_id: i32, conn_type: ConnType, token: u32 },
+    /// [dc_id] 的某个连接尝试已结束 (成功或失败), 需要更新连接状态
+    Dialed { dc_id: i32 },
+    /// 有请求需要重发
+    Resend,
+    /// 收到 `PHONE_MIGRATE_X`, `USER_MIGRATE_X` 或 `NETWORK_MIGRATE_X`, 需要切换 home DC
+    Migrate { dc_id: i32 },
+    /// 在 [dc_id] 上还没有授权, 需要从 home DC 导出授权后导入
+    Unauthorized { dc_id: i32 },
+    /// 授权导入完成 (或失败), 可以继续发送到 [dc_id] 的请求
+    Imported { dc_id: i32 },
+    /// 收到 `auth.authorization`, 登录或导入授权成功
+    Authorized { user_id: i64 },
+}
+
+/// `303 SEE_OTHER` 错误中的迁移目标
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Migrate {
+    /// 账号所在的 DataCenter, 之后的请求都发送到该 DC
+    Home(i32),
+    /// 只有当前请求需要发送到该 DC, 比如文件所在的 DC
+    Request(i32),
+}
+
+/// 解析 [错误](https://core.telegram.org/api/errors#303-see-other) 中的 DataCenter id
+pub(crate) fn parse_migrate(code: i32, message: &str) -> Option<Migrate> {
+    if code != 303 { return None; }
+    let (kind, dc_id) = message.rsplit_once('_')?;
+    let dc_id = dc_id.parse().ok()?;
+    match kind {
+        "PHONE_MIGRATE" | "USER_MIGRATE" | "NETWORK_MIGRATE" => Some(Migrate::Home(dc_id)),
+        "FILE_MIGRATE" | "STATS_MIGRATE" => Some(Migrate::Request(dc_id)),
+        _ => None,
+    }
+}
+
+/// 收到消息的连接
+pub(crate) struct Source<'a> {
+    pub dc_id: i32,
+    pub conn_type: ConnType,
+    pub session: &'a Session,
+}
+
+/// 在各个连接的读任务中分发收到的消息:
+/// - 请求的响应按 `req_msg_id` 交给等待的调用方
+/// - 服务消息由 [Dispatcher::on_service] 处理
+/// - updates 解析后广播给订阅者
+pub(crate) struct Dispatcher {
+    requests: Mutex<RequestQueue>,
+    signals: (Sender<Signal>, Receiver<Signal>),
+    updates: broadcast::Sender<Update>,
+    home_dc: AtomicI32,
+}
+
+impl Dispatcher {
+    pub fn new(updates: broadcast::Sender<Update>, home_dc: i32) -> Self {
+        Self {
+            requests: Mutex::new(RequestQueue::new()),
+            signals: unbounded(),
+            updates,
+            home_dc: AtomicI32::new(home_dc),
+        }
+    }
+
+    /// 没有指定 DataCenter 的请求发送到的 DC
+    pub fn home_dc(&self) -> i32 {
+        self.home_dc.load(Ordering::Acquire)
+    }
+
+    pub fn set_home_dc(&self, dc_id: i32) {
+        self.home_dc.store(dc_id, Ordering::Release);
+    }
+
+    /// 请求队列, 不能在持有时 await
+    pub fn requests(&self) -> MutexGuard<'_, RequestQueue> {
+        self.requests.lock().unwrap()
+    }
+
+    pub fn signal(&self, signal: Signal) {
+        self.signals.0.try_send(signal).ok();
+    }
+
+    pub fn signals(&self) -> Receiver<Signal> {
+        self.signals.1.clone()
+    }
+
+    pub fn dispatch(&self, source: &Source, msg: Incoming) {
+        match msg {
+            Incoming::RpcResult { req_msg_id, result } => {
+                match parse_authorization(&result) {
+                    Ok(Some(user_id)) => self.signal(Signal::Authorized { user_id }),
+                    Ok(None) => {}
+                    Err(e) => warn!("(dc{} {:?}) Parse authorization failed: {}", source.dc_id, source.conn_type, e),
+                }
+                self.requests().complete(req_msg_id, Ok(result));
+            }
+            Incoming::RpcError { req_msg_id, code, message } => self.on_rpc_error(source, req_msg_id, code, message),
+            Incoming::BadServerSalt { bad_msg_id, new_server_salt } => {
+                source.session.set_server_salt(new_server_salt);
+                self.resend(bad_msg_id);
+            }
+            // msg_id 过小或过大, 说明本地时间不准
+            Incoming::BadMsgNotification { msg_id, bad_msg_id, error_code: 16 | 17 } => {
+                source.session.sync_time(msg_id);
+                self.resend(bad_msg_id);
+            }
+            Incoming::BadMsgNotification { bad_msg_id, error_code, .. } => {
+                self.requests().complete(bad_msg_id, Err(RequestError::BadMsg(error_code).into()));
+            }
+            Incoming::Update { body, .. } => self.on_update(body),
+            msg => self.on_service(source, msg),
+        }
+    }
+
+    fn on_rpc_error(&self, source: &Source, req_msg_id: i64, code: i32, message: String) {
+        let mut requests = self.requests();
+        match parse_migrate(code, &message) {
+            Some(Migrate::Home(dc_id)) => {
+                debug!("(dc{} {:?}) {}, migrate to dc{}", source.dc_id, source.conn_type, message, dc_id);
+                requests.redirect(req_msg_id, None);
+                self.signal(Signal::Migrate { dc_id });
+            }
+            Some(Migrate::Request(dc_id)) => {
+                debug!("(dc{} {:?}) {}, resend to dc{}", source.dc_id, source.conn_type, message, dc_id);
+                requests.redirect(req_msg_id, Some(dc_id));
+                self.signal(Signal::Resend);
+            }
+            // 发送到其他 DC 的请求, 导入授权后重发
+            None if code == 401 && message == "AUTH_KEY_UNREGISTERED" && requests.sent_dc(req_msg_id) != Some(self.home_dc()) => {
+                requests.resend(req_msg_id);
+                self.signal(Signal::Unauthorized { dc_id: source.dc_id });
+            }
+            None => requests.complete(req_msg_id, Err(RequestError::Rpc { code, message }.into())),
+        }
+    }
+
+    fn resend(&self, bad_msg_id: i64) {
+        self.requests().resend(bad_msg_id);
+        self.signal(Signal::Resend);
+    }
+
+    fn on_service(&self, source: &Source, msg: Incoming) {
+        match msg {
+            Incoming::NewSessionCreated { first_msg_id, server_salt } => {
+                debug!("(dc{} {:?}) New session created, first msg_id {}", source.dc_id, source.conn_type, first_msg_id);
+                source.session.set_server_salt(server_salt);
+            }
+            Incoming::Pong { ping_id, .. } => {
+                debug!("(dc{} {:?}) Pong {}", source.dc_id, source.conn_type, ping_id);
+            }
+            Incoming::MsgsAck { msg_ids } => {
+                debug!("(dc{} {:?}) Acked {:?}", source.dc_id, source.conn_type, msg_ids);
+            }
+            _ => {}
+        }
+    }
+
+    fn on_update(&self, body: Bytes) {
+        // 没有订阅者时发送失败, 忽略
+        self.updates.send(Update::parse(body)).ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::oneshot;
+
+    use crate::net::RequestOptions;
+
+    use super::*;
+
+    #[test]
+    fn migrate_error() {
+        assert_eq!(parse_migrate(303, "USER_MIGRATE_2"), Some(Migrate::Home(2)));
+        assert_eq!(parse_migrate(303, "NETWORK_MIGRATE_5"), Some(Migrate::Home(5)));
+        assert_eq!(parse_migrate(303, "FILE_MIGRATE_4"), Some(Migrate::Request(4)));
+        assert_eq!(parse_migrate(303, "FILE_MIGRATE_X"), None);
+        assert_eq!(parse_migrate(420, "FLOOD_WAIT_3"), None);
+    }
+
+    #[tokio::test]
+    async fn follow_migrate() {
+        let (updates, _) = broadcast::channel(8);
+        let dispatcher = Dispatcher::new(updates, 1);
+        let session = Session::new();
+        let source = Source { dc_id: 1, conn_type: ConnType::Generic, session: &session };
+
+        let (tx, _rx) = oneshot::channel();
+        {
+            let mut requests = dispatcher.requests();
+            let id = requests.push(Bytes::from_static(b"a"), RequestOptions::new(), Duration::from_secs(10), tx);
+            requests.pending(1, |_| false);
+            requests.sent(id, 1, 100, 1);
+        }
+        dispatcher.dispatch(&source, Incoming::RpcError { req_msg_id: 100, code: 303, message: "PHONE_MIGRATE_2".into() });
+        assert_eq!(dispatcher.signals().recv().await.unwrap(), Signal::Migrate { dc_id: 2 });
+        dispatcher.set_home_dc(2);
+        assert_eq!(dispatcher.requests().pending(dispatcher.home_dc(), |_| true)[0].dc_id, 2);
+
+        // 在非 home DC 上未授权, 需要导入授权
+        dispatcher.requests().sent(0, 4, 104, 2);
+        let source = Source { dc_id: 4, conn_type: ConnType::Download, session: &session };
+        dispatcher.dispatch(&source, Incoming::RpcError { req_msg_id: 104, code: 401, message: "AUTH_KEY_UNREGISTERED".into() });
+        assert_eq!(dispatcher.signals().recv().await.unwrap(), Signal::Unauthorized { dc_id: 4 });
+        assert_eq!(dispatcher.requests().pending(2, |_| true).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn route_by_req_msg_id() {
+        let (updates, mut updates_rx) = broadcast::channel(8);
+        let dispatcher = Dispatcher::new(updates, 1);
+        let session = Session::new();
+        let source = Source { dc_id: 1, conn_type: ConnType::Generic, session: &session };
+
+        // 两个请求同时等待响应
+        let (tx_a, rx_a) = oneshot::channel();
+        let (tx_b, rx_b) = oneshot::channel();
+        {
+            let mut requests = dispatcher.requests();
+            let a = requests.push(Bytes::from_static(b"a"), RequestOptions::new(), Duration::from_secs(10), tx_a);
+            let b = requests.push(Bytes::from_static(b"b"), RequestOptions::new(), Duration::from_secs(10), tx_b);
+            requests.pending(1, |_| false);
+            requests.sent(a, 1, 100, 1);
+            requests.sent(b, 1, 104, 1);
+        }
+
+        dispatcher.dispatch(&source, Incoming::Update { msg_id: 1, body: Bytes::from_static(b"update") });
+        dispatcher.dispatch(&source, Incoming::RpcResult { req_msg_id: 104, result: Bytes::from_static(b"b") });
+        dispatcher.dispatch(&source, Incoming::BadServerSalt { bad_msg_id: 100, new_server_salt: 42 });
+
+        assert_eq!(updates_rx.recv().await.unwrap(), Update::Raw(Bytes::from_static(b"update")));
+        assert_eq!(rx_b.await.unwrap().unwrap(), Bytes::from_static(b"b"));
+        // 使用新的 salt 重发
+        assert_eq!(session.server_salt(), 42);
+        assert_eq!(dispatcher.signals().recv().await.unwrap(), Signal::Resend);
+        let pending = dispatcher.requests().pending(1, |t| t == 1);
+        assert_eq!(pending.iter().map(|p| (p.id, p.body.clone())).collect::<Vec<_>>(), vec![(0, Bytes::from_static(b"a"))]);
+
+        dispatcher.requests().sent(0, 1, 108, 1);
+        dispatcher.dispatch(&source, Incoming::RpcError { req_msg_id: 108, code: 400, message: "BAD_REQUEST".into() });
+        let e = rx_a.await.unwrap().unwrap_err();
+        assert_eq!(e.downcast::<RequestError>().unwrap(), RequestError::Rpc { code: 400, message: "BAD_REQUEST".into() });
+    }
+}
@@ -1,12 +1,14 @@
 use std::io;
 use thiserror::Error;
 
+use crate::net::{ConnState, ConnType};
+
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("EOF")]
-    EOF,
-    #[error("intercepted")]
-    Intercepted,
+    #[error("not connected: {0:?}")]
+    NotConnected(ConnState),
+    #[error("no connection available: {0:?}")]
+    NoConnection(ConnType),
     #[error("{0}")]
     Io(#[from] io::Error),
     #[error("{0}")]
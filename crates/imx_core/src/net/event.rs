@@ -26,19 +26,12 @@ impl EventSender {
 }
 
 impl EventReceiver {
-    pub fn len(&self) -> usize {
-        self.0.len()
-    }
-
     pub async fn recv(&self) -> Event {
         self.0.recv().await.unwrap_or_else(|_| Event::OnIntercepted)
     }
-
-    pub fn close(&self) -> bool {
-        self.0.close()
-    }
 }
 
+#[allow(clippy::enum_variant_names)]
 pub enum Event {
     // OnConnectionConnected { dc_id: usize, conn_type: ConnType },
     OnReceivedData(Vec<u8>),
@@ -50,7 +43,10 @@ impl Debug for Event {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match *self {
             // Event::OnConnectionConnected { dc_id, conn_type } => write!(f, "OnConnectionConnected(dc_id={}, conn_type={:?})", dc_id, conn_type),
+            #[cfg(feature = "trace")]
             Event::OnReceivedData(ref data) => write!(f, "OnReceivedData(data={:?})", data),
+            #[cfg(not(feature = "trace"))]
+            Event::OnReceivedData(ref data) => write!(f, "OnReceivedData(len={})", data.len()),
             Event::OnIntercepted => write!(f, "OnIntercepted"),
             Event::OnSocketError(ref e) => write!(f, "OnSocketError {}", e),
         }
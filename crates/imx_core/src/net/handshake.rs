@@ -1,13 +1,20 @@
+use std::fmt::{Debug, Formatter};
+
 use anyhow::{bail, Result};
-use num::BigUint;
-use rand::{RngCore, thread_rng};
-use rsa::pkcs1::DecodeRsaPublicKey;
-use rsa::RsaPublicKey;
+use rand::{random, RngCore, thread_rng};
+use rsa::BigUint;
 use thiserror::Error;
+use zeroize::Zeroizing;
+
 use crate::defines::TEMP_AUTH_KEY_EXPIRE_TIME;
 
+use crate::net::{AuthKey, rsa_keys, RsaKeys, Session};
+use crate::net::time_sync::TimeSync;
 use crate::proto;
-use crate::proto::{MtSer, PQInnerData};
+use crate::proto::{ByteBuffer, MtSer, PQInnerData};
+use crate::proto::handshake::parse_server_dh_inner_data;
+use crate::proto::msg::{aes_ige_decrypt, aes_ige_encrypt, encrypt_v1};
+use crate::sha1;
 use crate::util::factorize;
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -15,30 +22,50 @@ pub enum HandshakeType {
     Perm,
     Temp,
     MediaTemp,
-    Current,
-    All
 }
 /// https://core.telegram.org/mtproto/auth_key#dh-exchange-initiation
 pub struct Step1 {
     nonce: [u8; 16],
     dc_id: i32,
+    handshake_type: HandshakeType,
 }
+/// `nonce` 和 `server_nonce` 明文传输, `new_nonce` 用于派生密钥, drop 时清零
 pub struct Step2 {
     nonce: [u8; 16],
     server_nonce: [u8; 16],
-    new_nonce: [u8; 32],
+    new_nonce: Zeroizing<[u8; 32]>,
 }
+/// [gab] 为 DH 交换得到的 `g^ab`, 大端序, 即 auth_key 的原始数据. drop 时清零
 pub struct Step3 {
     nonce: [u8; 16],
     server_nonce: [u8; 16],
-    new_nonce: [u8; 32],
-    gab: BigUint,
+    new_nonce: Zeroizing<[u8; 32]>,
+    gab: Zeroizing<Vec<u8>>,
     time_diff: i32,
 }
 
+impl Debug for Step2 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Step2")
+            .field("nonce", &self.nonce)
+            .field("server_nonce", &self.server_nonce)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Debug for Step3 {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Step3")
+            .field("nonce", &self.nonce)
+            .field("server_nonce", &self.server_nonce)
+            .field("time_diff", &self.time_diff)
+            .finish_non_exhaustive()
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct Completion {
-    pub auth_key: [u8; 256],
+    pub auth_key: AuthKey,
     pub time_diff: i32,
     pub first_salt: i64,
 }
@@ -53,18 +80,34 @@ pub enum Error {
     InnerDataTooLarge(usize),
     #[error("all server fingerprints are unknown: {0:?}")]
     UnknownFingerprints(Vec<i64>),
+    #[error("server_DH_params_fail")]
+    ServerDHParamsFail,
+    #[error("invalid encrypted answer")]
+    InvalidAnswer,
+    #[error("invalid DH params")]
+    InvalidDHParams,
+    #[error("invalid new_nonce_hash")]
+    InvalidNewNonceHash,
+    #[error("dh_gen_retry")]
+    DHGenRetry,
+    #[error("dh_gen_fail")]
+    DHGenFail,
 }
 
-pub fn step1(dc_id: i32) -> Result<(proto::ReqPQMulti, Step1)> {
+/// [handshake_type] 为 [HandshakeType::Temp] 或 [HandshakeType::MediaTemp] 时生成
+/// [TEMP_AUTH_KEY_EXPIRE_TIME] 后过期的临时密钥, 需要通过 [bind_temp_auth_key] 绑定到永久密钥.
+/// [dc_id] 为 [Environment::raw_dc_id](crate::net::Environment) 编码后的 id
+pub fn step1(dc_id: i32, handshake_type: HandshakeType) -> Result<(proto::ReqPQMulti, Step1)> {
     let mut random_bytes = [0; 16];
     thread_rng().fill_bytes(&mut random_bytes);
     let req = proto::ReqPQMulti { nonce: random_bytes };
-    let step = Step1 { nonce: random_bytes, dc_id };
+    let step = Step1 { nonce: random_bytes, dc_id, handshake_type };
     Ok((req, step))
 }
 
-pub fn step2(prev: Step1, res: proto::ResPQ) -> Result<(proto::ReqDHParams, Step2)> {
-    let Step1 { nonce, dc_id } = prev;
+/// 用 [keys] 中与服务器 fingerprint 匹配的公钥加密 `p_q_inner_data`
+pub fn step2(prev: Step1, res: proto::ResPQ, keys: &RsaKeys) -> Result<(proto::ReqDHParams, Step2)> {
+    let Step1 { nonce, dc_id, handshake_type } = prev;
 
     check_nonce(&res.nonce, &nonce)?;
 
@@ -83,30 +126,19 @@ pub fn step2(prev: Step1, res: proto::ResPQ) -> Result<(proto::ReqDHParams, Step
     let p = [(p >> 24) as u8, (p >> 16) as u8, (p >> 8) as u8, p as u8];
     let q = [(q >> 24) as u8, (q >> 16) as u8, (q >> 8) as u8, q as u8];
 
-    let mut random_bytes = [0; 32 + 224];
-    thread_rng().fill_bytes(&mut random_bytes);
-    let new_nonce = {
-        let mut buf = [0; 32];
-        buf.copy_from_slice(&random_bytes[..32]);
-        buf
-    };
-    // Remove the now-used first part from our available random data.
-    let random_bytes = {
-        let mut buf = [0; 224];
-        buf.copy_from_slice(&random_bytes[32..]);
-        buf
-    };
+    let mut new_nonce = Zeroizing::new([0; 32]);
+    thread_rng().fill_bytes(&mut *new_nonce);
 
-    let handshake_type = HandshakeType::Perm;
-    let pq_inner_data = if handshake_type == HandshakeType::Perm {
+    // 包含 new_nonce, 结构体和序列化的结果都在 drop 时清零
+    let pq_inner_data = Zeroizing::new(if handshake_type == HandshakeType::Perm {
         PQInnerData::Dc {
             pq: res.pq,
             p,
             q,
             nonce,
             server_nonce: res.server_nonce,
-            new_nonce,
-            dc: dc_id, // todo if testBackend { cur_dc_id + 10000 } else { cur_dc_id }
+            new_nonce: *new_nonce,
+            dc: dc_id,
         }
     } else {
         PQInnerData::TempDc {
@@ -115,28 +147,24 @@ pub fn step2(prev: Step1, res: proto::ResPQ) -> Result<(proto::ReqDHParams, Step
             q,
             nonce,
             server_nonce: res.server_nonce,
-            new_nonce,
-            dc: dc_id, // todo if testBackend { cur_dc_id + 10000 } else { cur_dc_id }
+            new_nonce: *new_nonce,
+            dc: dc_id,
             expires_in: TEMP_AUTH_KEY_EXPIRE_TIME,
         }
-    };
-    let pq_inner_data = pq_inner_data.to_bytes()?;
+    });
+    let pq_inner_data = proto::to_zeroizing(&*pq_inner_data, 256)?;
     if pq_inner_data.len() > 144 {
         bail!(Error::InnerDataTooLarge(pq_inner_data.len()));
     }
-    // todo
 
     let public_key_fingerprint = match res.server_public_key_fingerprints
-        .iter().cloned().find(|&f| pub_key_for_fingerprint(f).is_some()) {
+        .iter().cloned().find(|&f| keys.get(f).is_some()) {
         Some(x) => x,
         None => bail!(Error::UnknownFingerprints(res.server_public_key_fingerprints.clone()))
     };
 
-    let pub_key = pub_key_for_fingerprint(public_key_fingerprint).unwrap();
-
-    let encrypted_data = vec![]; // todo rsa::encrypt_hashed(&pq_inner_data, &pub_key, &random_bytes);
-
-
+    let pub_key = keys.get(public_key_fingerprint).unwrap();
+    let encrypted_data = rsa_keys::encrypt_padded(pub_key, &pq_inner_data);
 
     let req = proto::ReqDHParams {
         nonce,
@@ -155,12 +183,172 @@ pub fn step2(prev: Step1, res: proto::ResPQ) -> Result<(proto::ReqDHParams, Step
     Ok((req, step))
 }
 
+/// 解密服务器的 DH 参数 `g`, `dh_prime`, `g_a`, 生成随机的 `b`, 发送 `g_b` 并计算 `g_ab`.
+/// 只校验 `dh_prime` 的长度和 `g`, `g_a`, `g_b` 的取值范围, 不做素性检查
 pub fn step3(prev: Step2, res: proto::ServerDHParams) -> Result<(proto::SetClientDHParams, Step3)> {
-    todo!()
+    let Step2 { nonce, server_nonce, new_nonce } = prev;
+
+    let encrypted_answer = match res {
+        proto::ServerDHParams::Ok { nonce: res_nonce, server_nonce: res_server_nonce, encrypted_answer } => {
+            check_nonce(&res_nonce, &nonce)?;
+            check_nonce(&res_server_nonce, &server_nonce)?;
+            encrypted_answer
+        }
+        proto::ServerDHParams::Fail { .. } => bail!(Error::ServerDHParamsFail),
+    };
+    if encrypted_answer.is_empty() || encrypted_answer.len() % 16 != 0 {
+        bail!(Error::InvalidAnswer);
+    }
+
+    // answer_with_hash = sha1(answer) + answer + padding
+    let (key, iv) = tmp_aes_key_iv(&server_nonce, &new_nonce);
+    let answer_with_hash = aes_ige_decrypt(&encrypted_answer, &key, &iv);
+    if answer_with_hash.len() < 20 {
+        bail!(Error::InvalidAnswer);
+    }
+    let (inner, len) = parse_server_dh_inner_data(&answer_with_hash[20..])?;
+    if sha1!(&answer_with_hash[20..20 + len]) != answer_with_hash[..20] {
+        bail!(Error::InvalidAnswer);
+    }
+    check_nonce(&inner.nonce, &nonce)?;
+    check_nonce(&inner.server_nonce, &server_nonce)?;
+
+    let dh_prime = BigUint::from_bytes_be(&inner.dh_prime);
+    if dh_prime.bits() != 2048 || !(2..=7).contains(&inner.g) {
+        bail!(Error::InvalidDHParams);
+    }
+    let g = BigUint::from(inner.g as u32);
+    let g_a = BigUint::from_bytes_be(&inner.g_a);
+    check_dh_value(&g_a, &dh_prime)?;
+
+    let mut b = Zeroizing::new([0; 256]);
+    thread_rng().fill_bytes(&mut *b);
+    let b = Zeroizing::new(BigUint::from_bytes_be(&*b));
+    let g_b = g.modpow(&b, &dh_prime);
+    check_dh_value(&g_b, &dh_prime)?;
+    let gab = Zeroizing::new(g_a.modpow(&b, &dh_prime).to_bytes_be());
+
+    let client_inner = proto::ClientDHInnerData {
+        nonce,
+        server_nonce,
+        retry_id: 0,
+        g_b: g_b.to_bytes_be(),
+    }.to_bytes()?;
+    // data_with_hash = sha1(data) + data + padding, 总长度为 16 的倍数
+    let mut data_with_hash = sha1!(&client_inner).to_vec();
+    data_with_hash.extend_from_slice(&client_inner);
+    let mut padding = vec![0; (16 - data_with_hash.len() % 16) % 16];
+    thread_rng().fill_bytes(&mut padding);
+    data_with_hash.extend(padding);
+
+    let req = proto::SetClientDHParams {
+        nonce,
+        server_nonce,
+        encrypted_data: aes_ige_encrypt(&data_with_hash, &key, &iv),
+    };
+    let step = Step3 {
+        nonce,
+        server_nonce,
+        new_nonce,
+        gab,
+        time_diff: inner.server_time - TimeSync::local_seconds(),
+    };
+
+    Ok((req, step))
 }
 
+/// 校验 `new_nonce_hash`, 生成 auth_key 和第一个 server salt.
+/// 服务器返回 `dh_gen_retry` 时不重试, 由重连重新开始握手
 pub fn complete(prev: Step3, res: proto::SetClientDHParamsAnswer) -> Result<Completion> {
-    todo!()
+    let Step3 { nonce, server_nonce, new_nonce, gab, time_diff } = prev;
+
+    let (res_nonce, res_server_nonce, number, new_nonce_hash) = match res {
+        proto::SetClientDHParamsAnswer::Ok { nonce, server_nonce, new_nonce_hash1 } => (nonce, server_nonce, 1, new_nonce_hash1),
+        proto::SetClientDHParamsAnswer::Retry { nonce, server_nonce, new_nonce_hash2 } => (nonce, server_nonce, 2, new_nonce_hash2),
+        proto::SetClientDHParamsAnswer::Fail { nonce, server_nonce, new_nonce_hash3 } => (nonce, server_nonce, 3, new_nonce_hash3),
+    };
+    check_nonce(&res_nonce, &nonce)?;
+    check_nonce(&res_server_nonce, &server_nonce)?;
+
+    // g_ab 为大端序, 不足 256 字节时在前面补 0
+    let mut key = Zeroizing::new([0; 256]);
+    key[256 - gab.len()..].copy_from_slice(&gab);
+    let auth_key = AuthKey::from_bytes(*key);
+
+    let auth_key_aux_hash = &sha1!(auth_key.as_bytes())[..8];
+    if sha1!(&*new_nonce, [number], auth_key_aux_hash)[4..20] != new_nonce_hash {
+        bail!(Error::InvalidNewNonceHash);
+    }
+    match number {
+        2 => bail!(Error::DHGenRetry),
+        3 => bail!(Error::DHGenFail),
+        _ => {}
+    }
+
+    let first_salt = i64::from_le_bytes(new_nonce[..8].try_into().unwrap())
+        ^ i64::from_le_bytes(server_nonce[..8].try_into().unwrap());
+
+    Ok(Completion { auth_key, time_diff, first_salt })
+}
+
+/// [PFS](https://core.telegram.org/api/pfs): 生成 `auth.bindTempAuthKey` 请求, 使用 [temp_key] 加密后以 [msg_id] 发送
+pub fn bind_temp_auth_key(
+    perm_key: &AuthKey,
+    temp_key: &AuthKey,
+    session: &Session,
+    msg_id: i64,
+    expires_at: i32,
+) -> Result<proto::BindTempAuthKey> {
+    let nonce = random();
+    let inner = proto::BindAuthKeyInner {
+        nonce,
+        temp_auth_key_id: temp_key.id,
+        perm_auth_key_id: perm_key.id,
+        temp_session_id: session.session_id(),
+        expires_at,
+    }.to_bytes()?;
+
+    // salt 和 session_id 使用随机值, msg_id 与外层请求相同, seq_no 为 0
+    let mut data = ByteBuffer::with_capacity(32 + inner.len());
+    data.put_i64(random());
+    data.put_i64(random());
+    data.put_i64(msg_id);
+    data.put_i32(0);
+    data.put_i32(inner.len() as i32);
+    data.put_all(&inner);
+
+    Ok(proto::BindTempAuthKey {
+        perm_auth_key_id: perm_key.id,
+        nonce,
+        expires_at,
+        encrypted_message: encrypt_v1(perm_key, &data.to_bytes()),
+    })
+}
+
+/// 由 `new_nonce` 和 `server_nonce` 派生加密 `server_DH_inner_data` 和 `client_DH_inner_data` 的密钥
+fn tmp_aes_key_iv(server_nonce: &[u8; 16], new_nonce: &[u8; 32]) -> (Zeroizing<[u8; 32]>, Zeroizing<[u8; 32]>) {
+    let new_server = sha1!(new_nonce, server_nonce);
+    let server_new = sha1!(server_nonce, new_nonce);
+    let new_new = sha1!(new_nonce, new_nonce);
+
+    let mut key = Zeroizing::new([0; 32]);
+    key[..20].copy_from_slice(&new_server);
+    key[20..].copy_from_slice(&server_new[..12]);
+
+    let mut iv = Zeroizing::new([0; 32]);
+    iv[..8].copy_from_slice(&server_new[12..]);
+    iv[8..28].copy_from_slice(&new_new);
+    iv[28..].copy_from_slice(&new_nonce[..4]);
+    (key, iv)
+}
+
+/// `g_a` 和 `g_b` 需要在 `(2^{2048-64}, dh_prime - 2^{2048-64})` 之间
+fn check_dh_value(value: &BigUint, dh_prime: &BigUint) -> Result<()> {
+    let min = BigUint::from(1u32) << (2048 - 64);
+    if *value <= min || *value >= dh_prime - &min {
+        bail!(Error::InvalidDHParams);
+    }
+    Ok(())
 }
 
 fn check_nonce(got: &[u8; 16], expected: &[u8; 16]) -> Result<()> {
@@ -171,30 +359,250 @@ fn check_nonce(got: &[u8; 16], expected: &[u8; 16]) -> Result<()> {
     }
 }
 
-fn pub_key_for_fingerprint(fingerprint: i64) -> Option<RsaPublicKey> {
-    match fingerprint as u64 {
-        // 正式服
-        0xd09d1d85de64fd85 => RsaPublicKey::from_pkcs1_pem(
-            "-----BEGIN RSA PUBLIC KEY-----\n\
-            MIIBCgKCAQEA6LszBcC1LGzyr992NzE0ieY+BSaOW622Aa9Bd4ZHLl+TuFQ4lo4g\n\
-            5nKaMBwK/BIb9xUfg0Q29/2mgIR6Zr9krM7HjuIcCzFvDtr+L0GQjae9H0pRB2OO\n\
-            62cECs5HKhT5DZ98K33vmWiLowc621dQuwKWSQKjWf50XYFw42h21P2KXUGyp2y/\n\
-            +aEyZ+uVgLLQbRA1dEjSDZ2iGRy12Mk5gpYc397aYp438fsJoHIgJ2lgMv5h7WY9\n\
-            t6N/byY9Nw9p21Og3AoXSL2q/2IJ1WRUhebgAdGVMlV1fkuOQoEzR7EdpqtQD9Cs\n\
-            5+bfo3Nhmcyvk5ftB0WkJ9z6bNZ7yxrP8wIDAQAB\n\
-            -----END RSA PUBLIC KEY-----"
-        ).ok(),
-        // 测试服
-        0xb25898df208d2603 => RsaPublicKey::from_pkcs1_pem(
-            "-----BEGIN RSA PUBLIC KEY-----\n\
-            MIIBCgKCAQEAyMEdY1aR+sCR3ZSJrtztKTKqigvO/vBfqACJLZtS7QMgCGXJ6XIR\n\
-            yy7mx66W0/sOFa7/1mAZtEoIokDP3ShoqF4fVNb6XeqgQfaUHd8wJpDWHcR2OFwv\n\
-            plUUI1PLTktZ9uW2WE23b+ixNwJjJGwBDJPQEQFBE+vfmH0JP503wr5INS1poWg/\n\
-            j25sIWeYPHYeOrFp/eXaqhISP6G+q2IeTaWTXpwZj4LzXq5YOpk4bYEQ6mvRq7D1\n\
-            aHWfYmlEGepfaYR8Q0YqvvhYtMte3ITnuSJs171+GDqpdKcSwHnd6FudwGO4pcCO\n\
-            j4WcDuXc2CTHgH8gFTNhp/Y8/SpDOhvn9QIDAQAB\n\
-            -----END RSA PUBLIC KEY-----"
-        ).ok(),
-        _ => None
-    }
-}
\ No newline at end of file
+/// 服务器一侧的握手, 用于测试. pq 为 `0x17ED48941A08F981`, 使用 Telegram 的 2048 位 dh_prime 和 g = 3
+#[cfg(test)]
+pub(crate) struct TestServer {
+    private_key: rsa::RsaPrivateKey,
+    nonce: [u8; 16],
+    server_nonce: [u8; 16],
+    new_nonce: [u8; 32],
+    a: BigUint,
+    /// 处理完 `set_client_DH_params` 后得到
+    pub(crate) auth_key: Option<AuthKey>,
+}
+
+#[cfg(test)]
+impl TestServer {
+    const DH_PRIME: &'static str = "C71CAEB9C6B1C9048E6C522F70F13F73980D40238E3E21C14934D037563D930F48198A0AA7C14058229493D22530F4DBFA336F6E0AC925139543AED44CCE7C3720FD51F69458705AC68CD4FE6B6B13ABDC9746512969328454F18FAF8C595F642477FE96BB2A941D5BCD1D4AC8CC49880708FA9B378E3C4F3A9060BEE67CF9A4A4A695811051907E162753B56B0F6B410DBA74D8A84B2A14B3144E0EF1284754FD17ED950D5965B4B9DD46582DB1178D169C6BC465B0D6FF9CA3928FEF5B9AE4E418FC15E83EBEA0F87FA9FF5EED70050DED2849F47BF959D956850CE929851F0D8115F635B105EE2E4E15D04B2454BF6F4FADF034B10403119CD8E3B92FCC5B";
+    /// 服务器时间比本机快 100 秒
+    pub(crate) const TIME_DIFF: i32 = 100;
+
+    pub(crate) fn new() -> Self {
+        let mut a = [0; 256];
+        thread_rng().fill_bytes(&mut a);
+        Self {
+            private_key: rsa_keys::test_private_key(),
+            nonce: [0; 16],
+            server_nonce: random(),
+            new_nonce: [0; 32],
+            a: BigUint::from_bytes_be(&a),
+            auth_key: None,
+        }
+    }
+
+    pub(crate) fn rsa_keys(&self) -> RsaKeys {
+        RsaKeys::new().key(self.private_key.to_public_key())
+    }
+
+    /// `req_pq_multi` -> `resPQ`
+    pub(crate) fn res_pq(&mut self, req: &[u8]) -> Vec<u8> {
+        self.nonce = req[4..20].try_into().unwrap();
+        let mut buf = ByteBuffer::new();
+        buf.put_u32(0x05162463);
+        buf.put_all(&self.nonce);
+        buf.put_all(&self.server_nonce);
+        put_tl_bytes(&mut buf, &0x17ED48941A08F981_u64.to_be_bytes());
+        buf.put_u32(0x1cb5c415);
+        buf.put_i32(1);
+        buf.put_i64(self.rsa_keys().fingerprints().next().unwrap());
+        buf.to_bytes().to_vec()
+    }
+
+    /// `req_DH_params` -> `server_DH_params_ok`
+    pub(crate) fn server_dh_params(&mut self, req: &[u8]) -> Vec<u8> {
+        // p 和 q 为 TL string
+        assert_eq!(req[36..44], [4, 0x49, 0x4C, 0x55, 0x3B, 0, 0, 0]);
+        assert_eq!(req[44..52], [4, 0x53, 0x91, 0x10, 0x73, 0, 0, 0]);
+        let data = rsa_keys::decrypt_padded(&self.private_key, tl_bytes(&req[60..]));
+        self.new_nonce = data[64..96].try_into().unwrap();
+
+        let dh_prime = BigUint::parse_bytes(Self::DH_PRIME.as_bytes(), 16).unwrap();
+        let answer = proto::ServerDHInnerData {
+            nonce: self.nonce,
+            server_nonce: self.server_nonce,
+            g: 3,
+            dh_prime: dh_prime.to_bytes_be(),
+            g_a: BigUint::from(3u32).modpow(&self.a, &dh_prime).to_bytes_be(),
+            server_time: TimeSync::local_seconds() + Self::TIME_DIFF,
+        }.to_bytes().unwrap();
+        let mut answer_with_hash = sha1!(&answer).to_vec();
+        answer_with_hash.extend_from_slice(&answer);
+        answer_with_hash.resize(answer_with_hash.len().div_ceil(16) * 16, 0);
+        let (key, iv) = tmp_aes_key_iv(&self.server_nonce, &self.new_nonce);
+
+        let mut buf = ByteBuffer::new();
+        buf.put_u32(0xd0e8075c);
+        buf.put_all(&self.nonce);
+        buf.put_all(&self.server_nonce);
+        put_tl_bytes(&mut buf, &aes_ige_encrypt(&answer_with_hash, &key, &iv));
+        buf.to_bytes().to_vec()
+    }
+
+    /// `set_client_DH_params` -> `dh_gen_ok`
+    pub(crate) fn dh_gen(&mut self, req: &[u8]) -> Vec<u8> {
+        let (key, iv) = tmp_aes_key_iv(&self.server_nonce, &self.new_nonce);
+        let data = aes_ige_decrypt(tl_bytes(&req[36..]), &key, &iv);
+        // sha1 + crc + nonce + server_nonce + retry_id + g_b
+        assert_eq!(data[24..40], self.nonce);
+        let g_b = BigUint::from_bytes_be(tl_bytes(&data[64..]));
+        let dh_prime = BigUint::parse_bytes(Self::DH_PRIME.as_bytes(), 16).unwrap();
+        let gab = g_b.modpow(&self.a, &dh_prime).to_bytes_be();
+        let mut auth_key = [0; 256];
+        auth_key[256 - gab.len()..].copy_from_slice(&gab);
+        let auth_key = AuthKey::from_bytes(auth_key);
+
+        let mut buf = ByteBuffer::new();
+        buf.put_u32(0x3bcbf734);
+        buf.put_all(&self.nonce);
+        buf.put_all(&self.server_nonce);
+        buf.put_all(&sha1!(&self.new_nonce, [1], &sha1!(auth_key.as_bytes())[..8])[4..20]);
+        self.auth_key = Some(auth_key);
+        buf.to_bytes().to_vec()
+    }
+}
+
+#[cfg(test)]
+fn tl_bytes(data: &[u8]) -> &[u8] {
+    match data[0] {
+        254 => &data[4..4 + u32::from_le_bytes([data[1], data[2], data[3], 0]) as usize],
+        len => &data[1..1 + len as usize],
+    }
+}
+
+#[cfg(test)]
+fn put_tl_bytes(buf: &mut ByteBuffer, data: &[u8]) {
+    let header = if data.len() < 254 {
+        buf.put_u8(data.len() as u8);
+        1
+    } else {
+        buf.put_u8(254);
+        buf.put_uint(data.len() as u64, 3);
+        4
+    };
+    buf.put_all(data);
+    buf.put_all(&vec![0; (4 - (header + data.len()) % 4) % 4]);
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Buf;
+
+    use crate::proto::msg::decrypt_v1;
+    use crate::sha1;
+
+    use super::*;
+
+    #[test]
+    fn step2_with_custom_key() {
+        let private = crate::net::rsa_keys::test_private_key();
+        let keys = RsaKeys::new().key(private.to_public_key());
+        let fingerprint = keys.fingerprints().next().unwrap();
+
+        let (req, step) = step1(2, HandshakeType::Perm).unwrap();
+        let res = proto::ResPQ {
+            nonce: req.nonce,
+            server_nonce: [3; 16],
+            pq: 0x17ED48941A08F981_u64.to_be_bytes().to_vec(),
+            server_public_key_fingerprints: vec![1, fingerprint],
+        };
+        let (req, step) = step2(step, res, &keys).unwrap();
+        assert_eq!((req.p, req.q), (0x494C553B_u32.to_be_bytes(), 0x53911073_u32.to_be_bytes()));
+        assert_eq!(req.public_key_fingerprint, fingerprint);
+
+        // 服务器用私钥解密得到 p_q_inner_data_dc
+        let data = crate::net::rsa_keys::decrypt_padded(&private, &req.encrypted_data);
+        let inner = PQInnerData::Dc {
+            pq: 0x17ED48941A08F981_u64.to_be_bytes().to_vec(),
+            p: req.p,
+            q: req.q,
+            nonce: req.nonce,
+            server_nonce: [3; 16],
+            new_nonce: *step.new_nonce,
+            dc: 2,
+        }.to_bytes().unwrap();
+        assert_eq!(data[..inner.len()], inner[..]);
+
+        // 没有信任的公钥
+        let (req, step) = step1(2, HandshakeType::Perm).unwrap();
+        let res = proto::ResPQ {
+            nonce: req.nonce,
+            server_nonce: [3; 16],
+            pq: 0x17ED48941A08F981_u64.to_be_bytes().to_vec(),
+            server_public_key_fingerprints: vec![1],
+        };
+        let e = step2(step, res, &keys).unwrap_err();
+        assert_eq!(e.downcast::<Error>().unwrap(), Error::UnknownFingerprints(vec![1]));
+    }
+
+    #[test]
+    fn handshake_with_test_server() {
+        let mut server = TestServer::new();
+        let (req, step) = step1(2, HandshakeType::Temp).unwrap();
+        let res = proto::handshake::parse_res_pq(&server.res_pq(&req.to_bytes().unwrap())).unwrap();
+        let (req, step) = step2(step, res, &server.rsa_keys()).unwrap();
+        let res = proto::handshake::parse_server_dh_params(&server.server_dh_params(&req.to_bytes().unwrap())).unwrap();
+        let (req, step) = step3(step, res).unwrap();
+        let new_nonce = *step.new_nonce;
+        let res = server.dh_gen(&req.to_bytes().unwrap());
+        let completion = complete(step, proto::handshake::parse_set_client_dh_params_answer(&res).unwrap()).unwrap();
+
+        assert_eq!(Some(completion.auth_key), server.auth_key);
+        assert!((TestServer::TIME_DIFF - 1..=TestServer::TIME_DIFF + 1).contains(&completion.time_diff));
+        let salt: [u8; 8] = std::array::from_fn(|i| new_nonce[i] ^ server.server_nonce[i]);
+        assert_eq!(completion.first_salt, i64::from_le_bytes(salt));
+    }
+
+    #[test]
+    fn reject_invalid_dh_answer() {
+        let handshake = |tamper_answer: fn(&mut Vec<u8>), tamper_gen: fn(&mut Vec<u8>)| {
+            let mut server = TestServer::new();
+            let (req, step) = step1(2, HandshakeType::Perm).unwrap();
+            let res = proto::handshake::parse_res_pq(&server.res_pq(&req.to_bytes().unwrap())).unwrap();
+            let (req, step) = step2(step, res, &server.rsa_keys()).unwrap();
+            let mut res = server.server_dh_params(&req.to_bytes().unwrap());
+            tamper_answer(&mut res);
+            let (req, step) = step3(step, proto::handshake::parse_server_dh_params(&res).unwrap())?;
+            let mut res = server.dh_gen(&req.to_bytes().unwrap());
+            tamper_gen(&mut res);
+            complete(step, proto::handshake::parse_set_client_dh_params_answer(&res).unwrap())
+        };
+        let error = |e: anyhow::Error| e.downcast::<Error>().unwrap();
+        assert!(handshake(|_| {}, |_| {}).is_ok());
+        // encrypted_answer 的最后一块被篡改, sha1 不匹配
+        let e = handshake(|res| *res.last_mut().unwrap() ^= 1, |_| {}).unwrap_err();
+        assert_eq!(error(e), Error::InvalidAnswer);
+        // new_nonce_hash1 错误
+        let e = handshake(|_| {}, |res| *res.last_mut().unwrap() ^= 1).unwrap_err();
+        assert_eq!(error(e), Error::InvalidNewNonceHash);
+    }
+
+    #[test]
+    fn bind_temp_key() {
+        let perm_key = AuthKey::from_bytes(std::array::from_fn(|i| i as u8));
+        let temp_key = AuthKey::from_bytes(std::array::from_fn(|i| (i * 7) as u8));
+        let session = Session::new();
+        let req = bind_temp_auth_key(&perm_key, &temp_key, &session, 0x5f00_0000_0000_0004, 1_700_086_400).unwrap();
+        assert_eq!(req.perm_auth_key_id, perm_key.id);
+        assert_eq!(req.expires_at, 1_700_086_400);
+
+        // 使用永久密钥按 MTProto 1.0 加密
+        let encrypted = &req.encrypted_message;
+        assert_eq!(encrypted[..8], perm_key.id.to_le_bytes());
+        let plain = decrypt_v1(&perm_key, encrypted);
+        let inner = proto::BindAuthKeyInner {
+            nonce: req.nonce,
+            temp_auth_key_id: temp_key.id,
+            perm_auth_key_id: perm_key.id,
+            temp_session_id: session.session_id(),
+            expires_at: 1_700_086_400,
+        }.to_bytes().unwrap();
+        let message = &plain[..32 + inner.len()];
+        assert_eq!(encrypted[8..24], sha1!(message)[4..20]);
+
+        let mut buf = &message[16..];
+        assert_eq!(buf.get_i64_le(), 0x5f00_0000_0000_0004);
+        assert_eq!(buf.get_i32_le(), 0);
+        assert_eq!(buf.get_i32_le(), inner.len() as i32);
+        assert_eq!(buf, &inner[..]);
+    }
+}
@@ -1,14 +1,27 @@
 pub use addr::{Addr, Addrs};
 pub use auth_key::AuthKey;
 pub(crate) use client::Client;
+pub use config::{AppInfo, ClientConfig, ConfigError, DcConfig, DcEntry, DEFAULT_DC_ID, Environment, KeepaliveConfig, PoolConfig, ProxyAuth, ProxyConfig, ReconnectConfig, SocketConfig, TlsConfig, TransportConfig, TransportOptions};
+pub(crate) use config::NetConfig;
+pub use connection::{ConnState, ConnStateEvent, ConnType};
 pub use data_center::DataCenter;
+pub use dc_store::{DcStore, DcTable, FileDcStore, MemoryDcStore};
+pub(crate) use dc_store::StorageDcStore;
+pub use ping::RttStats;
+pub use request::{RequestError, RequestOptions};
+pub use rsa_keys::RsaKeys;
 pub use session::Session;
+pub use socket::{ResolveFuture, Resolver, StaticResolver, SystemResolver};
+pub use update_stream::{Lagged, UpdateStream};
 
 mod addr;
 mod auth_key;
+mod backoff;
+mod config;
 mod error;
 pub(crate) mod event;
 mod data_center;
+mod dc_store;
 pub(crate) mod handshake;
 mod time_sync;
 mod session;
@@ -16,6 +29,10 @@ mod ping;
 mod client;
 mod socket;
 mod connection;
+pub(crate) mod dispatcher;
+mod request;
+mod rsa_keys;
+mod update_stream;
 
 // #[derive(Debug)]
 // pub struct NetworkMessage {
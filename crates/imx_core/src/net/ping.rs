@@ -1,39 +1,138 @@
-// todo last_ping_time 和 ping_time 是否只保留其一
-#[derive(Debug, Default, Copy, Clone)]
-pub struct Ping {
-    pub(crate) last_check_ping_time: i64, // 单位: 毫秒
-    pub(crate) last_ping_id: i64,
-    pub(crate) ping_time: i32, // 单位: 秒
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::net::{ConnType, KeepaliveConfig};
+
+/// 一个连接的往返时间统计, 由 keepalive 的 `ping_delay_disconnect` 测量
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct RttStats {
+    pub dc_id: i32,
+    pub conn_type: ConnType,
+    /// 平滑后的 RTT
+    pub srtt: Duration,
+    /// RTT 的平均偏差
+    pub jitter: Duration,
+    /// 最近一次测量的 RTT
+    pub last: Duration,
+    /// 连续未收到 pong 的次数
+    pub missed: u32,
+}
+
+/// 连接的 keepalive 状态, 按 [RFC 6298](https://www.rfc-editor.org/rfc/rfc6298) 计算 RTT
+#[derive(Debug)]
+pub(crate) struct Ping {
+    stats: RttStats,
+    keepalive: KeepaliveConfig,
+    /// 是否收到过 pong
+    measured: bool,
+    /// 后台模式使用更长的间隔
+    background: bool,
+    last_ping_id: i64,
+    /// 等待 pong 的 ping_id 和发送时间
+    pending: Option<(i64, Instant)>,
 }
 
 impl Ping {
-    pub fn new() -> Self { Default::default() }
+    pub fn new(dc_id: i32, conn_type: ConnType, keepalive: KeepaliveConfig) -> Self {
+        Self {
+            stats: RttStats { dc_id, conn_type, srtt: Duration::ZERO, jitter: Duration::ZERO, last: Duration::ZERO, missed: 0 },
+            keepalive,
+            measured: false,
+            background: false,
+            last_ping_id: 0,
+            pending: None,
+        }
+    }
+
+    pub fn set_background(&mut self, background: bool) {
+        self.background = background;
+    }
+
+    /// 发送 ping 的间隔
+    pub fn interval(&self) -> Duration {
+        if self.background { self.keepalive.background_interval } else { self.keepalive.interval }
+    }
+
+    /// 服务器在该时间内没有收到下一个 ping 时断开连接, 单位: 秒
+    pub fn disconnect_delay(&self) -> i32 {
+        let delay = if self.background { self.keepalive.background_disconnect_delay } else { self.keepalive.disconnect_delay };
+        delay.as_secs() as i32
+    }
+
+    /// 发送下一个 ping, 返回 ping_id. 上一个 ping 还没有收到 pong 时计为丢失
+    pub fn start(&mut self, now: Instant) -> i64 {
+        if self.pending.is_some() {
+            self.stats.missed += 1;
+        }
+        self.last_ping_id += 1;
+        self.pending = Some((self.last_ping_id, now));
+        self.last_ping_id
+    }
+
+    /// 收到 pong, 不是最近一次 ping 的 pong 时忽略
+    pub fn on_pong(&mut self, ping_id: i64, now: Instant) -> bool {
+        let Some((id, sent_at)) = self.pending else { return false; };
+        if id != ping_id { return false; }
+        self.pending = None;
+
+        let rtt = now.duration_since(sent_at);
+        let stats = &mut self.stats;
+        if self.measured {
+            let diff = if stats.srtt > rtt { stats.srtt - rtt } else { rtt - stats.srtt };
+            stats.jitter = (stats.jitter * 3 + diff) / 4;
+            stats.srtt = (stats.srtt * 7 + rtt) / 8;
+        } else {
+            stats.srtt = rtt;
+            stats.jitter = rtt / 2;
+            self.measured = true;
+        }
+        stats.last = rtt;
+        stats.missed = 0;
+        true
+    }
+
+    /// 连续 [KeepaliveConfig::max_missed] 次没有收到 pong, 认为连接已断开
+    pub fn is_dead(&self) -> bool {
+        self.stats.missed >= self.keepalive.max_missed
+    }
+
+    /// 还没有收到过 pong 时返回 `None`
+    pub fn stats(&self) -> Option<RttStats> {
+        self.measured.then_some(self.stats)
+    }
 }
 
-/*pub fn check_run(session: Session) -> Option<NetworkMessage> {
-    let mut state = session.get_ping_state();
-    let now = TimeSync::local_millis();
-    if now - state.last_ping_time < PING_DURATION {
-        return None;
-    }
-
-    state.last_ping_time = now;
-    state.last_ping_id += 1;
-    state.ping_time = TimeSync::local_seconds();
-    session.set_ping_state(state);
-
-    let mut req = PingDelayDisconnect::default();
-    req.ping_id = state.last_ping_id;
-    req.disconnect_delay = 35;
-
-    // let body = proto::to_bytes(&req).unwrap_or_default();
-    // let mut msg = Message::default();
-    //
-    // msg.msg_id = session.new_msg_id();
-    // msg.bytes = body.len() as i32;
-    // msg.body = Bytes::from(body);
-    // todo
-    // msg.seqno = conn.generate_msg_seq_no(false) as i32;
-
-    Some(NetworkMessage::new(msg))
-}*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rtt_and_missed_pongs() {
+        let start = Instant::now();
+        let ms = |n| start + Duration::from_millis(n);
+        let keepalive = KeepaliveConfig::default();
+        let mut ping = Ping::new(2, ConnType::Generic, keepalive);
+        assert_eq!(ping.stats(), None);
+
+        let id = ping.start(ms(0));
+        assert!(!ping.on_pong(id + 1, ms(50)));
+        assert!(ping.on_pong(id, ms(100)));
+        let stats = ping.stats().unwrap();
+        assert_eq!((stats.srtt, stats.jitter, stats.last), (Duration::from_millis(100), Duration::from_millis(50), Duration::from_millis(100)));
+
+        let id = ping.start(ms(1000));
+        assert!(ping.on_pong(id, ms(1200)));
+        let stats = ping.stats().unwrap();
+        assert_eq!((stats.srtt, stats.jitter), (Duration::from_micros(112500), Duration::from_micros(62500)));
+
+        // 迟到的 pong 不计入
+        let id = ping.start(ms(2000));
+        ping.start(ms(3000));
+        assert!(!ping.on_pong(id, ms(3100)));
+        assert!(!ping.is_dead());
+        ping.start(ms(4000));
+        assert!(ping.is_dead());
+        assert_eq!(ping.stats().unwrap().missed, keepalive.max_missed);
+    }
+}
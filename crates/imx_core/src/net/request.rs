@@ -0,0 +1,382 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use anyhow::Result;
+use bytes::Bytes;
+use log::debug;
+use thiserror::Error;
+
+use crate::net::ConnType;
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+/// 因 `bad_server_salt`, DataCenter 迁移等原因重发的最大次数, 断线重连后的重发不计入
+const MAX_RESENDS: u32 = 5;
+
+/// 单个请求的选项
+///
+/// # Examples
+/// ```rust
+/// use std::time::Duration;
+/// use imx_core::RequestOptions;
+///
+/// let options = RequestOptions::new().timeout(Duration::from_secs(5));
+/// ```
+#[derive(Debug, Clone)]
+pub struct RequestOptions {
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) conn_type: ConnType,
+    pub(crate) dc_id: Option<i32>,
+    /// 导入授权的请求, 不需要等待目标 DataCenter 完成授权
+    pub(crate) without_auth: bool,
+}
+
+impl Default for RequestOptions {
+    fn default() -> Self {
+        Self { timeout: None, conn_type: ConnType::Generic, dc_id: None, without_auth: false }
+    }
+}
+
+impl RequestOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 请求超时时间, 不设置时使用 [ClientBuilder::request_timeout](crate::ClientBuilder::request_timeout)
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// 发送请求使用的连接类型, 默认为 [ConnType::Generic]. 上传下载文件时使用
+    /// [ConnType::Upload], [ConnType::Download] 可以避免阻塞其他请求
+    pub fn conn_type(mut self, conn_type: ConnType) -> Self {
+        self.conn_type = conn_type;
+        self
+    }
+
+    /// 发送到指定的 DataCenter, 默认发送到 home DC
+    pub fn dc_id(mut self, dc_id: i32) -> Self {
+        self.dc_id = Some(dc_id);
+        self
+    }
+}
+
+/// 请求失败的原因
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum RequestError {
+    /// 超时前没有收到响应
+    #[error("request timed out")]
+    Timeout,
+    /// 服务器返回的 `rpc_error`
+    #[error("rpc error {code}: {message}")]
+    Rpc { code: i32, message: String },
+    /// 服务器拒绝了消息, 见 [bad_msg_notification](https://core.telegram.org/mtproto/service_messages_about_messages#notice-of-ignored-error-message)
+    #[error("bad msg notification: {0}")]
+    BadMsg(i32),
+    /// 重发次数过多
+    #[error("request resent too many times")]
+    TooManyResends,
+    /// 没有该 DataCenter 的地址
+    #[error("unknown dc {0}")]
+    UnknownDc(i32),
+    /// 客户端已停止
+    #[error("client stopped")]
+    Stopped,
+}
+
+/// 请求发送到的连接
+#[derive(Debug, Clone, Copy)]
+struct Sent {
+    dc_id: i32,
+    msg_id: i64,
+    token: u32,
+}
+
+/// 等待发送或等待响应的请求
+struct Request {
+    body: Bytes,
+    options: RequestOptions,
+    deadline: Instant,
+    tx: oneshot::Sender<Result<Bytes>>,
+    /// 重发时使用新的 msg_id
+    sent: Option<Sent>,
+    resends: u32,
+}
+
+/// 等待发送的请求
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Pending {
+    pub id: u64,
+    pub dc_id: i32,
+    pub conn_type: ConnType,
+    pub without_auth: bool,
+    pub body: Bytes,
+}
+
+/// 请求队列, 请求按加入的顺序发送, 收到响应, 超时或调用方放弃等待后移出队列
+#[derive(Default)]
+pub(crate) struct RequestQueue {
+    next_id: u64,
+    requests: BTreeMap<u64, Request>,
+    /// msg_id -> request id
+    msg_ids: HashMap<i64, u64>,
+}
+
+impl RequestQueue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 加入队列, 返回请求 id. [timeout] 为请求没有单独设置超时时间时使用的默认值
+    pub fn push(&mut self, body: Bytes, options: RequestOptions, timeout: Duration, tx: oneshot::Sender<Result<Bytes>>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        let deadline = Instant::now() + options.timeout.unwrap_or(timeout);
+        self.requests.insert(id, Request { body, options, deadline, tx, sent: None, resends: 0 });
+        id
+    }
+
+    /// 最早的超时时间
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.requests.values().map(|r| r.deadline).min()
+    }
+
+    /// 等待发送的请求, 包括在已断开的连接上发送过但还没有响应的请求.
+    /// 没有指定 DataCenter 的请求发送到 [home_dc], 调用方已放弃等待的请求直接移除, 不再发送
+    pub fn pending(&mut self, home_dc: i32, is_live: impl Fn(u32) -> bool) -> Vec<Pending> {
+        self.remove_cancelled();
+        let mut pending = vec![];
+        for (&id, req) in self.requests.iter_mut() {
+            match req.sent {
+                Some(sent) if is_live(sent.token) => continue,
+                Some(sent) => {
+                    debug!("Resend request {} (msg_id {}) after reconnect", id, sent.msg_id);
+                    self.msg_ids.remove(&sent.msg_id);
+                    req.sent = None;
+                }
+                None => {}
+            }
+            pending.push(Pending {
+                id,
+                dc_id: req.options.dc_id.unwrap_or(home_dc),
+                conn_type: req.options.conn_type,
+                without_auth: req.options.without_auth,
+                body: req.body.clone(),
+            });
+        }
+        pending
+    }
+
+    /// 请求已通过 [token] 对应的连接发送
+    pub fn sent(&mut self, id: u64, dc_id: i32, msg_id: i64, token: u32) {
+        if let Some(req) = self.requests.get_mut(&id) {
+            req.sent = Some(Sent { dc_id, msg_id, token });
+            self.msg_ids.insert(msg_id, id);
+        }
+    }
+
+    /// 是否有在 [token] 对应的连接上等待响应的请求
+    pub fn in_flight(&self, token: u32) -> bool {
+        self.requests.values().any(|r| matches!(r.sent, Some(sent) if sent.token == token))
+    }
+
+    /// [msg_id] 对应的请求发送到的 DataCenter
+    pub fn sent_dc(&self, msg_id: i64) -> Option<i32> {
+        let id = self.msg_ids.get(&msg_id)?;
+        self.requests.get(id)?.sent.map(|sent| sent.dc_id)
+    }
+
+    /// 收到响应
+    pub fn complete(&mut self, msg_id: i64, result: Result<Bytes>) {
+        let Some(id) = self.msg_ids.remove(&msg_id) else {
+            debug!("No request for msg_id {}", msg_id);
+            return;
+        };
+        if let Some(req) = self.requests.remove(&id) {
+            // 调用方已放弃等待
+            req.tx.send(result).ok();
+        }
+    }
+
+    /// 使用新的 msg_id 重发, 超过重发次数时请求失败
+    pub fn resend(&mut self, msg_id: i64) {
+        let Some(id) = self.msg_ids.remove(&msg_id) else { return; };
+        let Some(req) = self.requests.get_mut(&id) else { return; };
+        req.sent = None;
+        req.resends += 1;
+        if req.resends > MAX_RESENDS {
+            self.fail(id, RequestError::TooManyResends);
+        }
+    }
+
+    /// 重发到另一个 DataCenter, [dc_id] 为 `None` 时发送到 home DC
+    pub fn redirect(&mut self, msg_id: i64, dc_id: Option<i32>) {
+        if let Some(req) = self.msg_ids.get(&msg_id).and_then(|id| self.requests.get_mut(id)) {
+            req.options.dc_id = dc_id;
+        }
+        self.resend(msg_id);
+    }
+
+    /// 移除超时和调用方已放弃等待的请求
+    pub fn expire(&mut self, now: Instant) {
+        self.remove_cancelled();
+        let expired: Vec<_> = self.requests.iter()
+            .filter(|(_, r)| r.deadline <= now)
+            .map(|(&id, _)| id)
+            .collect();
+        for id in expired {
+            self.fail(id, RequestError::Timeout);
+        }
+    }
+
+    /// 所有请求失败
+    pub fn fail_all(&mut self, e: RequestError) {
+        let ids: Vec<_> = self.requests.keys().copied().collect();
+        for id in ids {
+            self.fail(id, e.clone());
+        }
+    }
+
+    /// 移除调用方已放弃等待 (丢弃了 receiver) 的请求, 之后收到的响应按未知 msg_id 忽略
+    fn remove_cancelled(&mut self) {
+        let msg_ids = &mut self.msg_ids;
+        self.requests.retain(|&id, req| {
+            if !req.tx.is_closed() { return true; }
+            debug!("Request {} cancelled", id);
+            if let Some(sent) = req.sent {
+                msg_ids.remove(&sent.msg_id);
+            }
+            false
+        });
+    }
+
+    /// 请求失败
+    pub fn fail(&mut self, id: u64, e: RequestError) {
+        if let Some(req) = self.requests.remove(&id) {
+            if let Some(sent) = req.sent {
+                self.msg_ids.remove(&sent.msg_id);
+            }
+            req.tx.send(Err(e.into())).ok();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn push(queue: &mut RequestQueue, body: &'static [u8]) -> (u64, oneshot::Receiver<Result<Bytes>>) {
+        let (tx, rx) = oneshot::channel();
+        (queue.push(Bytes::from_static(body), RequestOptions::new(), Duration::from_secs(10), tx), rx)
+    }
+
+    fn error(rx: &mut oneshot::Receiver<Result<Bytes>>) -> RequestError {
+        let e = rx.try_recv().unwrap().unwrap_err();
+        e.downcast::<RequestError>().unwrap()
+    }
+
+    fn targets(pending: Vec<Pending>) -> Vec<(u64, i32)> {
+        pending.into_iter().map(|p| (p.id, p.dc_id)).collect()
+    }
+
+    #[tokio::test]
+    async fn resend_after_reconnect() {
+        let mut queue = RequestQueue::new();
+        let (a, mut rx_a) = push(&mut queue, b"a");
+        let (b, _rx_b) = push(&mut queue, b"b");
+
+        assert_eq!(queue.pending(1, |t| t == 1).len(), 2);
+        queue.sent(a, 1, 100, 1);
+        queue.sent(b, 1, 104, 1);
+        assert!(queue.pending(1, |t| t == 1).is_empty());
+        assert!(queue.in_flight(1));
+
+        // 重连后未完成的请求需要重发
+        let pending = queue.pending(1, |t| t == 2);
+        assert_eq!(pending[0], Pending {
+            id: a,
+            dc_id: 1,
+            conn_type: ConnType::Generic,
+            without_auth: false,
+            body: Bytes::from_static(b"a"),
+        });
+        assert_eq!(targets(pending), vec![(a, 1), (b, 1)]);
+        queue.sent(a, 1, 108, 2);
+        // 旧的 msg_id 不再对应请求
+        queue.complete(100, Ok(Bytes::new()));
+        assert!(rx_a.try_recv().is_err());
+        queue.complete(108, Ok(Bytes::from_static(b"ok")));
+        assert_eq!(rx_a.try_recv().unwrap().unwrap(), Bytes::from_static(b"ok"));
+    }
+
+    #[tokio::test]
+    async fn redirect_to_dc() {
+        let mut queue = RequestQueue::new();
+        let (a, _rx_a) = push(&mut queue, b"a");
+        let (b, _rx_b) = push(&mut queue, b"b");
+        queue.pending(1, |_| true);
+        queue.sent(a, 1, 100, 1);
+        queue.sent(b, 1, 104, 1);
+        assert_eq!(queue.sent_dc(104), Some(1));
+
+        // FILE_MIGRATE_4 只重发当前请求, USER_MIGRATE_2 切换 home DC 后重发
+        queue.redirect(100, Some(4));
+        queue.redirect(104, None);
+        assert_eq!(targets(queue.pending(2, |_| true)), vec![(a, 4), (b, 2)]);
+    }
+
+    #[tokio::test]
+    async fn resend_limit() {
+        let mut queue = RequestQueue::new();
+        let (a, mut rx) = push(&mut queue, b"a");
+        for msg_id in 0..=MAX_RESENDS as i64 {
+            assert_eq!(queue.pending(1, |t| t == 1).len(), 1);
+            queue.sent(a, 1, msg_id, 1);
+            queue.resend(msg_id);
+        }
+        assert!(queue.next_deadline().is_none());
+        assert_eq!(error(&mut rx), RequestError::TooManyResends);
+    }
+
+    #[tokio::test]
+    async fn cancel_before_resend() {
+        let mut queue = RequestQueue::new();
+        let (a, rx_a) = push(&mut queue, b"a");
+        let (b, rx_b) = push(&mut queue, b"b");
+        let (c, _rx_c) = push(&mut queue, b"c");
+        queue.pending(1, |_| true);
+        queue.sent(a, 1, 100, 1);
+        queue.sent(b, 1, 104, 1);
+        queue.sent(c, 1, 108, 1);
+
+        // 放弃等待后, 重连或 bad_server_salt 都不会重发
+        drop(rx_a);
+        drop(rx_b);
+        queue.resend(104);
+        assert_eq!(targets(queue.pending(1, |t| t == 2)), vec![(c, 1)]);
+        assert_eq!(queue.sent_dc(100), None);
+        assert!(!queue.in_flight(1));
+        queue.sent(c, 1, 112, 2);
+        assert!(queue.pending(1, |t| t == 2).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn timeout_and_cancel() {
+        let mut queue = RequestQueue::new();
+        let (tx, mut rx) = oneshot::channel();
+        queue.push(Bytes::new(), RequestOptions::new().timeout(Duration::from_secs(1)), Duration::from_secs(10), tx);
+        let (_, dropped) = push(&mut queue, b"b");
+        drop(dropped);
+
+        assert_eq!(queue.next_deadline(), Some(Instant::now() + Duration::from_secs(1)));
+        // 调用方放弃等待的请求立即移除
+        queue.expire(Instant::now());
+        assert_eq!(queue.pending(1, |t| t == 1).len(), 1);
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        queue.expire(Instant::now());
+        assert!(queue.next_deadline().is_none());
+        assert_eq!(error(&mut rx), RequestError::Timeout);
+    }
+}
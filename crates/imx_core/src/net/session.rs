@@ -1,7 +1,6 @@
 use std::sync::Arc;
 use crossbeam::atomic::AtomicCell;
-use crate::net::AuthKey;
-use crate::net::ping::Ping;
+use rand::random;
 use crate::net::time_sync::TimeSync;
 
 pub fn msg_id_to_time(id: i64) -> i64 {
@@ -12,25 +11,46 @@ pub fn time_to_msg_id(time: i64) -> i64 {
     (time as f64 * 4294967296.0 / 1000.0) as i64
 }
 
-///
+/// MTProto 会话, 生成 msg_id 和 seq_no, 记录 server salt 和与服务器的时间差
 #[derive(Clone)]
 pub struct Session {
+    /// 随机生成, 同一个 Session 的连接断开重连后不变
+    id: i64,
     sync: TimeSync,
     last_out_msg_id: Arc<AtomicCell<i64>>,
-    ping: Arc<AtomicCell<Ping>>,
-    pub first_salt: i64,
+    /// 已发送的内容相关消息数
+    content_count: Arc<AtomicCell<i32>>,
+    salt: Arc<AtomicCell<i64>>,
 }
 
 impl Session {
     pub fn new() -> Self {
         Self {
+            id: random(),
             sync: TimeSync::new(),
             last_out_msg_id: Arc::new(AtomicCell::new(0)),
-            ping: Arc::new(AtomicCell::new(Ping::new())),
-            first_salt: 0,
+            content_count: Arc::new(AtomicCell::new(0)),
+            salt: Arc::new(AtomicCell::new(0)),
         }
     }
 
+    /// 恢复保存的会话, 继续使用原来的 session_id 和 server salt
+    pub fn restore(id: i64, server_salt: i64, time_diff: i32) -> Self {
+        let session = Self { id, ..Self::new() };
+        session.set_server_salt(server_salt);
+        session.set_time_diff(time_diff);
+        session
+    }
+
+    pub fn session_id(&self) -> i64 {
+        self.id
+    }
+
+    /// 服务器当前时间, 单位: 秒
+    pub fn server_time(&self) -> i32 {
+        (self.sync.now() / 1000) as i32
+    }
+
     /// 生成消息 id, [Message Identifier](https://core.telegram.org/mtproto/description#message-identifier-msg-id)
     pub fn new_msg_id(&self) -> i64 {
         let now = self.sync.now();
@@ -44,7 +64,17 @@ impl Session {
             }
             Some(id)
         }).unwrap();
-        return id;
+        id
+    }
+
+    /// [msg_seqno](https://core.telegram.org/mtproto/description#message-sequence-number-msg-seqno),
+    /// 内容相关的消息 (rpc 请求, ping 等需要确认的消息) 为奇数, 并且递增计数
+    pub fn next_seq_no(&self, content_related: bool) -> i32 {
+        if content_related {
+            self.content_count.fetch_add(1) * 2 + 1
+        } else {
+            self.content_count.load() * 2
+        }
     }
 
     /// 使用消息 id 进行时间同步
@@ -54,18 +84,23 @@ impl Session {
         self.set_time_diff(diff as i32);
     }
 
+    /// 与服务器的时间差, 单位: 秒
+    pub fn time_diff(&self) -> i32 {
+        self.sync.time_diff()
+    }
+
     /// 设置 time_diff, 单位: 秒
     pub fn set_time_diff(&self, diff: i32) {
         self.sync.update(diff);
     }
 
-    /// 获取 ping 状态
-    pub fn get_ping_state(&self) -> Ping {
-        self.ping.load()
+    /// 当前使用的 server salt
+    pub fn server_salt(&self) -> i64 {
+        self.salt.load()
     }
 
-    /// 设置 ping 状态
-    pub fn set_ping_state(&self, state: Ping) {
-        self.ping.store(state)
+    /// 握手完成或收到 `bad_server_salt` 后更新
+    pub fn set_server_salt(&self, salt: i64) {
+        self.salt.store(salt)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,110 @@
+use std::sync::Arc;
+
+use anyhow::Result;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{CryptoProvider, verify_tls12_signature, verify_tls13_signature};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sha2::{Digest, Sha256};
+
+use crate::net::TlsConfig;
+
+/// 根据 [TlsConfig] 生成 rustls 客户端配置
+pub(crate) fn client_config(tls: &TlsConfig) -> Result<ClientConfig> {
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+
+    let mut config = if !tls.pinned_certs.is_empty() || !tls.pinned_spki.is_empty() {
+        let verifier = PinnedCertVerifier {
+            certs: tls.pinned_certs.clone(),
+            spki: tls.pinned_spki.clone(),
+            provider,
+        };
+        builder.dangerous()
+            .with_custom_certificate_verifier(Arc::new(verifier))
+            .with_no_client_auth()
+    } else {
+        let mut roots = RootCertStore::empty();
+        if tls.root_certs.is_empty() {
+            roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        } else {
+            for cert in &tls.root_certs {
+                roots.add(CertificateDer::from(cert.clone()))?;
+            }
+        }
+        builder.with_root_certificates(roots).with_no_client_auth()
+    };
+    config.alpn_protocols = tls.alpn.clone();
+
+    Ok(config)
+}
+
+/// 握手使用的服务器名称, 优先使用 [TlsConfig::server_name], 否则取地址中的主机名
+pub(crate) fn server_name(tls: &TlsConfig, host_port: &str) -> String {
+    if let Some(name) = &tls.server_name {
+        return name.clone();
+    }
+    let host = match host_port.rfind(':') {
+        Some(i) if !host_port[i + 1..].contains(']') => &host_port[..i],
+        _ => host_port,
+    };
+    host.trim_start_matches('[').trim_end_matches(']').to_owned()
+}
+
+/// 证书中 SubjectPublicKeyInfo 的 SHA-256
+pub(crate) fn spki_sha256(cert: &CertificateDer<'_>) -> Result<[u8; 32], rustls::Error> {
+    let cert = webpki::EndEntityCert::try_from(cert)
+        .map_err(|_| rustls::Error::InvalidCertificate(CertificateError::BadEncoding))?;
+    Ok(Sha256::digest(cert.subject_public_key_info().as_ref()).into())
+}
+
+/// 只信任固定证书或固定公钥, 证书内容完全一致或公钥一致即视为可信. 替代 WebPKI 校验,
+/// 不检查证书链, 有效期和服务器名称, 握手签名仍然正常校验
+#[derive(Debug)]
+struct PinnedCertVerifier {
+    certs: Vec<Vec<u8>>,
+    spki: Vec<[u8; 32]>,
+    provider: Arc<CryptoProvider>,
+}
+
+impl ServerCertVerifier for PinnedCertVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if self.certs.iter().any(|c| c.as_slice() == end_entity.as_ref()) {
+            return Ok(ServerCertVerified::assertion());
+        }
+        if !self.spki.is_empty() && self.spki.contains(&spki_sha256(end_entity)?) {
+            return Ok(ServerCertVerified::assertion());
+        }
+        Err(rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure))
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls12_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        verify_tls13_signature(message, cert, dss, &self.provider.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.provider.signature_verification_algorithms.supported_schemes()
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail, Result};
+use log::debug;
+use tokio::task::JoinSet;
+use tokio::time::sleep;
+
+use crate::net::addr::split_host_port;
+use crate::net::socket::Error;
+
+/// [RFC 8305](https://www.rfc-editor.org/rfc/rfc8305#section-5) 推荐的连接尝试间隔
+const CONNECTION_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+pub type ResolveFuture<'a> = Pin<Box<dyn Future<Output = io::Result<Vec<IpAddr>>> + Send + 'a>>;
+
+/// 域名解析, 返回域名对应的所有 A/AAAA 记录
+pub trait Resolver: Send + Sync + 'static {
+    fn resolve<'a>(&'a self, host: &'a str) -> ResolveFuture<'a>;
+}
+
+/// 使用系统的域名解析
+#[derive(Debug, Clone, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve<'a>(&'a self, host: &'a str) -> ResolveFuture<'a> {
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((host, 0)).await?;
+            Ok(addrs.map(|addr| addr.ip()).collect())
+        })
+    }
+}
+
+/// 固定的域名映射, 不在映射中的域名解析失败
+///
+/// # Examples
+/// ```rust
+/// use imx_core::StaticResolver;
+///
+/// let resolver = StaticResolver::new()
+///     .insert("dc1.example.com", ["149.154.175.50".parse().unwrap()]);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct StaticResolver {
+    hosts: HashMap<String, Vec<IpAddr>>,
+}
+
+impl StaticResolver {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn insert<S, I>(mut self, host: S, ips: I) -> Self
+        where S: Into<String>, I: IntoIterator<Item = IpAddr> {
+        self.hosts.entry(host.into()).or_default().extend(ips);
+        self
+    }
+}
+
+impl Resolver for StaticResolver {
+    fn resolve<'a>(&'a self, host: &'a str) -> ResolveFuture<'a> {
+        Box::pin(async move {
+            match self.hosts.get(host) {
+                Some(ips) => Ok(ips.clone()),
+                None => Err(io::Error::new(io::ErrorKind::NotFound, "host not found")),
+            }
+        })
+    }
+}
+
+/// 解析 `host:port` 得到所有地址, host 为 ip 时不经过 [Resolver]
+pub(crate) async fn resolve(host_port: &str, resolver: &dyn Resolver) -> Result<Vec<SocketAddr>> {
+    let Some((host, port)) = split_host_port(host_port) else {
+        bail!(Error::Resolve { host: host_port.to_owned(), source: io::ErrorKind::InvalidInput.into() });
+    };
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(vec![SocketAddr::new(ip, port)]);
+    }
+
+    let ips = resolver.resolve(host).await
+        .map_err(|source| Error::Resolve { host: host.to_owned(), source })?;
+    if ips.is_empty() {
+        bail!(Error::Resolve { host: host.to_owned(), source: io::ErrorKind::NotFound.into() });
+    }
+    debug!("(dns) Resolved {}: {:?}", host, ips);
+    Ok(ips.into_iter().map(|ip| SocketAddr::new(ip, port)).collect())
+}
+
+/// [RFC 8305](https://www.rfc-editor.org/rfc/rfc8305) Happy Eyeballs
+///
+/// 按 IPv6, IPv4 交替排列地址, 依次发起连接, 每次间隔 [CONNECTION_ATTEMPT_DELAY],
+/// 上一次尝试失败时立即发起下一次, 返回最先成功的连接, 其余连接被取消
+pub(crate) async fn happy_eyeballs<T, F, Fut>(addrs: Vec<SocketAddr>, connect: F) -> Result<T>
+    where T: Send + 'static,
+          F: Fn(SocketAddr) -> Fut,
+          Fut: Future<Output = Result<T>> + Send + 'static {
+    let mut pending = interleave(addrs);
+    let mut attempts = JoinSet::new();
+    let mut last_err = None;
+
+    if let Some(addr) = pending.pop_front() {
+        attempts.spawn(connect(addr));
+    }
+    while !attempts.is_empty() {
+        tokio::select! {
+            Some(res) = attempts.join_next() => {
+                match res {
+                    Ok(Ok(conn)) => return Ok(conn),
+                    Ok(Err(e)) => last_err = Some(e),
+                    Err(e) => last_err = Some(e.into()),
+                }
+                if let Some(addr) = pending.pop_front() {
+                    attempts.spawn(connect(addr));
+                }
+            }
+            _ = sleep(CONNECTION_ATTEMPT_DELAY), if !pending.is_empty() => {
+                if let Some(addr) = pending.pop_front() {
+                    attempts.spawn(connect(addr));
+                }
+            }
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| anyhow!("no address to connect")))
+}
+
+/// IPv6 优先, 两种地址交替排列, 保持各自原有的顺序
+fn interleave(addrs: Vec<SocketAddr>) -> VecDeque<SocketAddr> {
+    let (mut v6, mut v4): (VecDeque<_>, VecDeque<_>) = addrs.into_iter().partition(|a| a.is_ipv6());
+    let mut out = VecDeque::with_capacity(v6.len() + v4.len());
+    loop {
+        match (v6.pop_front(), v4.pop_front()) {
+            (None, None) => break,
+            (a, b) => out.extend(a.into_iter().chain(b)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::time::{Instant, sleep};
+
+    use crate::net::socket::Error;
+
+    use super::*;
+
+    fn addrs(list: &[&str]) -> Vec<SocketAddr> {
+        list.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn interleave_ipv6_first() {
+        let sorted = interleave(addrs(&["1.1.1.1:1", "2.2.2.2:1", "[::1]:1", "[::2]:1", "[::3]:1"]));
+        assert_eq!(Vec::from(sorted), addrs(&["[::1]:1", "1.1.1.1:1", "[::2]:1", "2.2.2.2:1", "[::3]:1"]));
+    }
+
+    #[tokio::test]
+    async fn resolve_static() {
+        let resolver = StaticResolver::new()
+            .insert("dc1.example.com", ["::1".parse().unwrap(), "127.0.0.1".parse().unwrap()]);
+
+        let resolved = resolve("dc1.example.com:443", &resolver).await.unwrap();
+        assert_eq!(resolved, addrs(&["[::1]:443", "127.0.0.1:443"]));
+        // ip 不经过解析
+        let resolved = resolve("[::1]:80", &resolver).await.unwrap();
+        assert_eq!(resolved, addrs(&["[::1]:80"]));
+
+        let e = resolve("dc2.example.com:443", &resolver).await.unwrap_err();
+        assert!(matches!(e.downcast_ref::<Error>(), Some(Error::Resolve { host, .. }) if host == "dc2.example.com"));
+    }
+
+    #[tokio::test]
+    async fn fallback_when_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        // ::1 上没有监听, 连接被拒绝后立即尝试 127.0.0.1
+        let resolver = StaticResolver::new()
+            .insert("dc1.example.com", ["::1".parse().unwrap(), "127.0.0.1".parse().unwrap()]);
+
+        let addrs = resolve(&format!("dc1.example.com:{}", port), &resolver).await.unwrap();
+        let stream = happy_eyeballs(addrs, |addr| async move { Ok(TcpStream::connect(addr).await?) }).await.unwrap();
+        assert_eq!(stream.peer_addr().unwrap().port(), port);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn staggered_attempts() {
+        let start = Instant::now();
+        let addrs = addrs(&["[::1]:1", "127.0.0.1:1"]);
+        // IPv6 一直没有响应, 250ms 后发起的 IPv4 连接先完成
+        let winner = happy_eyeballs(addrs, |addr| async move {
+            if addr.is_ipv6() {
+                sleep(Duration::from_secs(10)).await;
+            }
+            Ok(addr)
+        }).await.unwrap();
+        assert!(winner.is_ipv4());
+        assert_eq!(start.elapsed(), CONNECTION_ATTEMPT_DELAY);
+    }
+
+    #[tokio::test]
+    async fn all_attempts_failed() {
+        let addrs = addrs(&["[::1]:1", "127.0.0.1:1"]);
+        let e = happy_eyeballs(addrs, |addr| async move {
+            Err::<(), _>(anyhow!("refused {}", addr))
+        }).await.unwrap_err();
+        assert_eq!(e.to_string(), "refused 127.0.0.1:1");
+    }
+}
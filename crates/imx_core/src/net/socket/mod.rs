@@ -1,27 +1,37 @@
 use std::io;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
 use thiserror::Error;
 
-use crate::net::Addr;
+use crate::net::{Addr, SocketConfig};
 use crate::net::event::EventReceiver;
 
+pub use dns::{ResolveFuture, Resolver, StaticResolver, SystemResolver};
+
+pub(crate) mod dns;
+
 cfg_net_tcp! {
     mod tcp;
-    pub(crate) type SocketImpl = tcp::TcpSocket;
+}
+cfg_net_stream! {
+    mod proxy;
+}
+cfg_net_tls! {
+    mod tls;
 }
 cfg_net_quic! {
     mod quic;
-    pub(crate) type SocketImpl = quic::QuicSocket;
+}
+cfg_net_rustls! {
+    mod certs;
 }
 cfg_net_ws! {
     mod ws;
-    pub(crate) type SocketImpl = ws::WebSocket;
 }
 
 pub trait Socket: Sized {
     /// 连接服务器
-    async fn connect(addr: Addr) -> Result<Self>;
+    async fn connect(addr: Addr, config: &SocketConfig) -> Result<Self>;
     /// 发送数据包
     async fn send(&self, data: &[u8]) -> Result<()>;
     /// 接收 [Event]
@@ -30,20 +40,172 @@ pub trait Socket: Sized {
     async fn close(&mut self);
 }
 
+/// 所有已启用的 [Socket] 实现, 连接时根据 [Addr::scheme] 选择:
+/// - `tcp://` 或没有前缀: tcp
+/// - `tls://`: 使用 TLS 加密的 tcp
+/// - `ws://`, `wss://`: WebSocket
+/// - `quic://`: QUIC
+pub(crate) enum SocketImpl {
+    #[cfg(any(feature = "tcp", not(any(feature = "quic", feature = "ws"))))]
+    Tcp(tcp::TcpSocket),
+    #[cfg(feature = "tls")]
+    Tls(tls::TlsSocket),
+    #[cfg(feature = "quic")]
+    Quic(quic::QuicSocket),
+    #[cfg(feature = "ws")]
+    Ws(ws::WebSocket),
+}
+
+/// 是否有 [scheme] 协议的 [Socket] 实现
+pub(crate) fn is_supported(scheme: &str) -> bool {
+    match scheme {
+        #[cfg(any(feature = "tcp", not(any(feature = "quic", feature = "ws"))))]
+        "tcp" => true,
+        #[cfg(feature = "tls")]
+        "tls" => true,
+        #[cfg(feature = "quic")]
+        "quic" => true,
+        #[cfg(feature = "ws")]
+        "ws" | "wss" => true,
+        _ => false,
+    }
+}
+
+impl Socket for SocketImpl {
+    async fn connect(addr: Addr, config: &SocketConfig) -> Result<Self> {
+        match addr.scheme() {
+            #[cfg(any(feature = "tcp", not(any(feature = "quic", feature = "ws"))))]
+            "tcp" => Ok(Self::Tcp(tcp::TcpSocket::connect(addr, config).await?)),
+            #[cfg(feature = "tls")]
+            "tls" => Ok(Self::Tls(tls::TlsSocket::connect(addr, config).await?)),
+            #[cfg(feature = "quic")]
+            "quic" => Ok(Self::Quic(quic::QuicSocket::connect(addr, config).await?)),
+            #[cfg(feature = "ws")]
+            "ws" | "wss" => Ok(Self::Ws(ws::WebSocket::connect(addr, config).await?)),
+            scheme => bail!(Error::UnsupportedScheme(scheme.to_owned())),
+        }
+    }
+
+    async fn send(&self, data: &[u8]) -> Result<()> {
+        match self {
+            #[cfg(any(feature = "tcp", not(any(feature = "quic", feature = "ws"))))]
+            Self::Tcp(s) => s.send(data).await,
+            #[cfg(feature = "tls")]
+            Self::Tls(s) => s.send(data).await,
+            #[cfg(feature = "quic")]
+            Self::Quic(s) => s.send(data).await,
+            #[cfg(feature = "ws")]
+            Self::Ws(s) => s.send(data).await,
+        }
+    }
+
+    fn receiver(&self) -> EventReceiver {
+        match self {
+            #[cfg(any(feature = "tcp", not(any(feature = "quic", feature = "ws"))))]
+            Self::Tcp(s) => s.receiver(),
+            #[cfg(feature = "tls")]
+            Self::Tls(s) => s.receiver(),
+            #[cfg(feature = "quic")]
+            Self::Quic(s) => s.receiver(),
+            #[cfg(feature = "ws")]
+            Self::Ws(s) => s.receiver(),
+        }
+    }
+
+    async fn close(&mut self) {
+        match self {
+            #[cfg(any(feature = "tcp", not(any(feature = "quic", feature = "ws"))))]
+            Self::Tcp(s) => s.close().await,
+            #[cfg(feature = "tls")]
+            Self::Tls(s) => s.close().await,
+            #[cfg(feature = "quic")]
+            Self::Quic(s) => s.close().await,
+            #[cfg(feature = "ws")]
+            Self::Ws(s) => s.close().await,
+        }
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("EOF")]
-    EOF,
+    Eof,
 
     /// 收到该错误, 表示连接已中断, 对应消息通道已关闭
     #[error("intercepted")]
     Intercepted,
 
+    /// 地址前缀对应的连接协议不支持, 或未启用对应的 feature
+    #[error("unsupported scheme: {0}")]
+    UnsupportedScheme(String),
+
+    /// 发送数据失败
+    #[error("send: {0}")]
+    Send(anyhow::Error),
+
+    /// 对端关闭连接, 附带关闭码
+    #[error("closed with code {code}: {reason}")]
+    Closed { code: u64, reason: String },
+
     #[error("{0}")]
     Io(#[from] io::Error),
 
-    #[cfg(all(feature = "quic", not(feature = "tcp")))]
+    /// 域名解析失败
+    #[error("resolve {host}: {source}")]
+    Resolve { host: String, source: io::Error },
+
+    /// 通过代理建立连接失败
+    #[error("proxy: {0}")]
+    Proxy(ProxyError),
+
+    /// TLS 握手失败, 例如证书校验不通过
+    #[cfg(feature = "tls")]
+    #[error("tls handshake failed: {0}")]
+    Handshake(rustls::Error),
+
+    #[cfg(feature = "quic")]
     #[error("{0}")]
-    Quic(#[from] quinn::recv_stream::ReadError),
+    Quic(#[from] quinn::ReadError),
+
+    #[cfg(feature = "ws")]
+    #[error("{0}")]
+    Ws(Box<tokio_tungstenite::tungstenite::Error>),
+
+}
+
+#[cfg(feature = "ws")]
+impl From<tokio_tungstenite::tungstenite::Error> for Error {
+    fn from(value: tokio_tungstenite::tungstenite::Error) -> Self {
+        Self::Ws(Box::new(value))
+    }
+}
+
+#[derive(Error, Debug, PartialEq)]
+pub enum ProxyError {
+    /// 目标地址无法通过代理连接
+    #[error("invalid target address: {0}")]
+    InvalidTarget(String),
+
+    /// 代理服务器不接受客户端提供的认证方式
+    #[error("no acceptable authentication method")]
+    NoAcceptableAuth,
+
+    /// 用户名或密码错误
+    #[error("authentication failed")]
+    AuthFailed,
+
+    /// SOCKS5 代理返回的错误码
+    #[error("socks5 reply {0:#04x}")]
+    Socks5(u8),
+
+    /// HTTP 代理返回的状态码
+    #[error("http status {0}")]
+    Http(u16),
+
+    #[error("invalid response")]
+    InvalidResponse,
 
+    /// 该连接协议不支持代理
+    #[error("unsupported by {0}")]
+    Unsupported(&'static str),
 }
@@ -0,0 +1,319 @@
+use std::net::IpAddr;
+
+use anyhow::{bail, Result};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use log::info;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+use crate::net::addr::split_host_port;
+use crate::net::{ProxyAuth, ProxyConfig, SocketConfig};
+use crate::net::socket::{dns, Error, ProxyError};
+
+const SOCKS5_VERSION: u8 = 0x05;
+const SOCKS5_AUTH_NONE: u8 = 0x00;
+const SOCKS5_AUTH_PASSWORD: u8 = 0x02;
+const SOCKS5_AUTH_UNACCEPTABLE: u8 = 0xFF;
+const SOCKS5_CMD_CONNECT: u8 = 0x01;
+const SOCKS5_ATYP_IPV4: u8 = 0x01;
+const SOCKS5_ATYP_DOMAIN: u8 = 0x03;
+const SOCKS5_ATYP_IPV6: u8 = 0x04;
+
+/// 建立到 `host:port` 的 tcp 连接, 配置了代理时先连接代理服务器, 再通过代理建立隧道
+pub(crate) async fn connect_tcp(host_port: &str, config: &SocketConfig) -> Result<TcpStream> {
+    let Some(proxy) = &config.proxy else {
+        return dial(host_port, config).await;
+    };
+    let Some((host, port)) = split_host_port(host_port) else {
+        bail!(Error::Proxy(ProxyError::InvalidTarget(host_port.to_owned())));
+    };
+
+    info!("(proxy) Connecting {} via {:?} ...", host_port, proxy.addr());
+    let mut stream = dial(proxy.addr(), config).await?;
+    match proxy {
+        ProxyConfig::Socks5 { auth, .. } => socks5(&mut stream, host, port, auth.as_ref()).await?,
+        ProxyConfig::Http { auth, .. } => http_connect(&mut stream, host, port, auth.as_ref()).await?,
+    }
+    info!("(proxy) Tunnel to {} established", host_port);
+
+    Ok(stream)
+}
+
+/// 解析域名后使用 happy eyeballs 连接
+async fn dial(host_port: &str, config: &SocketConfig) -> Result<TcpStream> {
+    let addrs = dns::resolve(host_port, config.resolver.as_ref()).await?;
+    dns::happy_eyeballs(addrs, |addr| async move { Ok(TcpStream::connect(addr).await?) }).await
+}
+
+/// [RFC 1928](https://www.rfc-editor.org/rfc/rfc1928), 用户名/密码认证见 [RFC 1929](https://www.rfc-editor.org/rfc/rfc1929)
+async fn socks5(stream: &mut TcpStream, host: &str, port: u16, auth: Option<&ProxyAuth>) -> Result<()> {
+    // 协商认证方式
+    let method = if auth.is_some() { SOCKS5_AUTH_PASSWORD } else { SOCKS5_AUTH_NONE };
+    stream.write_all(&[SOCKS5_VERSION, 1, method]).await?;
+    let mut buf = [0u8; 2];
+    stream.read_exact(&mut buf).await?;
+    if buf[0] != SOCKS5_VERSION {
+        bail!(Error::Proxy(ProxyError::InvalidResponse));
+    }
+    match (buf[1], auth) {
+        (SOCKS5_AUTH_NONE, _) => {}
+        (SOCKS5_AUTH_PASSWORD, Some(auth)) => {
+            let username = auth.username.as_bytes();
+            let password = auth.password.as_bytes();
+            if username.len() > 255 || password.len() > 255 {
+                bail!(Error::Proxy(ProxyError::AuthFailed));
+            }
+            let mut req = Vec::with_capacity(3 + username.len() + password.len());
+            req.push(0x01);
+            req.push(username.len() as u8);
+            req.extend_from_slice(username);
+            req.push(password.len() as u8);
+            req.extend_from_slice(password);
+            stream.write_all(&req).await?;
+
+            stream.read_exact(&mut buf).await?;
+            if buf[1] != 0x00 {
+                bail!(Error::Proxy(ProxyError::AuthFailed));
+            }
+        }
+        (SOCKS5_AUTH_UNACCEPTABLE, _) => bail!(Error::Proxy(ProxyError::NoAcceptableAuth)),
+        _ => bail!(Error::Proxy(ProxyError::InvalidResponse)),
+    }
+
+    // 请求建立连接, 域名交给代理服务器解析
+    let mut req = vec![SOCKS5_VERSION, SOCKS5_CMD_CONNECT, 0x00];
+    match host.parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            req.push(SOCKS5_ATYP_IPV4);
+            req.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            req.push(SOCKS5_ATYP_IPV6);
+            req.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            if host.len() > 255 {
+                bail!(Error::Proxy(ProxyError::InvalidTarget(host.to_owned())));
+            }
+            req.push(SOCKS5_ATYP_DOMAIN);
+            req.push(host.len() as u8);
+            req.extend_from_slice(host.as_bytes());
+        }
+    }
+    req.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&req).await?;
+
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head).await?;
+    if head[0] != SOCKS5_VERSION {
+        bail!(Error::Proxy(ProxyError::InvalidResponse));
+    }
+    if head[1] != 0x00 {
+        bail!(Error::Proxy(ProxyError::Socks5(head[1])));
+    }
+    // 跳过代理服务器绑定的地址和端口
+    let len = match head[3] {
+        SOCKS5_ATYP_IPV4 => 4,
+        SOCKS5_ATYP_IPV6 => 16,
+        SOCKS5_ATYP_DOMAIN => stream.read_u8().await? as usize,
+        _ => bail!(Error::Proxy(ProxyError::InvalidResponse)),
+    };
+    let mut bound = vec![0u8; len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(())
+}
+
+/// HTTP `CONNECT` 隧道
+async fn http_connect(stream: &mut TcpStream, host: &str, port: u16, auth: Option<&ProxyAuth>) -> Result<()> {
+    let target = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(_)) => format!("[{}]:{}", host, port),
+        _ => format!("{}:{}", host, port),
+    };
+    let mut req = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if let Some(auth) = auth {
+        let credentials = STANDARD.encode(format!("{}:{}", auth.username, auth.password));
+        req.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+    }
+    req.push_str("\r\n");
+    stream.write_all(req.as_bytes()).await?;
+
+    // 逐字节读取响应头, 避免读到隧道中的数据
+    let mut head = Vec::with_capacity(128);
+    while !head.ends_with(b"\r\n\r\n") {
+        if head.len() >= 8192 {
+            bail!(Error::Proxy(ProxyError::InvalidResponse));
+        }
+        head.push(stream.read_u8().await?);
+    }
+
+    // HTTP/1.1 200 Connection established
+    let status = std::str::from_utf8(&head).ok()
+        .and_then(|s| s.split_whitespace().nth(1))
+        .and_then(|s| s.parse::<u16>().ok());
+    match status {
+        Some(200..=299) => Ok(()),
+        Some(status) => bail!(Error::Proxy(ProxyError::Http(status))),
+        None => bail!(Error::Proxy(ProxyError::InvalidResponse)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::task::JoinHandle;
+
+    use crate::net::{ProxyConfig, SocketConfig};
+    use crate::net::socket::{Error, ProxyError};
+
+    use super::connect_tcp;
+
+    /// 最简单的 SOCKS5 代理: 校验认证信息, 返回 [reply], 成功后原样返回隧道中的数据.
+    /// task 结果为客户端请求连接的目标地址
+    async fn socks5_stub(auth: Option<(&'static str, &'static str)>, reply: u8) -> (SocketAddr, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let task = tokio::spawn(async move {
+            let (mut s, _) = listener.accept().await.unwrap();
+            let mut head = [0u8; 2];
+            s.read_exact(&mut head).await.unwrap();
+            let mut methods = vec![0u8; head[1] as usize];
+            s.read_exact(&mut methods).await.unwrap();
+
+            if let Some((username, password)) = auth {
+                if !methods.contains(&0x02) {
+                    s.write_all(&[0x05, 0xFF]).await.unwrap();
+                    return String::new();
+                }
+                s.write_all(&[0x05, 0x02]).await.unwrap();
+                let mut buf = [0u8; 2];
+                s.read_exact(&mut buf).await.unwrap();
+                let mut user = vec![0u8; buf[1] as usize];
+                s.read_exact(&mut user).await.unwrap();
+                let mut pass = vec![0u8; s.read_u8().await.unwrap() as usize];
+                s.read_exact(&mut pass).await.unwrap();
+                if user != username.as_bytes() || pass != password.as_bytes() {
+                    s.write_all(&[0x01, 0x01]).await.unwrap();
+                    return String::new();
+                }
+                s.write_all(&[0x01, 0x00]).await.unwrap();
+            } else {
+                s.write_all(&[0x05, 0x00]).await.unwrap();
+            }
+
+            let mut req = [0u8; 4];
+            s.read_exact(&mut req).await.unwrap();
+            assert_eq!(&req[..3], &[0x05, 0x01, 0x00]);
+            let host = match req[3] {
+                0x01 => {
+                    let mut ip = [0u8; 4];
+                    s.read_exact(&mut ip).await.unwrap();
+                    Ipv4Addr::from(ip).to_string()
+                }
+                0x03 => {
+                    let mut name = vec![0u8; s.read_u8().await.unwrap() as usize];
+                    s.read_exact(&mut name).await.unwrap();
+                    String::from_utf8(name).unwrap()
+                }
+                atyp => panic!("unexpected atyp {}", atyp),
+            };
+            let port = s.read_u16().await.unwrap();
+            s.write_all(&[0x05, reply, 0x00, 0x01, 0, 0, 0, 0, 0, 0]).await.unwrap();
+
+            if reply == 0x00 {
+                let mut buf = [0u8; 64];
+                let n = s.read(&mut buf).await.unwrap();
+                s.write_all(&buf[..n]).await.unwrap();
+            }
+            format!("{}:{}", host, port)
+        });
+        (addr, task)
+    }
+
+    fn proxy_error(e: anyhow::Error) -> ProxyError {
+        match e.downcast::<Error>() {
+            Ok(Error::Proxy(e)) => e,
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn socks5_with_auth() {
+        let (addr, task) = socks5_stub(Some(("user", "secret")), 0x00).await;
+        let config = SocketConfig::new()
+            .proxy(ProxyConfig::socks5(addr.to_string()).auth("user", "secret"));
+
+        let mut stream = connect_tcp("dc1.example.com:443", &config).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+        assert_eq!(task.await.unwrap(), "dc1.example.com:443");
+    }
+
+    #[tokio::test]
+    async fn socks5_ip_target() {
+        let (addr, task) = socks5_stub(None, 0x00).await;
+        let config = SocketConfig::new().proxy(ProxyConfig::socks5(addr.to_string()));
+
+        let mut stream = connect_tcp("149.154.167.50:443", &config).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(task.await.unwrap(), "149.154.167.50:443");
+    }
+
+    #[tokio::test]
+    async fn socks5_auth_failed() {
+        let (addr, _task) = socks5_stub(Some(("user", "secret")), 0x00).await;
+        let config = SocketConfig::new()
+            .proxy(ProxyConfig::socks5(addr.to_string()).auth("user", "wrong"));
+
+        let e = connect_tcp("dc1.example.com:443", &config).await.unwrap_err();
+        assert_eq!(proxy_error(e), ProxyError::AuthFailed);
+    }
+
+    #[tokio::test]
+    async fn socks5_auth_required() {
+        let (addr, _task) = socks5_stub(Some(("user", "secret")), 0x00).await;
+        let config = SocketConfig::new().proxy(ProxyConfig::socks5(addr.to_string()));
+
+        let e = connect_tcp("dc1.example.com:443", &config).await.unwrap_err();
+        assert_eq!(proxy_error(e), ProxyError::NoAcceptableAuth);
+    }
+
+    #[tokio::test]
+    async fn socks5_host_unreachable() {
+        let (addr, _task) = socks5_stub(None, 0x04).await;
+        let config = SocketConfig::new().proxy(ProxyConfig::socks5(addr.to_string()));
+
+        let e = connect_tcp("dc1.example.com:443", &config).await.unwrap_err();
+        assert_eq!(proxy_error(e), ProxyError::Socks5(0x04));
+    }
+
+    #[tokio::test]
+    async fn http_connect_rejected() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let task = tokio::spawn(async move {
+            let (mut s, _) = listener.accept().await.unwrap();
+            let mut head = vec![];
+            while !head.ends_with(b"\r\n\r\n") {
+                head.push(s.read_u8().await.unwrap());
+            }
+            s.write_all(b"HTTP/1.1 407 Proxy Authentication Required\r\n\r\n").await.unwrap();
+            String::from_utf8(head).unwrap()
+        });
+        let config = SocketConfig::new().proxy(ProxyConfig::http(addr.to_string()).auth("user", "secret"));
+
+        let e = connect_tcp("dc1.example.com:443", &config).await.unwrap_err();
+        assert_eq!(proxy_error(e), ProxyError::Http(407));
+        let head = task.await.unwrap();
+        assert!(head.starts_with("CONNECT dc1.example.com:443 HTTP/1.1\r\n"));
+        assert!(head.contains("Proxy-Authorization: Basic dXNlcjpzZWNyZXQ=\r\n"));
+    }
+}
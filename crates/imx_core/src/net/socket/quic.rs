@@ -1,45 +1,85 @@
-use std::net::SocketAddr;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
 
-use anyhow::Result;
-use async_channel::Receiver;
+use anyhow::{bail, Result};
 use crossbeam::atomic::AtomicCell;
 use log::info;
-use quinn::{ClientConfig, Endpoint, SendStream};
+use quinn::{ClientConfig, Connection, ConnectionError, Endpoint, ReadError, SendStream, VarInt};
 use quinn::crypto::rustls::QuicClientConfig;
-use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
 
-use crate::defines::READ_BUFFER_SIZE;
-use crate::net::Addr;
+use crate::net::{Addr, SocketConfig};
 use crate::net::event::{Event, event_channel, EventReceiver, EventSender};
-use crate::net::socket::{Channel, channel, Error, Socket};
+use crate::net::socket::{certs, dns, Error, ProxyError, Socket};
 
 /// [quinn 文档](https://quinn-rs.github.io/quinn/networking-introduction.html)
 /// [quinn examples](https://github.com/quinn-rs/quinn/tree/main/quinn/examples)
+///
+/// 每个连接只打开一个双向 stream, 用于传输 transport 层的数据.
+/// 同一个 [SocketConfig] 发起的连接共享 TLS 会话缓存, 重连时尝试 0-RTT
 pub(crate) struct QuicSocket {
     intercept: Arc<AtomicCell<bool>>,
     tx: EventSender,
     rx: EventReceiver,
-    send: SendStream,
+    conn: Connection,
+    send: Mutex<SendStream>,
+    /// 是否通过 0-RTT 建立并被服务端接受
+    zero_rtt: bool,
 }
 
 impl Socket for QuicSocket {
-    async fn connect(addr: Addr) -> Result<Self> {
-        info!("(quic)Connecting {:?} ...", addr);
-        // let mut client_crypto = rustls::ClientConfig::builder()
-        //     .with_root_certificates(roots)
-        //     .with_no_client_auth();
-        let server_addr = SocketAddr::try_from(addr)?;
+    async fn connect(addr: Addr, config: &SocketConfig) -> Result<Self> {
+        info!("(quic) Connecting {:?} ...", addr);
+        // 不支持 SOCKS5 UDP ASSOCIATE, 不能绕过代理直连
+        if config.proxy.is_some() {
+            bail!(Error::Proxy(ProxyError::Unsupported("quic")));
+        }
+        let host_port = addr.without_scheme();
+        let server_name = certs::server_name(&config.tls, &host_port);
 
-        let quic_config = QuicClientConfig::try_from(client_crypto)?;
-        let config = ClientConfig::new(Arc::new(quic_config));
+        let client_config = match config.quic.get() {
+            Some(c) => c.clone(),
+            None => {
+                let mut client_crypto = certs::client_config(&config.tls)?;
+                client_crypto.enable_early_data = true;
+                let quic_config = QuicClientConfig::try_from(client_crypto)?;
+                config.quic.get_or_init(|| ClientConfig::new(Arc::new(quic_config))).clone()
+            }
+        };
 
-        // 本地端口
-        let mut endpoint = Endpoint::client("127.0.0.1:5000".into())?;
-        endpoint.set_default_client_config(config);
+        let addrs = dns::resolve(&host_port, config.resolver.as_ref()).await?;
+        let (conn, send, mut recv, zero_rtt) = dns::happy_eyeballs(addrs, |server_addr| {
+            let client_config = client_config.clone();
+            let server_name = server_name.clone();
+            async move {
+                // 本地使用临时端口
+                let local_addr: SocketAddr = match server_addr {
+                    SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+                    SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+                };
+                let mut endpoint = Endpoint::client(local_addr)?;
+                endpoint.set_default_client_config(client_config);
 
-        let conn = endpoint.connect(server_addr, "")?.await?;
-        let (mut send, mut recv) = conn.open_bi().await?;
+                let connecting = endpoint.connect(server_addr, &server_name)?;
+                match connecting.into_0rtt() {
+                    Ok((conn, accepted)) => {
+                        let (send, recv) = conn.open_bi().await?;
+                        if accepted.await {
+                            return anyhow::Ok((conn, send, recv, true));
+                        }
+                        // 0-RTT 被服务端拒绝, 之前打开的 stream 失效, 握手已经完成, 重新打开
+                        info!("(quic) 0-RTT rejected by {}", server_addr);
+                        let (send, recv) = conn.open_bi().await?;
+                        anyhow::Ok((conn, send, recv, false))
+                    }
+                    Err(connecting) => {
+                        let conn = connecting.await?;
+                        let (send, recv) = conn.open_bi().await?;
+                        anyhow::Ok((conn, send, recv, false))
+                    }
+                }
+            }
+        }).await?;
 
         let (tx, rx) = event_channel();
         let tx2 = tx.clone();
@@ -47,8 +87,9 @@ impl Socket for QuicSocket {
         let intercept = Arc::new(AtomicCell::new(false));
         let intercept2 = intercept.clone();
 
+        let buffer_size = config.read_buffer_size;
         tokio::spawn(async move {
-            let mut buf = vec![0; READ_BUFFER_SIZE];
+            let mut buf = vec![0; buffer_size];
             loop {
                 if intercept2.load() {
                     tx2.close();
@@ -57,30 +98,31 @@ impl Socket for QuicSocket {
 
                 match recv.read(&mut buf).await {
                     Ok(None) => {
-                        tx2.send(Event::OnSocketError(Error::EOF)).await.ok();
+                        tx2.send(Event::OnSocketError(Error::Eof)).ok();
                         tx2.close();
                         break;
                     }
                     Ok(Some(n)) => {
-                        buf.truncate(n);
-                        tx2.send(Event::OnReceivedData(buf.to_vec())).ok();
+                        tx2.send(Event::OnReceivedData(buf[..n].to_vec())).ok();
                     }
                     Err(e) => {
-                        tx2.send(Event::OnSocketError(Error::Quic(e))).await.ok();
+                        tx2.send(Event::OnSocketError(read_error(e))).ok();
+                        tx2.close();
+                        break;
                     }
                 }
             }
-            info!("(quic)Connection intercepted");
+            info!("(quic) Connection intercepted");
         });
 
-        // todo
-        //conn.remote_address()
-
-        Ok(Self { intercept, tx, rx, send })
+        let socket = Self { intercept, tx, rx, conn, send: Mutex::new(send), zero_rtt };
+        info!("(quic) Connected to {:?}, 0-RTT: {}", socket.conn.remote_address(), socket.zero_rtt);
+        Ok(socket)
     }
 
     async fn send(&self, data: &[u8]) -> Result<()> {
-        todo!()
+        self.send.lock().await.write_all(data).await?;
+        Ok(())
     }
 
     fn receiver(&self) -> EventReceiver {
@@ -88,9 +130,185 @@ impl Socket for QuicSocket {
     }
 
     async fn close(&mut self) {
-        if let Err(e) = self.send.shutdown().await {
-            self.tx.send(Event::OnSocketError(Error::Io(e))).await.ok();
+        if let Err(e) = self.send.get_mut().finish() {
+            self.tx.send(Event::OnSocketError(Error::Io(e.into()))).ok();
         }
+        self.conn.close(VarInt::from_u32(0), b"");
         self.intercept.store(true);
     }
-}
\ No newline at end of file
+}
+
+/// 对端主动关闭时映射为 [Error::Closed] 或 [Error::Eof]
+fn read_error(e: ReadError) -> Error {
+    match e {
+        ReadError::ConnectionLost(ConnectionError::ApplicationClosed(close)) => {
+            let code = close.error_code.into_inner();
+            if code == 0 {
+                Error::Eof
+            } else {
+                Error::Closed {
+                    code,
+                    reason: String::from_utf8_lossy(&close.reason).into_owned(),
+                }
+            }
+        }
+        ReadError::ConnectionLost(ConnectionError::LocallyClosed) => Error::Intercepted,
+        e => Error::Quic(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use quinn::{Endpoint, ServerConfig, VarInt};
+    use quinn::crypto::rustls::QuicServerConfig;
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+
+    use crate::net::{Addr, ProxyConfig, SocketConfig, TlsConfig};
+    use crate::net::event::Event;
+    use crate::net::socket::{Error, ProxyError, Socket};
+
+    use super::QuicSocket;
+
+    /// 本地 quinn 服务端, 证书为 `localhost` 的自签名证书
+    fn server(alpn: &[u8]) -> (Endpoint, CertificateDer<'static>) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let cert_der = cert.cert.der().clone();
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der()));
+
+        let provider = std::sync::Arc::new(rustls::crypto::ring::default_provider());
+        let mut crypto = rustls::ServerConfig::builder_with_provider(provider)
+            .with_protocol_versions(&[&rustls::version::TLS13]).unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert_der.clone()], key).unwrap();
+        crypto.alpn_protocols = vec![alpn.to_vec()];
+        crypto.max_early_data_size = u32::MAX;
+
+        let config = ServerConfig::with_crypto(std::sync::Arc::new(QuicServerConfig::try_from(crypto).unwrap()));
+        let endpoint = Endpoint::server(config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        (endpoint, cert_der)
+    }
+
+    /// 每个连接只处理一个双向 stream, 原样返回收到的数据
+    fn echo(endpoint: Endpoint) {
+        tokio::spawn(async move {
+            while let Some(incoming) = endpoint.accept().await {
+                tokio::spawn(async move {
+                    let conn = incoming.await?;
+                    let (mut send, mut recv) = conn.accept_bi().await?;
+                    let mut buf = vec![0; 1024];
+                    while let Some(n) = recv.read(&mut buf).await? {
+                        send.write_all(&buf[..n]).await?;
+                    }
+                    anyhow::Ok(())
+                });
+            }
+        });
+    }
+
+    fn addr(local: SocketAddr) -> Addr {
+        Addr::Custom(format!("quic://{}", local))
+    }
+
+    async fn roundtrip(socket: &QuicSocket, data: &[u8]) -> Vec<u8> {
+        socket.send(data).await.unwrap();
+        let rx = socket.receiver();
+        let mut out = vec![];
+        while out.len() < data.len() {
+            match rx.recv().await {
+                Event::OnReceivedData(bytes) => out.extend(bytes),
+                ev => panic!("unexpected event: {:?}", ev),
+            }
+        }
+        out
+    }
+
+    #[tokio::test]
+    async fn echo_with_root_cert() {
+        let (endpoint, cert) = server(b"mtproto");
+        let local = endpoint.local_addr().unwrap();
+        echo(endpoint);
+
+        let tls = TlsConfig::new()
+            .root_cert(cert.to_vec())
+            .server_name("localhost")
+            .alpn("mtproto");
+        let config = SocketConfig::new().tls(tls);
+        let mut socket = QuicSocket::connect(addr(local), &config).await.unwrap();
+        assert_eq!(roundtrip(&socket, b"hello quic").await, b"hello quic");
+        socket.close().await;
+    }
+
+    #[tokio::test]
+    async fn reconnect_with_pinned_cert() {
+        let (endpoint, cert) = server(b"mtproto");
+        let local = endpoint.local_addr().unwrap();
+        echo(endpoint);
+
+        let tls = TlsConfig::new().pinned_cert(cert.to_vec()).alpn("mtproto");
+        let config = SocketConfig::new().tls(tls);
+        for i in 0..3 {
+            // 第二次开始复用缓存的客户端配置和会话票据, 通过 0-RTT 建立连接
+            let mut socket = QuicSocket::connect(addr(local), &config).await.unwrap();
+            assert_eq!(socket.zero_rtt, i > 0);
+            let data = format!("round {}", i);
+            assert_eq!(roundtrip(&socket, data.as_bytes()).await, data.as_bytes());
+            socket.close().await;
+        }
+        assert!(config.quic.get().is_some());
+    }
+
+    #[tokio::test]
+    async fn reject_unpinned_cert() {
+        let (endpoint, _) = server(b"mtproto");
+        let local = endpoint.local_addr().unwrap();
+        echo(endpoint);
+
+        let other = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let tls = TlsConfig::new().pinned_cert(other.cert.der().to_vec()).alpn("mtproto");
+        let config = SocketConfig::new().tls(tls);
+        assert!(QuicSocket::connect(addr(local), &config).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn reject_alpn_mismatch() {
+        let (endpoint, cert) = server(b"mtproto");
+        let local = endpoint.local_addr().unwrap();
+        echo(endpoint);
+
+        let tls = TlsConfig::new().pinned_cert(cert.to_vec()).alpn("h3");
+        let config = SocketConfig::new().tls(tls);
+        assert!(QuicSocket::connect(addr(local), &config).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn application_close() {
+        let (endpoint, cert) = server(b"mtproto");
+        let local = endpoint.local_addr().unwrap();
+        tokio::spawn(async move {
+            let conn = endpoint.accept().await.unwrap().await.unwrap();
+            conn.close(VarInt::from_u32(42), b"bye");
+            // 等待对端收到 CONNECTION_CLOSE
+            endpoint.wait_idle().await;
+        });
+
+        let tls = TlsConfig::new().pinned_cert(cert.to_vec()).alpn("mtproto");
+        let config = SocketConfig::new().tls(tls);
+        let socket = QuicSocket::connect(addr(local), &config).await.unwrap();
+        match socket.receiver().recv().await {
+            Event::OnSocketError(Error::Closed { code, reason }) => {
+                assert_eq!(code, 42);
+                assert_eq!(reason, "bye");
+            }
+            ev => panic!("unexpected event: {:?}", ev),
+        }
+    }
+
+    #[tokio::test]
+    async fn reject_proxy() {
+        let config = SocketConfig::new().proxy(ProxyConfig::socks5("127.0.0.1:1080"));
+        let e = QuicSocket::connect(Addr::Custom("quic://127.0.0.1:443".into()), &config).await.map(|_| ()).unwrap_err();
+        assert!(matches!(e.downcast::<Error>(), Ok(Error::Proxy(ProxyError::Unsupported("quic")))));
+    }
+}
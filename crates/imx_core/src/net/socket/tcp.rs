@@ -1,19 +1,15 @@
 use std::io;
-use std::net::SocketAddr;
 use std::sync::Arc;
 
 use anyhow::Result;
-use async_channel::Receiver;
 use crossbeam::atomic::AtomicCell;
 use log::info;
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::tcp::OwnedWriteHalf;
-use tokio::net::TcpStream;
 
-use crate::defines::READ_BUFFER_SIZE;
-use crate::net::Addr;
+use crate::net::{Addr, SocketConfig};
 use crate::net::event::{Event, event_channel, EventReceiver, EventSender};
-use crate::net::socket::{Error, Socket};
+use crate::net::socket::{Error, proxy, Socket};
 
 pub(crate) struct TcpSocket {
     intercept: Arc<AtomicCell<bool>>,
@@ -23,11 +19,10 @@ pub(crate) struct TcpSocket {
 }
 
 impl Socket for TcpSocket {
-    async fn connect(addr: Addr) -> Result<Self> {
+    async fn connect(addr: Addr, config: &SocketConfig) -> Result<Self> {
         info!("(tcp) Connecting {:?} ...", addr);
-        let server_addr = SocketAddr::try_from(addr)?;
-        // 连接服务器
-        let stream = TcpStream::connect(server_addr).await?;
+        // 连接服务器, 配置了代理时通过代理连接
+        let stream = proxy::connect_tcp(&addr.without_scheme(), config).await?;
         // 读写分离
         let (mut rd, wt) = stream.into_split();
         // 异步 task 之间通信
@@ -37,8 +32,9 @@ impl Socket for TcpSocket {
         let intercept = Arc::new(AtomicCell::new(false));
         let intercept2 = intercept.clone();
         // 在独立的异步任务中, 监听服务端发送的数据 (服务端可能会主动推送, 或客户端发送请求后返回的响应)
+        let buffer_size = config.read_buffer_size;
         tokio::spawn(async move {
-            let mut buf = vec![0; READ_BUFFER_SIZE];
+            let mut buf = vec![0; buffer_size];
             loop {
                 if intercept2.load() {
                     // 退出循环之前, 先关闭消息通道, 已发送的消息不会被清空, 直到被处理
@@ -48,16 +44,17 @@ impl Socket for TcpSocket {
 
                 match rd.read(&mut buf).await {
                     Ok(0) => {
-                        tx2.send(Event::OnSocketError(Error::EOF)).ok();
+                        tx2.send(Event::OnSocketError(Error::Eof)).ok();
                         tx2.close();
                         break;
                     }
                     Ok(n) => {
-                        buf.truncate(n);
-                        tx2.send(Event::OnReceivedData(buf.to_vec())).ok();
+                        tx2.send(Event::OnReceivedData(buf[..n].to_vec())).ok();
                     }
                     Err(e) => {
                         tx2.send(Event::OnSocketError(Error::Io(e))).ok();
+                        tx2.close();
+                        break;
                     }
                 }
             }
@@ -71,7 +68,7 @@ impl Socket for TcpSocket {
     }
 
     async fn send(&self, data: &[u8]) -> Result<()> {
-        let mut remaining = &data[..];
+        let mut remaining = data;
         loop {
             self.wt.writable().await?;
             match self.wt.try_write(remaining) {
@@ -0,0 +1,232 @@
+use std::io;
+use std::sync::Arc;
+
+use anyhow::Result;
+use crossbeam::atomic::AtomicCell;
+use log::info;
+use rustls::pki_types::ServerName;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, WriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::TlsConnector;
+
+use crate::net::{Addr, SocketConfig};
+use crate::net::event::{Event, event_channel, EventReceiver, EventSender};
+use crate::net::socket::{certs, Error, proxy, Socket};
+
+/// 使用 TLS 加密的 tcp 连接, 用于部署在 TLS 终端之后的服务器
+///
+/// 地址形如 `tls://example.com:443`, 握手使用的服务器名称默认取地址中的主机名,
+/// 可通过 [TlsConfig::server_name](crate::TlsConfig::server_name) 覆盖
+pub(crate) struct TlsSocket {
+    intercept: Arc<AtomicCell<bool>>,
+    tx: EventSender,
+    rx: EventReceiver,
+    wt: Mutex<WriteHalf<TlsStream<TcpStream>>>,
+}
+
+impl Socket for TlsSocket {
+    async fn connect(addr: Addr, config: &SocketConfig) -> Result<Self> {
+        info!("(tls) Connecting {:?} ...", addr);
+        let host_port = addr.without_scheme();
+        let server_name = ServerName::try_from(certs::server_name(&config.tls, &host_port))?;
+
+        let client_config = match config.rustls.get() {
+            Some(c) => c.clone(),
+            None => {
+                let client_config = Arc::new(certs::client_config(&config.tls)?);
+                config.rustls.get_or_init(|| client_config).clone()
+            }
+        };
+
+        // 连接服务器, 配置了代理时通过代理连接
+        let stream = proxy::connect_tcp(&host_port, config).await?;
+        stream.set_nodelay(true)?;
+        // TLS 握手
+        let stream = TlsConnector::from(client_config)
+            .connect(server_name, stream).await
+            .map_err(handshake_error)?;
+        // 读写分离
+        let (mut rd, wt) = tokio::io::split(stream);
+        // 异步 task 之间通信
+        let (tx, rx) = event_channel();
+        let tx2 = tx.clone();
+
+        let intercept = Arc::new(AtomicCell::new(false));
+        let intercept2 = intercept.clone();
+        let buffer_size = config.read_buffer_size;
+        tokio::spawn(async move {
+            let mut buf = vec![0; buffer_size];
+            loop {
+                if intercept2.load() {
+                    tx2.close();
+                    break;
+                }
+
+                match rd.read(&mut buf).await {
+                    Ok(0) => {
+                        tx2.send(Event::OnSocketError(Error::Eof)).ok();
+                        tx2.close();
+                        break;
+                    }
+                    Ok(n) => {
+                        tx2.send(Event::OnReceivedData(buf[..n].to_vec())).ok();
+                    }
+                    Err(e) => {
+                        tx2.send(Event::OnSocketError(Error::Io(e))).ok();
+                        tx2.close();
+                        break;
+                    }
+                }
+            }
+            info!("(tls) Connection intercepted");
+        });
+
+        info!("(tls) Connected to {}", host_port);
+
+        Ok(Self { intercept, tx, rx, wt: Mutex::new(wt) })
+    }
+
+    async fn send(&self, data: &[u8]) -> Result<()> {
+        let mut wt = self.wt.lock().await;
+        wt.write_all(data).await?;
+        wt.flush().await?;
+        Ok(())
+    }
+
+    fn receiver(&self) -> EventReceiver {
+        self.rx.clone()
+    }
+
+    async fn close(&mut self) {
+        if let Err(e) = self.wt.get_mut().shutdown().await {
+            self.tx.send(Event::OnSocketError(Error::Io(e))).ok();
+        }
+        self.intercept.store(true);
+    }
+}
+
+/// 握手过程中 rustls 返回的错误映射为 [Error::Handshake], 其它仍为 io 错误
+fn handshake_error(e: io::Error) -> Error {
+    match e.get_ref().and_then(|e| e.downcast_ref::<rustls::Error>()) {
+        Some(e) => Error::Handshake(e.clone()),
+        None => Error::Io(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use rustls::{CertificateError, ServerConfig};
+    use rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+
+    use crate::net::TlsConfig;
+
+    use super::*;
+
+    /// 本地 TLS 服务端, 证书为 `localhost` 的自签名证书, 原样返回收到的数据
+    async fn echo_server() -> (SocketAddr, CertificateDer<'static>) {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let cert_der = cert.cert.der().clone();
+        let key = PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der()));
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions().unwrap()
+            .with_no_client_auth()
+            .with_single_cert(vec![cert_der.clone()], key).unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let acceptor = acceptor.clone();
+                tokio::spawn(async move {
+                    let mut stream = acceptor.accept(stream).await?;
+                    let mut buf = vec![0; 1024];
+                    loop {
+                        let n = stream.read(&mut buf).await?;
+                        if n == 0 { break; }
+                        stream.write_all(&buf[..n]).await?;
+                    }
+                    io::Result::Ok(())
+                });
+            }
+        });
+        (local, cert_der)
+    }
+
+    async fn connect(local: SocketAddr, tls: TlsConfig) -> Result<TlsSocket> {
+        let addr = Addr::Custom(format!("tls://{}", local));
+        TlsSocket::connect(addr, &SocketConfig::new().tls(tls)).await
+    }
+
+    async fn roundtrip(socket: &TlsSocket, data: &[u8]) -> Vec<u8> {
+        socket.send(data).await.unwrap();
+        let rx = socket.receiver();
+        let mut out = vec![];
+        while out.len() < data.len() {
+            match rx.recv().await {
+                Event::OnReceivedData(bytes) => out.extend(bytes),
+                ev => panic!("unexpected event: {:?}", ev),
+            }
+        }
+        out
+    }
+
+    fn handshake_failure(res: Result<TlsSocket>) -> rustls::Error {
+        match res.map(|_| ()).unwrap_err().downcast::<Error>() {
+            Ok(Error::Handshake(e)) => e,
+            e => panic!("unexpected error: {:?}", e),
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_root_with_sni() {
+        let (local, cert) = echo_server().await;
+        let tls = TlsConfig::new().root_cert(cert.to_vec()).server_name("localhost");
+        let mut socket = connect(local, tls).await.unwrap();
+        assert_eq!(roundtrip(&socket, b"hello tls").await, b"hello tls");
+        socket.close().await;
+    }
+
+    #[tokio::test]
+    async fn reject_wrong_server_name() {
+        let (local, cert) = echo_server().await;
+        let tls = TlsConfig::new().root_cert(cert.to_vec()).server_name("other.example.com");
+        let e = handshake_failure(connect(local, tls).await);
+        assert!(matches!(e, rustls::Error::InvalidCertificate(CertificateError::NotValidForNameContext { .. })), "{:?}", e);
+
+        // 没有覆盖时使用地址中的 IP, 证书中不包含
+        let tls = TlsConfig::new().root_cert(cert.to_vec());
+        handshake_failure(connect(local, tls).await);
+    }
+
+    #[tokio::test]
+    async fn pinned_spki() {
+        let (local, cert) = echo_server().await;
+        // 固定公钥时不检查服务器名称
+        let tls = TlsConfig::new().pinned_spki(certs::spki_sha256(&cert).unwrap());
+        let mut socket = connect(local, tls).await.unwrap();
+        assert_eq!(roundtrip(&socket, b"pinned").await, b"pinned");
+        socket.close().await;
+
+        let tls = TlsConfig::new().pinned_spki([0; 32]);
+        let e = handshake_failure(connect(local, tls).await);
+        assert_eq!(e, rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure));
+    }
+
+    #[tokio::test]
+    async fn reject_unknown_root() {
+        let (local, _) = echo_server().await;
+        let other = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let tls = TlsConfig::new().root_cert(other.cert.der().to_vec()).server_name("localhost");
+        // 颁发者名称相同, 签名校验失败
+        let e = handshake_failure(connect(local, tls).await);
+        assert!(matches!(e, rustls::Error::InvalidCertificate(_)), "{:?}", e);
+    }
+}
@@ -2,24 +2,104 @@ use std::sync::Arc;
 
 use anyhow::Result;
 use crossbeam::atomic::AtomicCell;
+use futures_util::{SinkExt, StreamExt};
+use futures_util::stream::SplitSink;
+use log::{info, warn};
+use tokio::net::TcpStream;
+use tokio::sync::Mutex;
+use tokio_tungstenite::{client_async_tls, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::http::HeaderValue;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::tungstenite::protocol::CloseFrame;
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
 
-use crate::net::Addr;
-use crate::net::event::{EventReceiver, EventSender};
-use crate::net::socket::Socket;
+use crate::net::{Addr, SocketConfig};
+use crate::net::event::{Event, event_channel, EventReceiver, EventSender};
+use crate::net::socket::{Error, proxy, Socket};
 
+/// 服务器的 WebSocket 路径
+const WS_PATH: &str = "/apiws";
+
+type WsSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
+
+/// [WebSocket](https://core.telegram.org/mtproto/transports#websocket)
+///
+/// 地址形如 `ws://host:port/apiws` 或 `wss://host/apiws`, 握手时要求 `binary` 子协议,
+/// 数据只通过二进制帧传输. 按照协议要求, 使用 WebSocket 时需要开启 obfuscation
 pub(crate) struct WebSocket {
     intercept: Arc<AtomicCell<bool>>,
     tx: EventSender,
     rx: EventReceiver,
+    wt: Mutex<WsSink>,
 }
 
 impl Socket for WebSocket {
-    async fn connect(addr: Addr) -> Result<Self> {
-        todo!()
+    async fn connect(addr: Addr, config: &SocketConfig) -> Result<Self> {
+        info!("(ws) Connecting {:?} ...", addr);
+        let url = ws_url(&addr);
+        let mut request = url.as_str().into_client_request()?;
+        request.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_static("binary"));
+
+        let uri = request.uri();
+        let host = uri.host().unwrap_or_default();
+        let port = uri.port_u16().unwrap_or(if uri.scheme_str() == Some("wss") { 443 } else { 80 });
+        // 连接服务器, 配置了代理时通过代理连接
+        let stream = proxy::connect_tcp(&format!("{}:{}", host, port), config).await?;
+        // 握手, wss 使用 rustls
+        let (stream, _) = client_async_tls(request, stream).await?;
+        // 读写分离
+        let (wt, mut rd) = stream.split();
+        // 异步 task 之间通信
+        let (tx, rx) = event_channel();
+        let tx2 = tx.clone();
+
+        let intercept = Arc::new(AtomicCell::new(false));
+        let intercept2 = intercept.clone();
+        tokio::spawn(async move {
+            loop {
+                if intercept2.load() {
+                    tx2.close();
+                    break;
+                }
+
+                match rd.next().await {
+                    None => {
+                        tx2.send(Event::OnSocketError(Error::Eof)).ok();
+                        tx2.close();
+                        break;
+                    }
+                    Some(Ok(Message::Binary(data))) => {
+                        tx2.send(Event::OnReceivedData(data)).ok();
+                    }
+                    // 收到 ping 时 tungstenite 会在下一次读取时自动回复 pong
+                    Some(Ok(Message::Ping(_))) | Some(Ok(Message::Pong(_))) => {}
+                    Some(Ok(Message::Close(frame))) => {
+                        tx2.send(Event::OnSocketError(close_error(frame))).ok();
+                        tx2.close();
+                        break;
+                    }
+                    Some(Ok(msg)) => {
+                        warn!("(ws) Ignore non-binary message: {:?}", msg);
+                    }
+                    Some(Err(e)) => {
+                        tx2.send(Event::OnSocketError(e.into())).ok();
+                        tx2.close();
+                        break;
+                    }
+                }
+            }
+            info!("(ws) Connection intercepted");
+        });
+
+        info!("(ws) Connected to {}", url);
+
+        Ok(Self { intercept, tx, rx, wt: Mutex::new(wt) })
     }
 
     async fn send(&self, data: &[u8]) -> Result<()> {
-        todo!()
+        self.wt.lock().await.send(Message::binary(data)).await?;
+        Ok(())
     }
 
     fn receiver(&self) -> EventReceiver {
@@ -27,6 +107,102 @@ impl Socket for WebSocket {
     }
 
     async fn close(&mut self) {
+        if let Err(e) = self.wt.get_mut().close().await {
+            self.tx.send(Event::OnSocketError(e.into())).ok();
+        }
         self.intercept.store(true);
     }
-}
\ No newline at end of file
+}
+
+/// 连接使用的 URL, 没有指定路径时使用 `/apiws`. [Addr::SocketAddr] 使用 `ws://`
+fn ws_url(addr: &Addr) -> String {
+    match addr {
+        Addr::SocketAddr(addr) => format!("ws://{}{}", addr, WS_PATH),
+        Addr::Custom(url) => {
+            let authority = url.find("://").map_or(0, |i| i + 3);
+            if url[authority..].contains('/') {
+                url.clone()
+            } else {
+                format!("{}{}", url, WS_PATH)
+            }
+        }
+    }
+}
+
+/// 正常关闭视为 [Error::Eof], 其它关闭码原样返回
+fn close_error(frame: Option<CloseFrame>) -> Error {
+    match frame {
+        None => Error::Eof,
+        Some(frame) if frame.code == CloseCode::Normal || frame.code == CloseCode::Away => Error::Eof,
+        Some(frame) => Error::Closed { code: u16::from(frame.code) as u64, reason: frame.reason.into_owned() },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_hdr_async;
+    use tokio_tungstenite::tungstenite::handshake::server::{Request, Response};
+
+    use crate::net::SocketConfig;
+
+    use super::*;
+
+    #[test]
+    fn url_path() {
+        let addr: SocketAddr = "127.0.0.1:80".parse().unwrap();
+        assert_eq!(ws_url(&addr.into()), "ws://127.0.0.1:80/apiws");
+        assert_eq!(ws_url(&Addr::Custom("wss://example.com".into())), "wss://example.com/apiws");
+        assert_eq!(ws_url(&Addr::Custom("ws://example.com:8080/apiws_test".into())), "ws://example.com:8080/apiws_test");
+    }
+
+    /// 本地 WebSocket 服务端: 校验路径和子协议, 原样返回收到的二进制帧
+    async fn echo_server() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let local = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            // 回调的签名由 tungstenite 决定
+            #[allow(clippy::result_large_err)]
+            let callback = |req: &Request, mut resp: Response| {
+                assert_eq!(req.uri().path(), WS_PATH);
+                assert_eq!(req.headers()["Sec-WebSocket-Protocol"], "binary");
+                resp.headers_mut().insert("Sec-WebSocket-Protocol", HeaderValue::from_static("binary"));
+                Ok(resp)
+            };
+            let mut ws = accept_hdr_async(stream, callback).await.unwrap();
+            // 收到关闭帧后 tungstenite 自动回复, 读到 None 时结束
+            while let Some(Ok(msg)) = ws.next().await {
+                if let Message::Binary(data) = msg {
+                    ws.send(Message::binary(data)).await.unwrap();
+                }
+            }
+        });
+        local
+    }
+
+    #[tokio::test]
+    async fn connect_send_receive_close() {
+        let local = echo_server().await;
+        let mut socket = WebSocket::connect(local.into(), &SocketConfig::new()).await.unwrap();
+        let rx = socket.receiver();
+
+        socket.send(b"hello ws").await.unwrap();
+        match rx.recv().await {
+            Event::OnReceivedData(data) => assert_eq!(data.as_slice(), b"hello ws"),
+            ev => panic!("unexpected event: {:?}", ev),
+        }
+
+        socket.close().await;
+        // 服务端回应关闭帧后读取 task 结束
+        loop {
+            match rx.recv().await {
+                Event::OnSocketError(Error::Eof) => {}
+                Event::OnIntercepted => break,
+                ev => panic!("unexpected event: {:?}", ev),
+            }
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use std::fmt::{Debug, Formatter};
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use thiserror::Error;
+use tokio::sync::broadcast;
+use tokio_stream::Stream;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::wrappers::errors::BroadcastStreamRecvError;
+
+use crate::proto::Update;
+
+/// 消费太慢, 缓冲区满后最早的 updates 被丢弃. 收到后需要通过 `updates.getDifference` 重新同步
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+#[error("update stream lagged, {0} updates skipped")]
+pub struct Lagged(pub u64);
+
+/// [Client::updates](crate::Client::updates) 返回的 updates 流, 每个订阅者有独立的游标.
+/// 缓冲区大小由 [ClientBuilder::update_buffer](crate::ClientBuilder::update_buffer) 设置, 客户端释放后结束
+pub struct UpdateStream {
+    inner: BroadcastStream<Update>,
+}
+
+impl UpdateStream {
+    pub(crate) fn new(rx: broadcast::Receiver<Update>) -> Self {
+        Self { inner: BroadcastStream::new(rx) }
+    }
+}
+
+impl Stream for UpdateStream {
+    type Item = Result<Update, Lagged>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+            .map(|item| item.map(|res| res.map_err(|BroadcastStreamRecvError::Lagged(n)| Lagged(n))))
+    }
+}
+
+impl Debug for UpdateStream {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UpdateStream").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use tokio_stream::StreamExt;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn lagged() {
+        let (tx, rx) = broadcast::channel(2);
+        let mut stream = UpdateStream::new(rx);
+        for i in 0..3u8 {
+            tx.send(Update::Raw(Bytes::from(vec![i]))).unwrap();
+        }
+        drop(tx);
+        assert_eq!(stream.next().await, Some(Err(Lagged(1))));
+        assert_eq!(stream.next().await, Some(Ok(Update::Raw(Bytes::from_static(&[1])))));
+        assert_eq!(stream.next().await, Some(Ok(Update::Raw(Bytes::from_static(&[2])))));
+        assert_eq!(stream.next().await, None);
+    }
+}
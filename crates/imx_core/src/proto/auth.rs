@@ -0,0 +1,100 @@
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use crate::proto::ExportedAuthorization;
+use crate::proto::service::{get_bytes, get_i32, get_i64, get_u32};
+
+const EXPORTED_AUTHORIZATION: u32 = 0xb434e2b8;
+const AUTHORIZATION: u32 = 0x2ea2c0d4;
+const USER_EMPTY: u32 = 0xd3bc4b7a;
+const BOOL_TRUE: u32 = 0x997275b5;
+const BOOL_FALSE: u32 = 0xbc799737;
+
+/// 解析 `auth.exportedAuthorization#b434e2b8 id:long bytes:bytes`
+pub(crate) fn parse_exported_authorization(body: &[u8]) -> Result<ExportedAuthorization> {
+    let mut buf = Bytes::copy_from_slice(body);
+    if get_u32(&mut buf)? != EXPORTED_AUTHORIZATION { bail!("invalid exported authorization"); }
+    let id = get_i64(&mut buf)?;
+    let bytes = get_bytes(&mut buf)?.to_vec();
+    Ok(ExportedAuthorization { id, bytes })
+}
+
+/// 解析登录或导入授权返回的 [auth.authorization](https://core.telegram.org/constructor/auth.authorization)
+/// 中的用户 id, 其他结果返回 `None`. `user` 各个 layer 的构造器不同, 但都以 `flags:# flags2:# id:long` 开头
+pub(crate) fn parse_authorization(body: &[u8]) -> Result<Option<i64>> {
+    let mut buf = Bytes::copy_from_slice(body);
+    if get_u32(&mut buf)? != AUTHORIZATION { return Ok(None); }
+    let flags = get_u32(&mut buf)?;
+    if flags & (1 << 1) != 0 { get_i32(&mut buf)?; }
+    if flags & (1 << 0) != 0 { get_i32(&mut buf)?; }
+    if flags & (1 << 2) != 0 { get_bytes(&mut buf)?; }
+    if get_u32(&mut buf)? != USER_EMPTY {
+        get_u32(&mut buf)?;
+        get_u32(&mut buf)?;
+    }
+    Ok(Some(get_i64(&mut buf)?))
+}
+
+/// 解析 `Bool`, 例如 `auth.bindTempAuthKey` 的结果
+pub(crate) fn parse_bool(body: &[u8]) -> Result<bool> {
+    let mut buf = Bytes::copy_from_slice(body);
+    match get_u32(&mut buf)? {
+        BOOL_TRUE => Ok(true),
+        BOOL_FALSE => Ok(false),
+        crc => bail!("invalid Bool {:#x}", crc),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::ByteBuffer;
+
+    use super::*;
+
+    #[test]
+    fn exported_authorization() {
+        let mut buf = ByteBuffer::new();
+        buf.put_u32(EXPORTED_AUTHORIZATION);
+        buf.put_i64(7);
+        buf.put_u8(3);
+        buf.put_all(&[1, 2, 3]);
+        let auth = parse_exported_authorization(&buf.to_bytes()).unwrap();
+        assert_eq!((auth.id, auth.bytes), (7, vec![1, 2, 3]));
+
+        assert!(parse_exported_authorization(&[0xb8, 0xe2, 0x34, 0xb4, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn authorization() {
+        let mut buf = ByteBuffer::new();
+        buf.put_u32(AUTHORIZATION);
+        buf.put_u32(0b111);
+        buf.put_i32(30);
+        buf.put_i32(1);
+        buf.put_u8(2);
+        buf.put_all(&[1, 2, 0]);
+        buf.put_u32(0x215c4438);
+        buf.put_u32(1 << 10);
+        buf.put_u32(0);
+        buf.put_i64(42);
+        assert_eq!(parse_authorization(&buf.to_bytes()).unwrap(), Some(42));
+
+        let mut buf = ByteBuffer::new();
+        buf.put_u32(AUTHORIZATION);
+        buf.put_u32(0);
+        buf.put_u32(USER_EMPTY);
+        buf.put_i64(7);
+        assert_eq!(parse_authorization(&buf.to_bytes()).unwrap(), Some(7));
+
+        assert_eq!(parse_authorization(&BOOL_TRUE.to_le_bytes()).unwrap(), None);
+        assert!(parse_authorization(&AUTHORIZATION.to_le_bytes()).is_err());
+    }
+
+    #[test]
+    fn bool() {
+        assert!(parse_bool(&BOOL_TRUE.to_le_bytes()).unwrap());
+        assert!(!parse_bool(&BOOL_FALSE.to_le_bytes()).unwrap());
+        assert!(parse_bool(&EXPORTED_AUTHORIZATION.to_le_bytes()).is_err());
+        assert!(parse_bool(&[]).is_err());
+    }
+}
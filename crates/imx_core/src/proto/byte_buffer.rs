@@ -4,7 +4,7 @@ use bytes::{Buf, BufMut, Bytes, BytesMut};
 use serde::{Serialize, Serializer};
 
 /// **LittleEndian**
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct ByteBuffer(BytesMut);
 
 impl ByteBuffer {
@@ -0,0 +1,136 @@
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use crate::proto::service::{get_bytes, get_i32, get_string, get_u32};
+
+const CONFIG: u32 = 0xcc1a241e;
+const DC_OPTION: u32 = 0x18b7a10d;
+const BOOL_TRUE: u32 = 0x997275b5;
+const BOOL_FALSE: u32 = 0xbc799737;
+const VECTOR: u32 = 0x1cb5c415;
+
+/// `dcOption#18b7a10d`, DataCenter 的一个地址
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct DcOption {
+    pub id: i32,
+    pub ip_address: String,
+    pub port: i32,
+    pub ipv6: bool,
+    /// 只用于下载和上传文件
+    pub media_only: bool,
+    /// 只能通过混淆的传输协议连接
+    pub tcpo_only: bool,
+    pub cdn: bool,
+    /// 使用代理时优先连接的地址
+    pub is_static: bool,
+    /// 该地址使用的 obfuscation 密钥
+    pub secret: Option<Bytes>,
+}
+
+/// `help.getConfig` 返回的 `config`, 只解析需要的字段
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Config {
+    pub date: i32,
+    pub expires: i32,
+    pub this_dc: i32,
+    pub dc_options: Vec<DcOption>,
+}
+
+/// 解析 [config](https://core.telegram.org/constructor/config) 到 `dc_options` 为止, 之后的字段随 layer 变化, 忽略
+pub(crate) fn parse_config(body: &[u8]) -> Result<Config> {
+    let mut buf = Bytes::copy_from_slice(body);
+    if get_u32(&mut buf)? != CONFIG { bail!("invalid config"); }
+    let _flags = get_i32(&mut buf)?;
+    let date = get_i32(&mut buf)?;
+    let expires = get_i32(&mut buf)?;
+    match get_u32(&mut buf)? {
+        BOOL_TRUE | BOOL_FALSE => {}
+        _ => bail!("invalid test_mode"),
+    }
+    let this_dc = get_i32(&mut buf)?;
+
+    if get_u32(&mut buf)? != VECTOR { bail!("invalid dc_options"); }
+    let count = get_i32(&mut buf)?;
+    let dc_options = (0..count).map(|_| parse_dc_option(&mut buf)).collect::<Result<_>>()?;
+
+    Ok(Config { date, expires, this_dc, dc_options })
+}
+
+/// `dcOption#18b7a10d flags:# ipv6:flags.0?true media_only:flags.1?true tcpo_only:flags.2?true cdn:flags.3?true
+/// static:flags.4?true this_port_only:flags.5?true id:int ip_address:string port:int secret:flags.10?bytes`
+fn parse_dc_option(buf: &mut Bytes) -> Result<DcOption> {
+    if get_u32(buf)? != DC_OPTION { bail!("invalid dc_option"); }
+    let flags = get_i32(buf)?;
+    let id = get_i32(buf)?;
+    let ip_address = get_string(buf)?;
+    let port = get_i32(buf)?;
+    let secret = if flags & (1 << 10) != 0 { Some(get_bytes(buf)?) } else { None };
+    Ok(DcOption {
+        id,
+        ip_address,
+        port,
+        ipv6: flags & 1 != 0,
+        media_only: flags & (1 << 1) != 0,
+        tcpo_only: flags & (1 << 2) != 0,
+        cdn: flags & (1 << 3) != 0,
+        is_static: flags & (1 << 4) != 0,
+        secret,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::ByteBuffer;
+
+    use super::*;
+
+    fn put_string(buf: &mut ByteBuffer, s: &[u8]) {
+        buf.put_u8(s.len() as u8);
+        buf.put_all(s);
+        for _ in 0..(4 - (1 + s.len()) % 4) % 4 {
+            buf.put_u8(0);
+        }
+    }
+
+    #[test]
+    fn parse_dc_options() {
+        let mut buf = ByteBuffer::new();
+        buf.put_u32(CONFIG);
+        buf.put_i32(0);
+        buf.put_i32(1000);
+        buf.put_i32(4600);
+        buf.put_u32(BOOL_FALSE);
+        buf.put_i32(2);
+        buf.put_u32(VECTOR);
+        buf.put_i32(2);
+
+        buf.put_u32(DC_OPTION);
+        buf.put_i32(1);
+        buf.put_i32(2);
+        put_string(&mut buf, b"2001:67c:4e8:f002::a");
+        buf.put_i32(443);
+
+        buf.put_u32(DC_OPTION);
+        buf.put_i32(1 << 1 | 1 << 10);
+        buf.put_i32(4);
+        put_string(&mut buf, b"149.154.167.92");
+        buf.put_i32(443);
+        put_string(&mut buf, &[0xdd; 17]);
+        // 之后的字段不解析
+        buf.put_i32(0);
+
+        let config = parse_config(&buf.to_bytes()).unwrap();
+        assert_eq!((config.date, config.expires, config.this_dc), (1000, 4600, 2));
+        assert_eq!(config.dc_options, vec![
+            DcOption { id: 2, ip_address: "2001:67c:4e8:f002::a".into(), port: 443, ipv6: true, ..Default::default() },
+            DcOption {
+                id: 4,
+                ip_address: "149.154.167.92".into(),
+                port: 443,
+                media_only: true,
+                secret: Some(Bytes::from_static(&[0xdd; 17])),
+                ..Default::default()
+            },
+        ]);
+    }
+}
@@ -18,7 +18,9 @@ impl MtRpc for ReqPQMulti { type Return = ResPQ; }
 pub struct ReqDHParams {
     pub nonce: [u8; 16],
     pub server_nonce: [u8; 16],
+    #[serde(with = "serde_bytes")]
     pub p: [u8; 4],
+    #[serde(with = "serde_bytes")]
     pub q: [u8; 4],
     pub public_key_fingerprint: i64,
     #[serde(with = "serde_bytes")]
@@ -42,4 +44,37 @@ pub struct PingDelayDisconnect {
     pub ping_id: i64,
     pub disconnect_delay: i32,
 }
-impl MtRpc for PingDelayDisconnect { type Return = Pong; }
\ No newline at end of file
+impl MtRpc for PingDelayDisconnect { type Return = Pong; }
+
+#[derive(WithCrc, Default, Serialize, Deserialize, Debug)]
+#[crc(0xe5bfffcd)]
+pub struct ExportAuthorization {
+    pub dc_id: i32,
+}
+impl MtRpc for ExportAuthorization { type Return = ExportedAuthorization; }
+
+/// 返回 `auth.Authorization`, 只用于在 DataCenter 之间转移授权, 不解析结果
+#[derive(WithCrc, Default, Serialize, Deserialize, Debug)]
+#[crc(0xa57a7dad)]
+pub struct ImportAuthorization {
+    pub id: i64,
+    #[serde(with = "serde_bytes")]
+    pub bytes: Vec<u8>,
+}
+
+/// `help.getConfig`, 返回 `Config`. 结果带 flags, 由 [parse_config](crate::proto::config::parse_config) 解析
+#[derive(WithCrc, Default, Serialize, Deserialize, Debug)]
+#[crc(0xc4f9186b)]
+pub struct GetConfig {}
+
+/// 使用临时密钥发送, msg_id 必须与 `encrypted_message` 中的相同
+#[derive(WithCrc, Default, Serialize, Deserialize, Debug)]
+#[crc(0xcdd42a05)]
+pub struct BindTempAuthKey {
+    pub perm_auth_key_id: i64,
+    pub nonce: i64,
+    pub expires_at: i32,
+    #[serde(with = "serde_bytes")]
+    pub encrypted_message: Vec<u8>,
+}
+impl MtRpc for BindTempAuthKey { type Return = Bool; }
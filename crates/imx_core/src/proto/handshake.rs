@@ -0,0 +1,122 @@
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use crate::proto::{ResPQ, ServerDHInnerData, ServerDHParams, SetClientDHParamsAnswer};
+use crate::proto::service::{get_array, get_bytes, get_i32, get_i64, get_u32};
+
+const RES_PQ: u32 = 0x05162463;
+const SERVER_DH_PARAMS_FAIL: u32 = 0x79cb045d;
+const SERVER_DH_PARAMS_OK: u32 = 0xd0e8075c;
+const SERVER_DH_INNER_DATA: u32 = 0xb5890dba;
+const DH_GEN_OK: u32 = 0x3bcbf734;
+const DH_GEN_RETRY: u32 = 0x46dc1fb9;
+const DH_GEN_FAIL: u32 = 0xa69dae02;
+const VECTOR: u32 = 0x1cb5c415;
+
+/// 解析 `resPQ#05162463 nonce:int128 server_nonce:int128 pq:string server_public_key_fingerprints:Vector<long>`
+pub(crate) fn parse_res_pq(body: &[u8]) -> Result<ResPQ> {
+    let mut buf = Bytes::copy_from_slice(body);
+    if get_u32(&mut buf)? != RES_PQ { bail!("invalid resPQ"); }
+    let nonce = get_array(&mut buf)?;
+    let server_nonce = get_array(&mut buf)?;
+    let pq = get_bytes(&mut buf)?.to_vec();
+    if get_u32(&mut buf)? != VECTOR { bail!("invalid fingerprints vector"); }
+    let count = get_i32(&mut buf)?;
+    let server_public_key_fingerprints = (0..count).map(|_| get_i64(&mut buf)).collect::<Result<_>>()?;
+    Ok(ResPQ { nonce, server_nonce, pq, server_public_key_fingerprints })
+}
+
+/// 解析 `Server_DH_Params`
+pub(crate) fn parse_server_dh_params(body: &[u8]) -> Result<ServerDHParams> {
+    let mut buf = Bytes::copy_from_slice(body);
+    let crc = get_u32(&mut buf)?;
+    let nonce = get_array(&mut buf)?;
+    let server_nonce = get_array(&mut buf)?;
+    match crc {
+        SERVER_DH_PARAMS_OK => {
+            let encrypted_answer = get_bytes(&mut buf)?.to_vec();
+            Ok(ServerDHParams::Ok { nonce, server_nonce, encrypted_answer })
+        }
+        SERVER_DH_PARAMS_FAIL => {
+            let new_nonce_hash = get_array(&mut buf)?;
+            Ok(ServerDHParams::Fail { nonce, server_nonce, new_nonce_hash })
+        }
+        _ => bail!("invalid Server_DH_Params {:#x}", crc),
+    }
+}
+
+/// 解析 `server_DH_inner_data`, 同时返回它的长度, 用于校验解密后 `answer_with_hash` 中的 sha1.
+/// [body] 之后可以有 padding
+pub(crate) fn parse_server_dh_inner_data(body: &[u8]) -> Result<(ServerDHInnerData, usize)> {
+    let mut buf = Bytes::copy_from_slice(body);
+    if get_u32(&mut buf)? != SERVER_DH_INNER_DATA { bail!("invalid server_DH_inner_data"); }
+    let nonce = get_array(&mut buf)?;
+    let server_nonce = get_array(&mut buf)?;
+    let g = get_i32(&mut buf)?;
+    let dh_prime = get_bytes(&mut buf)?.to_vec();
+    let g_a = get_bytes(&mut buf)?.to_vec();
+    let server_time = get_i32(&mut buf)?;
+    let len = body.len() - buf.len();
+    Ok((ServerDHInnerData { nonce, server_nonce, g, dh_prime, g_a, server_time }, len))
+}
+
+/// 解析 `Set_client_DH_params_answer`
+pub(crate) fn parse_set_client_dh_params_answer(body: &[u8]) -> Result<SetClientDHParamsAnswer> {
+    let mut buf = Bytes::copy_from_slice(body);
+    let crc = get_u32(&mut buf)?;
+    let nonce = get_array(&mut buf)?;
+    let server_nonce = get_array(&mut buf)?;
+    let hash = get_array(&mut buf)?;
+    match crc {
+        DH_GEN_OK => Ok(SetClientDHParamsAnswer::Ok { nonce, server_nonce, new_nonce_hash1: hash }),
+        DH_GEN_RETRY => Ok(SetClientDHParamsAnswer::Retry { nonce, server_nonce, new_nonce_hash2: hash }),
+        DH_GEN_FAIL => Ok(SetClientDHParamsAnswer::Fail { nonce, server_nonce, new_nonce_hash3: hash }),
+        _ => bail!("invalid Set_client_DH_params_answer {:#x}", crc),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::ByteBuffer;
+
+    use super::*;
+
+    #[test]
+    fn res_pq() {
+        let mut buf = ByteBuffer::new();
+        buf.put_u32(RES_PQ);
+        buf.put_all(&[1; 16]);
+        buf.put_all(&[2; 16]);
+        buf.put_u8(8);
+        buf.put_all(&0x17ED48941A08F981_u64.to_be_bytes());
+        buf.put_all(&[0; 3]);
+        buf.put_u32(VECTOR);
+        buf.put_i32(2);
+        buf.put_i64(-5);
+        buf.put_i64(7);
+        let bytes = buf.to_bytes();
+        let res = parse_res_pq(&bytes).unwrap();
+        assert_eq!((res.nonce, res.server_nonce), ([1; 16], [2; 16]));
+        assert_eq!(res.pq, 0x17ED48941A08F981_u64.to_be_bytes());
+        assert_eq!(res.server_public_key_fingerprints, [-5, 7]);
+
+        // fingerprints 不完整
+        assert!(parse_res_pq(&bytes[..bytes.len() - 4]).is_err());
+    }
+
+    #[test]
+    fn set_client_dh_params_answer() {
+        let answer = |crc: u32| {
+            let mut buf = ByteBuffer::new();
+            buf.put_u32(crc);
+            buf.put_all(&[1; 16]);
+            buf.put_all(&[2; 16]);
+            buf.put_all(&[3; 16]);
+            parse_set_client_dh_params_answer(&buf.to_bytes())
+        };
+        assert!(matches!(answer(DH_GEN_OK).unwrap(), SetClientDHParamsAnswer::Ok { new_nonce_hash1: [3, ..], .. }));
+        assert!(matches!(answer(DH_GEN_RETRY).unwrap(), SetClientDHParamsAnswer::Retry { .. }));
+        assert!(matches!(answer(DH_GEN_FAIL).unwrap(), SetClientDHParamsAnswer::Fail { .. }));
+        assert!(answer(SERVER_DH_PARAMS_OK).is_err());
+    }
+}
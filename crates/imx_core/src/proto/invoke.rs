@@ -0,0 +1,63 @@
+use bytes::Bytes;
+
+use crate::net::AppInfo;
+use crate::proto::ByteBuffer;
+
+const INVOKE_WITH_LAYER: u32 = 0xda9b0d0d;
+const INIT_CONNECTION: u32 = 0xc1cd5ea9;
+
+/// `invokeWithLayer layer:int query:(initConnection ... query:!X)`, 包装新会话的第一个请求,
+/// 服务器据此记录客户端的 layer 和 [AppInfo]. [query] 为已序列化的请求, 响应与 [query] 相同
+pub(crate) fn init_connection(layer: i32, app: &AppInfo, query: &[u8]) -> Bytes {
+    let mut buf = ByteBuffer::with_capacity(query.len() + 128);
+    buf.put_u32(INVOKE_WITH_LAYER);
+    buf.put_i32(layer);
+    buf.put_u32(INIT_CONNECTION);
+    // flags: 不带 proxy 和 params
+    buf.put_i32(0);
+    buf.put_i32(app.api_id);
+    for s in [&app.device_model, &app.system_version, &app.app_version, &app.system_lang_code, &app.lang_pack, &app.lang_code] {
+        put_string(&mut buf, s);
+    }
+    buf.put_all(query);
+    buf.to_bytes()
+}
+
+/// [TL string](https://core.telegram.org/mtproto/serialize#base-types), 与 `service::get_string` 相反
+fn put_string(buf: &mut ByteBuffer, s: &str) {
+    let len = s.len();
+    let header = if len < 254 {
+        buf.put_u8(len as u8);
+        1
+    } else {
+        buf.put_u8(254);
+        buf.put_uint(len as u64, 3);
+        4
+    };
+    buf.put_all(s.as_bytes());
+    for _ in 0..(4 - (header + len) % 4) % 4 {
+        buf.put_u8(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::service::{get_i32, get_string, get_u32};
+
+    use super::*;
+
+    #[test]
+    fn wrap_query() {
+        let app = AppInfo { api_id: 42, device_model: "x".repeat(300), ..Default::default() };
+        let mut buf = init_connection(185, &app, &[1, 2, 3, 4]);
+        assert_eq!(get_u32(&mut buf).unwrap(), INVOKE_WITH_LAYER);
+        assert_eq!(get_i32(&mut buf).unwrap(), 185);
+        assert_eq!(get_u32(&mut buf).unwrap(), INIT_CONNECTION);
+        assert_eq!(get_i32(&mut buf).unwrap(), 0);
+        assert_eq!(get_i32(&mut buf).unwrap(), 42);
+        for s in [&app.device_model, &app.system_version, &app.app_version, &app.system_lang_code, &app.lang_pack, &app.lang_code] {
+            assert_eq!(&get_string(&mut buf).unwrap(), s);
+        }
+        assert_eq!(&buf[..], &[1, 2, 3, 4]);
+    }
+}
@@ -5,14 +5,22 @@ use serde::{Deserialize, Serialize};
 pub use byte_buffer::ByteBuffer;
 pub use funcs::*;
 pub use types::*;
+pub use updates::{ShortChatMessage, ShortMessage, Update};
 use with_crc::WithCrc;
+use zeroize::Zeroizing;
 
 pub mod transport;
 pub mod msg;
 
+pub(crate) mod auth;
+pub(crate) mod config;
 mod funcs;
+pub(crate) mod handshake;
+pub(crate) mod invoke;
+pub(crate) mod service;
 
 mod types;
+mod updates;
 mod byte_buffer;
 
 pub trait MtSer: Serialize + WithCrc + Sized {
@@ -22,7 +30,7 @@ pub trait MtSer: Serialize + WithCrc + Sized {
 }
 
 pub trait MtDe: Deserialize<'static> + WithCrc + Sized {
-    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+    fn from_bytes(_bytes: &[u8]) -> Result<Self> {
         todo!()
     }
 }
@@ -36,26 +44,35 @@ pub trait MtRpc: MtSer {
 
 /// 序列化
 pub fn to_bytes<T: MtSer>(value: &T) -> Result<Bytes> {
+    serialize_into(value, Vec::new()).map(Bytes::from)
+}
+
+/// 序列化到 drop 时清零的缓冲区, 用于包含密钥材料的数据.
+/// [capacity] 需要足够大, 避免扩容时留下没有清零的旧缓冲区
+pub(crate) fn to_zeroizing<T: MtSer>(value: &T, capacity: usize) -> Result<Zeroizing<Vec<u8>>> {
+    serialize_into(value, Vec::with_capacity(capacity)).map(Zeroizing::new)
+}
+
+fn serialize_into<T: MtSer>(value: &T, buf: Vec<u8>) -> Result<Vec<u8>> {
     #[cfg(all(feature = "serde_json", not(feature = "serde_mt")))]
-    {
-        let mut ser = serde_json::Serializer::new(Vec::new());
+    let bytes = {
+        let mut ser = serde_json::Serializer::new(buf);
         with_crc::serialize(value, &mut ser)?;
-        let bytes = ser.into_inner();
-        // println!("----- json: {}", String::from_utf8(bytes.clone())?);
-        return Ok(bytes.into());
-    }
+        // println!("----- json: {}", String::from_utf8(ser.get_ref().clone())?);
+        ser.into_inner()
+    };
     #[cfg(any(feature = "serde_mt", not(feature = "serde_json")))]
-    {
-        let mut ser = serde_mt::Serializer::new(Vec::new());
+    let bytes = {
+        let mut ser = serde_mt::Serializer::new(buf);
         value.crc().serialize(&mut ser)?;
         value.serialize(&mut ser)?;
-        let bytes = ser.into_inner();
-        // println!("----- mt: {:?}", bytes);
-        return Ok(bytes.into());
-    }
+        // println!("----- mt: {:?}", ser.get_ref());
+        ser.into_inner()
+    };
+    Ok(bytes)
 }
 
-pub fn from_bytes<T: MtDe>(bytes: &[u8]) -> Result<T> {
+pub fn from_bytes<T: MtDe>(_bytes: &[u8]) -> Result<T> {
     // #[cfg(all(feature = "serde_json", not(feature = "serde_mt")))]
     // {
     //     let de = serde_json::Deserializer::new(bytes);
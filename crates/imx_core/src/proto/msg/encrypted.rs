@@ -1,11 +1,21 @@
-use anyhow::Result;
-use bytes::Bytes;
+use aes::Aes256;
+use anyhow::{bail, Result};
+use bytes::{Buf, Bytes};
+use cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use cipher::generic_array::GenericArray;
+use log::debug;
+use rand::{RngCore, thread_rng};
 
 use crate::net::{AuthKey, Session};
-use crate::proto::{ByteBuffer, MtSer};
-use crate::proto::msg::MsgWrap;
+use crate::proto::ByteBuffer;
+use crate::proto::msg::{error_code, Error, MsgWrap};
+use crate::{sha1, sha256};
 
-/// 加密消息
+/// 计算 msg_key 和 aes 密钥时 auth_key 的偏移, 客户端发出的消息为 0, 服务器发出的消息为 8
+const X_CLIENT: usize = 0;
+const X_SERVER: usize = 8;
+
+/// 加密消息, 使用 [MTProto 2.0](https://core.telegram.org/mtproto/description#defining-aes-key-and-initialization-vector)
 pub struct Encrypted {
     pub(crate) session: Session,
     pub(crate) auth_key: AuthKey,
@@ -21,16 +31,320 @@ impl Encrypted {
 impl MsgWrap for Encrypted {
     fn wrap(&mut self, data: &[u8]) -> Result<(i64, Bytes)> {
         let msg_id = self.session.new_msg_id();
+        Ok((msg_id, self.wrap_with_msg_id(msg_id, data)?))
+    }
+
+    /// 解密并校验 msg_key, session_id 和长度. 服务器在更换 salt 期间可能仍使用旧的 salt, 这里不校验
+    fn unwrap(&mut self, data: &[u8]) -> Result<(i64, Bytes)> {
+        let plain = decrypt_v2(&self.auth_key, data, X_SERVER)?;
+        // salt + session_id + msg_id + seq_no + length + data + padding (12..=1024)
+        if plain.len() < 32 + 12 { bail!(Error::BadLen(plain.len())); }
+        let buf = &mut &plain[..];
+        let salt = buf.get_i64_le();
+        let session_id = buf.get_i64_le();
+        let msg_id = buf.get_i64_le();
+        let _seq_no = buf.get_i32_le();
+        let len = buf.get_u32_le() as usize;
+
+        let expected = self.session.session_id();
+        if session_id != expected { bail!(Error::SessionId { expected, got: session_id }); }
+        if !buf.len().checked_sub(len).is_some_and(|padding| (12..=1024).contains(&padding)) {
+            bail!(Error::BadLen(len));
+        }
+        if msg_id % 2 == 0 { bail!(Error::MsgId(msg_id)); }
+        if salt != self.session.server_salt() {
+            debug!("Message {} with server salt {}", msg_id, salt);
+        }
+
+        Ok((msg_id, Bytes::copy_from_slice(&buf[..len])))
+    }
+}
+
+impl Encrypted {
+    /// 使用指定的 msg_id 打包, 用于 msg_id 需要出现在消息内容中的请求, 如 `auth.bindTempAuthKey`
+    pub(crate) fn wrap_with_msg_id(&mut self, msg_id: i64, data: &[u8]) -> Result<Bytes> {
         let data_len = data.len();
-        // todo
+        // 至少 12 字节, 总长度为 16 的倍数
+        let padding = 12 + (16 - (32 + data_len + 12) % 16) % 16;
 
-        let mut buf = ByteBuffer::with_capacity(data_len);
-        // todo
+        // salt + session_id + msg_id + seq_no + length + data + padding
+        let mut buf = ByteBuffer::with_capacity(32 + data_len + padding);
+        buf.put_i64(self.session.server_salt());
+        buf.put_i64(self.session.session_id());
+        buf.put_i64(msg_id);
+        buf.put_i32(self.session.next_seq_no(true));
+        buf.put_i32(data_len as i32);
+        buf.put_all(data);
+        let mut random = vec![0; padding];
+        thread_rng().fill_bytes(&mut random);
+        buf.put_all(&random);
 
-        Ok((msg_id, buf.to_bytes()))
+        Ok(Bytes::from(encrypt_v2(&self.auth_key, &buf.to_bytes(), X_CLIENT)))
     }
+}
 
-    fn unwrap(&mut self, data: &[u8]) -> Result<(i64, Bytes)> {
-        todo!()
+/// MTProto 2.0 加密: `auth_key_id + msg_key + AES-IGE(plain)`, [plain] 已经包含 padding
+fn encrypt_v2(auth_key: &AuthKey, plain: &[u8], x: usize) -> Vec<u8> {
+    let key_bytes = auth_key.as_bytes();
+    let msg_key: [u8; 16] = sha256!(&key_bytes[88 + x..120 + x], plain)[8..24].try_into().unwrap();
+    let (key, iv) = kdf_v2(key_bytes, &msg_key, x);
+
+    let mut out = Vec::with_capacity(24 + plain.len());
+    out.extend(auth_key.id.to_le_bytes());
+    out.extend(msg_key);
+    out.extend(aes_ige_encrypt(plain, &key, &iv));
+    out
+}
+
+/// [encrypt_v2] 的逆过程, 校验 auth_key_id 和 msg_key, 返回包含 padding 的明文
+fn decrypt_v2(auth_key: &AuthKey, data: &[u8], x: usize) -> Result<Vec<u8>> {
+    if let Some(e) = error_code(data) { bail!(e); }
+    if data.len() < 24 || (data.len() - 24) % 16 != 0 { bail!(Error::BadLen(data.len())); }
+    let auth_key_id = i64::from_le_bytes(data[..8].try_into().unwrap());
+    if auth_key_id != auth_key.id { bail!(Error::AuthKeyId { expected: auth_key.id, got: auth_key_id }); }
+
+    let key_bytes = auth_key.as_bytes();
+    let msg_key: [u8; 16] = data[8..24].try_into().unwrap();
+    let (key, iv) = kdf_v2(key_bytes, &msg_key, x);
+    let plain = aes_ige_decrypt(&data[24..], &key, &iv);
+    if sha256!(&key_bytes[88 + x..120 + x], &plain)[8..24] != msg_key { bail!(Error::MsgKey); }
+    Ok(plain)
+}
+
+/// 服务器一侧解密客户端发出的消息, 返回 session_id, msg_id 和内容
+#[cfg(test)]
+pub(crate) fn decrypt_client_message(auth_key: &AuthKey, data: &[u8]) -> (i64, i64, Bytes) {
+    let plain = decrypt_v2(auth_key, data, X_CLIENT).unwrap();
+    let buf = &mut &plain[8..];
+    let session_id = buf.get_i64_le();
+    let msg_id = buf.get_i64_le();
+    let _seq_no = buf.get_i32_le();
+    let len = buf.get_u32_le() as usize;
+    (session_id, msg_id, Bytes::copy_from_slice(&buf[..len]))
+}
+
+/// 按服务器的方式加密 [body], 客户端可以用 [Encrypted::unwrap] 解密
+#[cfg(test)]
+pub(crate) fn encrypt_server_message(auth_key: &AuthKey, session_id: i64, msg_id: i64, body: &[u8]) -> Vec<u8> {
+    let mut buf = ByteBuffer::new();
+    buf.put_i64(0);
+    buf.put_i64(session_id);
+    buf.put_i64(msg_id);
+    buf.put_i32(1);
+    buf.put_i32(body.len() as i32);
+    buf.put_all(body);
+    buf.put_all(&vec![0; 12 + (16 - (32 + body.len() + 12) % 16) % 16]);
+    encrypt_v2(auth_key, &buf.to_bytes(), X_SERVER)
+}
+
+/// MTProto 2.0 由 msg_key 计算 aes_key 和 aes_iv
+fn kdf_v2(auth_key: &[u8; 256], msg_key: &[u8; 16], x: usize) -> ([u8; 32], [u8; 32]) {
+    let sha256_a = sha256!(msg_key, &auth_key[x..x + 36]);
+    let sha256_b = sha256!(&auth_key[40 + x..76 + x], msg_key);
+
+    let mut key = [0; 32];
+    key[..8].copy_from_slice(&sha256_a[..8]);
+    key[8..24].copy_from_slice(&sha256_b[8..24]);
+    key[24..].copy_from_slice(&sha256_a[24..]);
+
+    let mut iv = [0; 32];
+    iv[..8].copy_from_slice(&sha256_b[..8]);
+    iv[8..24].copy_from_slice(&sha256_a[8..24]);
+    iv[24..].copy_from_slice(&sha256_b[24..]);
+    (key, iv)
+}
+
+/// 使用 [MTProto 1.0](https://core.telegram.org/mtproto_v1) 格式加密, 只用于
+/// [bind_auth_key_inner](https://core.telegram.org/method/auth.bindTempAuthKey):
+/// `auth_key_id + msg_key + AES-IGE(data + padding)`, [data] 为包含 salt, session_id, msg_id 的完整消息
+pub(crate) fn encrypt_v1(auth_key: &AuthKey, data: &[u8]) -> Vec<u8> {
+    let msg_key: [u8; 16] = sha1!(data)[4..20].try_into().unwrap();
+    let (key, iv) = kdf_v1(auth_key.as_bytes(), &msg_key);
+
+    let mut plain = data.to_vec();
+    let mut padding = vec![0; (16 - data.len() % 16) % 16];
+    thread_rng().fill_bytes(&mut padding);
+    plain.extend(padding);
+
+    let mut out = Vec::with_capacity(24 + plain.len());
+    out.extend(auth_key.id.to_le_bytes());
+    out.extend(msg_key);
+    out.extend(aes_ige_encrypt(&plain, &key, &iv));
+    out
+}
+
+/// [encrypt_v1] 的逆过程, 返回包含 padding 的明文, 服务器一侧使用
+#[cfg(test)]
+pub(crate) fn decrypt_v1(auth_key: &AuthKey, data: &[u8]) -> Vec<u8> {
+    let msg_key: [u8; 16] = data[8..24].try_into().unwrap();
+    let (key, iv) = kdf_v1(auth_key.as_bytes(), &msg_key);
+    aes_ige_decrypt(&data[24..], &key, &iv)
+}
+
+/// MTProto 1.0 由 msg_key 计算 aes_key 和 aes_iv, 客户端发出的消息 x = 0
+fn kdf_v1(auth_key: &[u8; 256], msg_key: &[u8; 16]) -> ([u8; 32], [u8; 32]) {
+    let sha1_a = sha1!(msg_key, &auth_key[0..32]);
+    let sha1_b = sha1!(&auth_key[32..48], msg_key, &auth_key[48..64]);
+    let sha1_c = sha1!(&auth_key[64..96], msg_key);
+    let sha1_d = sha1!(msg_key, &auth_key[96..128]);
+
+    let mut key = [0; 32];
+    key[..8].copy_from_slice(&sha1_a[..8]);
+    key[8..20].copy_from_slice(&sha1_b[8..20]);
+    key[20..].copy_from_slice(&sha1_c[4..16]);
+
+    let mut iv = [0; 32];
+    iv[..12].copy_from_slice(&sha1_a[8..20]);
+    iv[12..20].copy_from_slice(&sha1_b[..8]);
+    iv[20..24].copy_from_slice(&sha1_c[16..20]);
+    iv[24..].copy_from_slice(&sha1_d[..8]);
+    (key, iv)
+}
+
+/// AES-256-IGE, [data] 长度必须是 16 的倍数. iv 的前 16 字节为 c0, 后 16 字节为 p0
+pub(crate) fn aes_ige_encrypt(data: &[u8], key: &[u8; 32], iv: &[u8; 32]) -> Vec<u8> {
+    debug_assert_eq!(data.len() % 16, 0);
+    let cipher = Aes256::new(key.into());
+    let mut prev_c: [u8; 16] = iv[..16].try_into().unwrap();
+    let mut prev_p: [u8; 16] = iv[16..].try_into().unwrap();
+
+    let mut out = Vec::with_capacity(data.len());
+    for chunk in data.chunks_exact(16) {
+        let mut block = GenericArray::clone_from_slice(chunk);
+        block.iter_mut().zip(prev_c).for_each(|(b, c)| *b ^= c);
+        cipher.encrypt_block(&mut block);
+        block.iter_mut().zip(prev_p).for_each(|(b, p)| *b ^= p);
+
+        prev_p.copy_from_slice(chunk);
+        prev_c.copy_from_slice(&block);
+        out.extend_from_slice(&block);
+    }
+    out
+}
+
+/// [aes_ige_encrypt] 的逆过程
+pub(crate) fn aes_ige_decrypt(data: &[u8], key: &[u8; 32], iv: &[u8; 32]) -> Vec<u8> {
+    debug_assert_eq!(data.len() % 16, 0);
+    let cipher = Aes256::new(key.into());
+    let mut prev_c: [u8; 16] = iv[..16].try_into().unwrap();
+    let mut prev_p: [u8; 16] = iv[16..].try_into().unwrap();
+
+    let mut out = Vec::with_capacity(data.len());
+    for chunk in data.chunks_exact(16) {
+        let mut block = GenericArray::clone_from_slice(chunk);
+        block.iter_mut().zip(prev_p).for_each(|(b, p)| *b ^= p);
+        cipher.decrypt_block(&mut block);
+        block.iter_mut().zip(prev_c).for_each(|(b, c)| *b ^= c);
+
+        prev_c.copy_from_slice(chunk);
+        prev_p.copy_from_slice(&block);
+        out.extend_from_slice(&block);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ige_with_zero_iv() {
+        let key = [7; 32];
+        let data = [1; 16];
+        // 第一个块与 ECB 相同
+        let mut block = GenericArray::clone_from_slice(&data);
+        Aes256::new(&key.into()).encrypt_block(&mut block);
+        assert_eq!(aes_ige_encrypt(&data, &key, &[0; 32]), block.to_vec());
+    }
+
+    #[test]
+    fn ige_round_trip() {
+        let (key, iv) = ([7; 32], [9; 32]);
+        let data: Vec<u8> = (0..64).collect();
+        assert_eq!(aes_ige_decrypt(&aes_ige_encrypt(&data, &key, &iv), &key, &iv), data);
     }
-}
\ No newline at end of file
+
+    /// 各个字节不同, 否则客户端和服务器方向使用的密钥部分相同
+    fn test_key(seed: u8) -> AuthKey {
+        AuthKey::from_bytes(core::array::from_fn(|i| (i as u8).wrapping_mul(31).wrapping_add(seed)))
+    }
+
+    /// 按服务器的方式打包 [body]
+    fn server_message(auth_key: &AuthKey, session_id: i64, msg_id: i64, body: &[u8], padding: usize) -> Vec<u8> {
+        let mut buf = ByteBuffer::new();
+        buf.put_i64(1);
+        buf.put_i64(session_id);
+        buf.put_i64(msg_id);
+        buf.put_i32(1);
+        buf.put_i32(body.len() as i32);
+        buf.put_all(body);
+        buf.put_all(&vec![0; padding]);
+        encrypt_v2(auth_key, &buf.to_bytes(), X_SERVER)
+    }
+
+    #[test]
+    fn unwrap_server_message() {
+        let auth_key = test_key(3);
+        let session = Session::new();
+        let mut wrap = Encrypted::new(session.clone(), auth_key.clone());
+
+        let data = server_message(&auth_key, session.session_id(), 101, b"body", 12);
+        assert_eq!(wrap.unwrap(&data).unwrap(), (101, Bytes::from_static(b"body")));
+
+        let error = |data: &[u8], wrap: &mut Encrypted| wrap.unwrap(data).unwrap_err().downcast::<Error>().unwrap();
+        // 篡改密文
+        let mut tampered = data.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(error(&tampered, &mut wrap), Error::MsgKey);
+        // 客户端方向的消息不能被当作服务器消息
+        let mut client = Encrypted::new(session.clone(), auth_key.clone());
+        let (_, sent) = client.wrap(b"ping").unwrap();
+        assert_eq!(error(&sent, &mut wrap), Error::MsgKey);
+
+        let data = server_message(&auth_key, session.session_id() ^ 1, 101, b"body", 12);
+        assert!(matches!(error(&data, &mut wrap), Error::SessionId { .. }));
+        let data = server_message(&auth_key, session.session_id(), 100, b"body", 12);
+        assert_eq!(error(&data, &mut wrap), Error::MsgId(100));
+        // padding 不足 12 字节
+        let data = server_message(&auth_key, session.session_id(), 101, &[0; 12], 4);
+        assert_eq!(error(&data, &mut wrap), Error::BadLen(12));
+
+        let other = Encrypted::new(session, test_key(4)).wrap(b"ping").unwrap().1;
+        assert!(matches!(error(&other, &mut wrap), Error::AuthKeyId { .. }));
+        assert_eq!(error(&(-404i32).to_le_bytes(), &mut wrap), Error::Code(-404));
+    }
+
+    #[test]
+    fn wrap_layout() {
+        let auth_key = test_key(3);
+        let session = Session::new();
+        session.set_server_salt(42);
+        let mut wrap = Encrypted::new(session.clone(), auth_key.clone());
+        let (msg_id, data) = wrap.wrap(&[5; 20]).unwrap();
+        let (_, data2) = wrap.wrap(&[5; 20]).unwrap();
+
+        let plain = decrypt_v2(&auth_key, &data, X_CLIENT).unwrap();
+        assert_eq!(plain.len() % 16, 0);
+        assert!(plain.len() - 32 - 20 >= 12);
+        let buf = &mut &plain[..];
+        assert_eq!(buf.get_i64_le(), 42);
+        assert_eq!(buf.get_i64_le(), session.session_id());
+        assert_eq!(buf.get_i64_le(), msg_id);
+        assert_eq!(buf.get_i32_le(), 1);
+        assert_eq!(buf.get_i32_le(), 20);
+        assert_eq!(&buf[..20], &[5; 20]);
+        // 内容相关的消息 seq_no 递增
+        assert_eq!(decrypt_v2(&auth_key, &data2, X_CLIENT).unwrap()[24..28], 3i32.to_le_bytes());
+    }
+
+    #[test]
+    fn encrypt_v1_layout() {
+        let auth_key = AuthKey::from_bytes([3; 256]);
+        let data = [5; 40];
+        let out = encrypt_v1(&auth_key, &data);
+        assert_eq!(out.len(), 8 + 16 + 48);
+        assert_eq!(out[..8], auth_key.id.to_le_bytes());
+        assert_eq!(out[8..24], sha1!(&data)[4..20]);
+        assert_eq!(decrypt_v1(&auth_key, &out)[..40], data);
+    }
+}
@@ -1,9 +1,11 @@
-use crate::proto::MtSer;
-
 pub use encrypted::Encrypted;
+pub(crate) use encrypted::{aes_ige_decrypt, aes_ige_encrypt, encrypt_v1};
+#[cfg(test)]
+pub(crate) use encrypted::{decrypt_client_message, decrypt_v1, encrypt_server_message};
 pub use unencrypted::Unencrypted;
 use anyhow::Result;
 use bytes::Bytes;
+use thiserror::Error;
 
 mod encrypted;
 mod unencrypted;
@@ -14,4 +16,30 @@ pub trait MsgWrap {
     fn wrap(&mut self, data: &[u8]) -> Result<(i64, Bytes)>;
 
     fn unwrap(&mut self, data: &[u8]) -> Result<(i64, Bytes)>;
-}
\ No newline at end of file
+}
+
+/// 解包收到的消息失败, 连接需要断开重连
+#[derive(Error, Clone, Debug, PartialEq)]
+pub enum Error {
+    /// 服务器返回的 4 字节错误码, 例如 `-404` 表示服务器没有对应的 auth_key
+    #[error("server error code {0}")]
+    Code(i32),
+    #[error("bad message length {0}")]
+    BadLen(usize),
+    #[error("unexpected auth_key_id (expected {expected:x}, got {got:x})")]
+    AuthKeyId { expected: i64, got: i64 },
+    /// 解密后计算的 msg_key 与收到的不一致, 数据被篡改或使用了错误的密钥
+    #[error("msg_key mismatch")]
+    MsgKey,
+    #[error("unexpected session_id (expected {expected:x}, got {got:x})")]
+    SessionId { expected: i64, got: i64 },
+    /// 服务器发出的 msg_id 必须是奇数
+    #[error("bad msg_id {0}")]
+    MsgId(i64),
+}
+
+/// 服务器返回的错误码长度固定为 4 字节
+fn error_code(data: &[u8]) -> Option<Error> {
+    let code: [u8; 4] = data.try_into().ok()?;
+    Some(Error::Code(i32::from_le_bytes(code)))
+}
@@ -1,9 +1,9 @@
-use anyhow::Result;
-use bytes::Bytes;
+use anyhow::{bail, Result};
+use bytes::{Buf, Bytes};
 
 use crate::net::Session;
-use crate::proto::msg::MsgWrap;
-use crate::proto::{ByteBuffer, MtSer};
+use crate::proto::msg::{error_code, Error, MsgWrap};
+use crate::proto::ByteBuffer;
 
 /// 非加密消息
 pub struct Unencrypted {
@@ -30,12 +30,42 @@ impl MsgWrap for Unencrypted {
         // message_data_length 32-bits
         buf.put_u32(data_len as u32);
         // message_data
-        buf.put_all(&data);
+        buf.put_all(data);
 
         Ok((msg_id, buf.to_bytes()))
     }
 
+    /// [data] 末尾可能带有 transport 的 padding, 按 message_data_length 截取
     fn unwrap(&mut self, data: &[u8]) -> Result<(i64, Bytes)> {
-        todo!()
+        if let Some(e) = error_code(data) { bail!(e); }
+        if data.len() < 20 { bail!(Error::BadLen(data.len())); }
+        let buf = &mut &data[..];
+        let auth_key_id = buf.get_i64_le();
+        if auth_key_id != 0 { bail!(Error::AuthKeyId { expected: 0, got: auth_key_id }); }
+        let msg_id = buf.get_i64_le();
+        let len = buf.get_u32_le() as usize;
+        if buf.len() < len { bail!(Error::BadLen(len)); }
+
+        Ok((msg_id, Bytes::copy_from_slice(&buf[..len])))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_and_unwrap() {
+        let mut wrap = Unencrypted::new(Session::new());
+        let (msg_id, mut data) = wrap.wrap(b"req_pq").map(|(id, data)| (id, data.to_vec())).unwrap();
+        // PaddedIntermediate 的 padding
+        data.extend([0xff; 3]);
+        assert_eq!(wrap.unwrap(&data).unwrap(), (msg_id, Bytes::from_static(b"req_pq")));
+
+        let error = |data: &[u8]| Unencrypted::new(Session::new()).unwrap(data).unwrap_err().downcast::<Error>().unwrap();
+        assert_eq!(error(&data[..24]), Error::BadLen(6));
+        assert_eq!(error(&(-404i32).to_le_bytes()), Error::Code(-404));
+        data[0] = 1;
+        assert_eq!(error(&data), Error::AuthKeyId { expected: 0, got: 1 });
     }
 }
\ No newline at end of file
@@ -0,0 +1,215 @@
+use anyhow::{bail, Result};
+use bytes::{Buf, Bytes};
+
+const RPC_RESULT: u32 = 0xf35c6d01;
+const RPC_ERROR: u32 = 0x2144ca19;
+const BAD_SERVER_SALT: u32 = 0xedab447b;
+const BAD_MSG_NOTIFICATION: u32 = 0xa7eff811;
+const MSG_CONTAINER: u32 = 0x73f1f8dc;
+const PONG: u32 = 0x347773c5;
+const NEW_SESSION_CREATED: u32 = 0x9ec20908;
+const MSGS_ACK: u32 = 0x62d6b459;
+const VECTOR: u32 = 0x1cb5c415;
+
+/// 解密后收到的消息, [Service Messages](https://core.telegram.org/mtproto/service_messages)
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Incoming {
+    /// `rpc_result#f35c6d01 req_msg_id:long result:Object`
+    RpcResult { req_msg_id: i64, result: Bytes },
+    /// `rpc_error#2144ca19 error_code:int error_message:string`, 作为 `rpc_result` 的结果返回
+    RpcError { req_msg_id: i64, code: i32, message: String },
+    /// `bad_server_salt#edab447b`, 需要使用新的 salt 重发
+    BadServerSalt { bad_msg_id: i64, new_server_salt: i64 },
+    /// `bad_msg_notification#a7eff811`
+    BadMsgNotification { msg_id: i64, bad_msg_id: i64, error_code: i32 },
+    /// `pong#347773c5 msg_id:long ping_id:long`
+    Pong { ping_msg_id: i64, ping_id: i64 },
+    /// `new_session_created#9ec20908 first_msg_id:long unique_id:long server_salt:long`
+    NewSessionCreated { first_msg_id: i64, server_salt: i64 },
+    /// `msgs_ack#62d6b459 msg_ids:Vector<long>`
+    MsgsAck { msg_ids: Vec<i64> },
+    /// 其他消息, 都视为服务器推送的 updates
+    Update { msg_id: i64, body: Bytes },
+}
+
+/// 解析一条消息, `msg_container` 会被展开
+pub(crate) fn parse(msg_id: i64, body: Bytes, out: &mut Vec<Incoming>) -> Result<()> {
+    let mut peek = body.clone();
+    let crc = get_u32(&mut peek)?;
+    match crc {
+        RPC_RESULT => {
+            let req_msg_id = get_i64(&mut peek)?;
+            let mut result = peek.clone();
+            if result.len() >= 4 && result.get_u32_le() == RPC_ERROR {
+                let code = get_i32(&mut result)?;
+                let message = get_string(&mut result)?;
+                out.push(Incoming::RpcError { req_msg_id, code, message });
+            } else {
+                out.push(Incoming::RpcResult { req_msg_id, result: peek });
+            }
+        }
+        BAD_SERVER_SALT => {
+            let bad_msg_id = get_i64(&mut peek)?;
+            let _bad_msg_seqno = get_i32(&mut peek)?;
+            let _error_code = get_i32(&mut peek)?;
+            let new_server_salt = get_i64(&mut peek)?;
+            out.push(Incoming::BadServerSalt { bad_msg_id, new_server_salt });
+        }
+        BAD_MSG_NOTIFICATION => {
+            let bad_msg_id = get_i64(&mut peek)?;
+            let _bad_msg_seqno = get_i32(&mut peek)?;
+            let error_code = get_i32(&mut peek)?;
+            out.push(Incoming::BadMsgNotification { msg_id, bad_msg_id, error_code });
+        }
+        PONG => {
+            let ping_msg_id = get_i64(&mut peek)?;
+            let ping_id = get_i64(&mut peek)?;
+            out.push(Incoming::Pong { ping_msg_id, ping_id });
+        }
+        NEW_SESSION_CREATED => {
+            let first_msg_id = get_i64(&mut peek)?;
+            let _unique_id = get_i64(&mut peek)?;
+            let server_salt = get_i64(&mut peek)?;
+            out.push(Incoming::NewSessionCreated { first_msg_id, server_salt });
+        }
+        MSGS_ACK => {
+            if get_u32(&mut peek)? != VECTOR { bail!("invalid msgs_ack"); }
+            let count = get_i32(&mut peek)?;
+            let msg_ids = (0..count).map(|_| get_i64(&mut peek)).collect::<Result<_>>()?;
+            out.push(Incoming::MsgsAck { msg_ids });
+        }
+        MSG_CONTAINER => {
+            let count = get_i32(&mut peek)?;
+            for _ in 0..count {
+                let msg_id = get_i64(&mut peek)?;
+                let _seqno = get_i32(&mut peek)?;
+                let len = get_i32(&mut peek)? as usize;
+                if peek.len() < len { bail!("message too short"); }
+                parse(msg_id, peek.split_to(len), out)?;
+            }
+        }
+        _ => {
+            out.push(Incoming::Update { msg_id, body });
+        }
+    }
+    Ok(())
+}
+
+pub(super) fn get_u32(buf: &mut Bytes) -> Result<u32> {
+    if buf.len() < 4 { bail!("message too short"); }
+    Ok(buf.get_u32_le())
+}
+
+pub(super) fn get_i32(buf: &mut Bytes) -> Result<i32> {
+    if buf.len() < 4 { bail!("message too short"); }
+    Ok(buf.get_i32_le())
+}
+
+pub(super) fn get_i64(buf: &mut Bytes) -> Result<i64> {
+    if buf.len() < 8 { bail!("message too short"); }
+    Ok(buf.get_i64_le())
+}
+
+/// `int128` 和 `int256`, 按原始字节读取
+pub(super) fn get_array<const N: usize>(buf: &mut Bytes) -> Result<[u8; N]> {
+    if buf.len() < N { bail!("message too short"); }
+    let mut array = [0; N];
+    buf.copy_to_slice(&mut array);
+    Ok(array)
+}
+
+/// [TL string](https://core.telegram.org/mtproto/serialize#base-types), 长度 + 数据 + 4 字节对齐
+pub(super) fn get_string(buf: &mut Bytes) -> Result<String> {
+    let bytes = get_bytes(buf)?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// TL bytes, 编码与 string 相同
+pub(super) fn get_bytes(buf: &mut Bytes) -> Result<Bytes> {
+    if buf.is_empty() { bail!("message too short"); }
+    let (len, header) = match buf.get_u8() {
+        254 => {
+            if buf.len() < 3 { bail!("message too short"); }
+            (buf.get_uint_le(3) as usize, 4)
+        }
+        n => (n as usize, 1),
+    };
+    let padding = (4 - (header + len) % 4) % 4;
+    if buf.len() < len + padding { bail!("message too short"); }
+    let bytes = buf.split_to(len);
+    buf.advance(padding);
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::ByteBuffer;
+
+    use super::*;
+
+    fn rpc_error(req_msg_id: i64, code: i32, message: &str) -> ByteBuffer {
+        let mut buf = ByteBuffer::new();
+        buf.put_u32(RPC_RESULT);
+        buf.put_i64(req_msg_id);
+        buf.put_u32(RPC_ERROR);
+        buf.put_i32(code);
+        buf.put_u8(message.len() as u8);
+        buf.put_all(message.as_bytes());
+        for _ in 0..(4 - (1 + message.len()) % 4) % 4 {
+            buf.put_u8(0);
+        }
+        buf
+    }
+
+    #[test]
+    fn parse_container() {
+        let mut salt = ByteBuffer::new();
+        salt.put_u32(BAD_SERVER_SALT);
+        salt.put_i64(100);
+        salt.put_i32(1);
+        salt.put_i32(48);
+        salt.put_i64(0x1234);
+        let error = rpc_error(104, 420, "FLOOD_WAIT_3");
+
+        let mut buf = ByteBuffer::new();
+        buf.put_u32(MSG_CONTAINER);
+        buf.put_i32(2);
+        for (msg_id, body) in [(1, salt), (2, error)] {
+            buf.put_i64(msg_id);
+            buf.put_i32(0);
+            buf.put_i32(body.len() as i32);
+            buf.put_all(&body.to_bytes());
+        }
+
+        let mut out = vec![];
+        parse(0, buf.to_bytes(), &mut out).unwrap();
+        assert_eq!(out, vec![
+            Incoming::BadServerSalt { bad_msg_id: 100, new_server_salt: 0x1234 },
+            Incoming::RpcError { req_msg_id: 104, code: 420, message: "FLOOD_WAIT_3".into() },
+        ]);
+    }
+
+    #[test]
+    fn parse_rpc_result() {
+        let mut buf = ByteBuffer::new();
+        buf.put_u32(RPC_RESULT);
+        buf.put_i64(8);
+        buf.put_u32(0x347773c5);
+        let mut out = vec![];
+        parse(0, buf.to_bytes(), &mut out).unwrap();
+        assert_eq!(out, vec![Incoming::RpcResult { req_msg_id: 8, result: Bytes::from_static(&[0xc5, 0x73, 0x77, 0x34]) }]);
+
+        // 不认识的消息作为 updates
+        let mut buf = ByteBuffer::new();
+        buf.put_u32(0x74ae4240);
+        let body = buf.to_bytes();
+        out.clear();
+        parse(12, body.clone(), &mut out).unwrap();
+        assert_eq!(out, vec![Incoming::Update { msg_id: 12, body }]);
+
+        // 长度不足
+        let mut buf = rpc_error(8, 400, "BAD_REQUEST").to_bytes();
+        buf.truncate(buf.len() - 4);
+        assert!(parse(0, buf, &mut out).is_err());
+    }
+}
@@ -1,6 +1,4 @@
 use anyhow::{bail, Result};
-use bytes::BufMut;
-use log::info;
 use crate::proto::ByteBuffer;
 
 use crate::proto::transport::{Error, Obfuscation, Transport};
@@ -28,7 +26,7 @@ use crate::proto::transport::{Error, Obfuscation, Transport};
 ///
 /// Then, payloads are wrapped in the following envelope:
 /// - Length: payload length, divided by four, and encoded as a single byte, only if the resulting
-/// packet length is a value between `0x01..0x7e`.
+///   packet length is a value between `0x01..0x7e`.
 /// - Payload: the MTProto payload
 ///
 /// If the packet length divided by four is bigger than or equal to 127 (>= `0x7f`), the following
@@ -59,13 +57,29 @@ impl Abridged {
         }
     }
 
-    pub fn obfuscation(self, secret: Option<String>) -> Self {
+    pub fn obfuscation(self, secret: Option<Vec<u8>>, dc_id: i16) -> Self {
         Self {
-            obfuscation: secret.map(|s| Obfuscation::new(s)),
+            obfuscation: secret.map(|s| Obfuscation::new(s, dc_id)),
             ack: self.ack,
             first_packet_sent: self.first_packet_sent,
         }
     }
+
+    /// 服务器一侧的传输协议, 用于测试收发
+    #[cfg(test)]
+    pub(super) fn peer(&self) -> Self {
+        Self {
+            obfuscation: self.obfuscation.as_ref().map(Obfuscation::peer),
+            ack: self.ack,
+            first_packet_sent: true,
+        }
+    }
+}
+
+impl Default for Abridged {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Transport for Abridged {
@@ -107,43 +121,37 @@ impl Transport for Abridged {
         let end = output.len();
 
         if let Some(obf) = &mut self.obfuscation {
-            info!("encrypt before: {:?}", output);
+            #[cfg(feature = "trace")]
+            log::trace!("(abridged) Obfuscate {} bytes: {:?}", end - start, &output[start..end]);
             obf.aes256_ctr128_encrypt(&mut output[start..end]);
-            info!("encrypt after: {:?}", output);
         }
     }
 
     fn unpack(&mut self, input: &[u8], output: &mut ByteBuffer) -> Result<usize> {
-        if input.is_empty() { bail!(Error::MissingBytes); }
-
-        // todo obfuscation
-        // match &mut self.obfuscation {
-        //     Some(obf) => {
-        //         obf.aes256_ctr128_decrypt(input)
-        //     }
-        //     None => {
-        //         // todo
-        //     }
-        // }
-
-        let header_len;
-        let len = input[0];
-        let len = if len < 0x7f {
-            header_len = 1;
-            len as u32
-        } else {
-            if input.len() < 4 { bail!(Error::MissingBytes); }
+        Obfuscation::unpack_with(&mut self.obfuscation, input, |input| unpack(input, output))
+    }
+}
 
-            header_len = 4;
-            let mut len = [0; 4];
-            len[..3].copy_from_slice(&input[1..4]);
-            u32::from_le_bytes(len)
-        };
+fn unpack(input: &[u8], output: &mut ByteBuffer) -> Result<usize> {
+    if input.is_empty() { bail!(Error::MissingBytes); }
 
-        let len = len as usize * 4;
-        if input.len() < header_len + len { bail!(Error::MissingBytes); }
+    let header_len;
+    let len = input[0];
+    let len = if len < 0x7f {
+        header_len = 1;
+        len as u32
+    } else {
+        if input.len() < 4 { bail!(Error::MissingBytes); }
 
-        output.put_all(&input[header_len..header_len + len]);
-        Ok(header_len + len)
-    }
+        header_len = 4;
+        let mut len = [0; 4];
+        len[..3].copy_from_slice(&input[1..4]);
+        u32::from_le_bytes(len)
+    };
+
+    let len = len as usize * 4;
+    if input.len() < header_len + len { bail!(Error::MissingBytes); }
+
+    output.put_all(&input[header_len..header_len + len]);
+    Ok(header_len + len)
 }
\ No newline at end of file
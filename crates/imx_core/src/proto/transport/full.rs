@@ -1,5 +1,5 @@
 use anyhow::{bail, Result};
-use bytes::{Buf, BufMut};
+use bytes::Buf;
 
 use crate::proto::ByteBuffer;
 use crate::proto::transport::{Error, Obfuscation, Transport};
@@ -20,10 +20,10 @@ use crate::proto::transport::{Error, Obfuscation, Transport};
 /// Envelope description:
 ///
 /// - Length: length+seqno+payload+crc length encoded as 4 length bytes (little endian, the length
-/// of the length field must be included, too)
+///   of the length field must be included, too)
 /// - Seqno: the TCP sequence number for this TCP connection (different from the
-/// [MTProto sequence number](https://core.telegram.org/mtproto/description#message-sequence-number-msg-seqno)):
-/// the first packet sent is numbered 0, the next one 1, etc.
+///   [MTProto sequence number](https://core.telegram.org/mtproto/description#message-sequence-number-msg-seqno)):
+///   the first packet sent is numbered 0, the next one 1, etc.
 /// - payload: MTProto payload
 /// - crc: 4 CRC32 bytes computed using length, sequence number, and payload together.
 pub struct Full {
@@ -55,15 +55,33 @@ impl Full {
         }
     }
 
-    pub fn obfuscation(self, secret: Option<String>) -> Self {
+    pub fn obfuscation(self, secret: Option<Vec<u8>>, dc_id: i16) -> Self {
         Self {
-            obfuscation: secret.map(|s| Obfuscation::new(s)),
+            obfuscation: secret.map(|s| Obfuscation::new(s, dc_id)),
             ack: self.ack,
             first_packet_sent: self.first_packet_sent,
             send_seq: self.send_seq,
             recv_seq: self.recv_seq,
         }
     }
+
+    /// 服务器一侧的传输协议, 用于测试收发
+    #[cfg(test)]
+    pub(super) fn peer(&self) -> Self {
+        Self {
+            obfuscation: self.obfuscation.as_ref().map(Obfuscation::peer),
+            ack: self.ack,
+            first_packet_sent: true,
+            send_seq: 0,
+            recv_seq: 0,
+        }
+    }
+}
+
+impl Default for Full {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Transport for Full {
@@ -91,39 +109,49 @@ impl Transport for Full {
         let crc = crc32fast::hash(&output[buf_start..]);
         output.put_u32(crc);
 
+        if let Some(obf) = &mut self.obfuscation {
+            let end = output.len();
+            obf.aes256_ctr128_encrypt(&mut output[buf_start..end]);
+        }
+
         self.send_seq += 1;
     }
 
     fn unpack(&mut self, input: &[u8], output: &mut ByteBuffer) -> Result<usize> {
-        // Need 4 bytes for the initial length
-        if input.len() < 4 { bail!(Error::MissingBytes); }
+        let recv_seq = &mut self.recv_seq;
+        Obfuscation::unpack_with(&mut self.obfuscation, input, |input| unpack(recv_seq, input, output))
+    }
+}
 
-        let total_len = input.len();
-        let slice = &mut &input[..];
+fn unpack(recv_seq: &mut u32, input: &[u8], output: &mut ByteBuffer) -> Result<usize> {
+    // Need 4 bytes for the initial length
+    if input.len() < 4 { bail!(Error::MissingBytes); }
 
-        // payload len
-        let len = slice.get_u32_le() as usize;
-        if len < 12 { bail!(Error::BadLen { got: len as u32 }); }
+    let total_len = input.len();
+    let slice = &mut &input[..];
 
-        if total_len < len { bail!(Error::MissingBytes); }
+    // payload len
+    let len = slice.get_u32_le() as usize;
+    if len < 12 { bail!(Error::BadLen { got: len as u32 }); }
 
-        // receive seq
-        let seq = slice.get_u32_le();
-        if seq != self.recv_seq { bail!(Error::BadSeq { expected: self.recv_seq, got: seq }); }
+    if total_len < len { bail!(Error::MissingBytes); }
 
-        // skip payload for now
-        slice.advance(len - 12);
+    // receive seq
+    let seq = slice.get_u32_le();
+    if seq != *recv_seq { bail!(Error::BadSeq { expected: *recv_seq, got: seq }); }
 
-        // crc32
-        let crc = slice.get_u32_le();
+    // skip payload for now
+    slice.advance(len - 12);
 
-        // 验证 crc
-        let valid_crc = crc32fast::hash(&input[..len - 4]);
-        if crc != valid_crc { bail!(Error::BadCrc { expected: valid_crc, got: crc}); }
+    // crc32
+    let crc = slice.get_u32_le();
 
-        self.recv_seq += 1;
+    // 验证 crc
+    let valid_crc = crc32fast::hash(&input[..len - 4]);
+    if crc != valid_crc { bail!(Error::BadCrc { expected: valid_crc, got: crc}); }
 
-        output.put_all(&input[8..len - 4]);
-        Ok(len)
-    }
+    *recv_seq += 1;
+
+    output.put_all(&input[8..len - 4]);
+    Ok(len)
 }
\ No newline at end of file
@@ -1,5 +1,5 @@
 use anyhow::{bail, Result};
-use bytes::{Buf, BufMut};
+use bytes::Buf;
 
 use crate::proto::ByteBuffer;
 use crate::proto::transport::{Error, Obfuscation, Transport};
@@ -47,13 +47,29 @@ impl Intermediate {
         }
     }
 
-    pub fn obfuscation(self, secret: Option<String>) -> Self {
+    pub fn obfuscation(self, secret: Option<Vec<u8>>, dc_id: i16) -> Self {
         Self {
-            obfuscation: secret.map(|s| Obfuscation::new(s)),
+            obfuscation: secret.map(|s| Obfuscation::new(s, dc_id)),
             ack: self.ack,
             first_packet_sent: self.first_packet_sent,
         }
     }
+
+    /// 服务器一侧的传输协议, 用于测试收发
+    #[cfg(test)]
+    pub(super) fn peer(&self) -> Self {
+        Self {
+            obfuscation: self.obfuscation.as_ref().map(Obfuscation::peer),
+            ack: self.ack,
+            first_packet_sent: true,
+        }
+    }
+}
+
+impl Default for Intermediate {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Transport for Intermediate {
@@ -85,15 +101,19 @@ impl Transport for Intermediate {
     }
 
     fn unpack(&mut self, input: &[u8], output: &mut ByteBuffer) -> Result<usize> {
-        if input.len() < 4 { bail!(Error::MissingBytes); }
+        Obfuscation::unpack_with(&mut self.obfuscation, input, |input| unpack(input, output))
+    }
+}
 
-        let slice = &mut &input[..];
+fn unpack(input: &[u8], output: &mut ByteBuffer) -> Result<usize> {
+    if input.len() < 4 { bail!(Error::MissingBytes); }
 
-        let len = slice.get_u32_le() as usize;
-        if slice.len() < len { bail!(Error::MissingBytes); }
+    let slice = &mut &input[..];
 
-        output.put_all(&slice[..len]);
+    let len = slice.get_u32_le() as usize;
+    if slice.len() < len { bail!(Error::MissingBytes); }
 
-        Ok(len + 4)
-    }
+    output.put_all(&slice[..len]);
+
+    Ok(len + 4)
 }
\ No newline at end of file
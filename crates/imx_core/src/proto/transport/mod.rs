@@ -1,10 +1,13 @@
 use std::cmp::min;
+use std::fmt::{Debug, Formatter};
 
 use anyhow::Result;
 use cipher::{KeyIvInit, StreamCipher, StreamCipherCoreWrapper};
 use rand::{RngCore, thread_rng};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use thiserror::Error;
+use zeroize::Zeroizing;
 
 pub use abridged::Abridged;
 pub use full::Full;
@@ -25,10 +28,13 @@ pub trait Transport {
     fn pack(&mut self, input: &[u8], output: &mut ByteBuffer);
 
     /// If ok, returns how many bytes of `input` were used.
+    /// 返回 [Error::MissingBytes] 时, 调用方需要在收到更多数据后用包含原有数据的 `input` 再次调用.
+    /// [PaddedIntermediate] 输出的数据末尾带有随机 padding, 消息层按自身的长度字段截取
     fn unpack(&mut self, input: &[u8], output: &mut ByteBuffer) -> Result<usize>;
 }
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum TransportType {
     Abridged = 0xef,
     Intermediate = 0xee,
@@ -36,6 +42,55 @@ pub enum TransportType {
     Full,
 }
 
+/// 运行时可选的 [Transport], 由 [TransportType] 决定具体实现
+pub enum TransportImpl {
+    Abridged(Abridged),
+    Intermediate(Intermediate),
+    PaddedIntermediate(PaddedIntermediate),
+    Full(Full),
+}
+
+impl TransportImpl {
+    /// [secret] 用于 obfuscation, 为 `None` 时不混淆, 为空时混淆但不使用密钥. [dc_id] 为编码后的 DC id, 写入 obfuscation header 供 MTProxy 转发
+    pub fn new(typo: TransportType, secret: Option<Vec<u8>>, dc_id: i16) -> Self {
+        match typo {
+            TransportType::Abridged => Self::Abridged(Abridged::new().obfuscation(secret, dc_id)),
+            TransportType::Intermediate => Self::Intermediate(Intermediate::new().obfuscation(secret, dc_id)),
+            TransportType::PaddedIntermediate => Self::PaddedIntermediate(PaddedIntermediate::new().obfuscation(secret, dc_id)),
+            TransportType::Full => Self::Full(Full::new().obfuscation(secret, dc_id)),
+        }
+    }
+
+    pub fn typo(&self) -> TransportType {
+        match self {
+            Self::Abridged(_) => TransportType::Abridged,
+            Self::Intermediate(_) => TransportType::Intermediate,
+            Self::PaddedIntermediate(_) => TransportType::PaddedIntermediate,
+            Self::Full(_) => TransportType::Full,
+        }
+    }
+}
+
+impl Transport for TransportImpl {
+    fn pack(&mut self, input: &[u8], output: &mut ByteBuffer) {
+        match self {
+            Self::Abridged(t) => t.pack(input, output),
+            Self::Intermediate(t) => t.pack(input, output),
+            Self::PaddedIntermediate(t) => t.pack(input, output),
+            Self::Full(t) => t.pack(input, output),
+        }
+    }
+
+    fn unpack(&mut self, input: &[u8], output: &mut ByteBuffer) -> Result<usize> {
+        match self {
+            Self::Abridged(t) => t.unpack(input, output),
+            Self::Intermediate(t) => t.unpack(input, output),
+            Self::PaddedIntermediate(t) => t.unpack(input, output),
+            Self::Full(t) => t.unpack(input, output),
+        }
+    }
+}
+
 #[derive(Error, Clone, Debug, PartialEq)]
 pub enum Error {
     /// Not enough bytes are provided.
@@ -55,28 +110,33 @@ pub enum Error {
 /// [Transport obfuscation](https://core.telegram.org/mtproto/mtproto-transports#transport-obfuscation)
 ///
 /// 使用 [WebSocket](https://core.telegram.org/mtproto/transports#websocket) 时需要,
-/// Android 客户端源码也使用了
+/// Android 客户端源码也使用了. 密钥和 cipher 状态 drop 时清零
 #[derive(Clone)]
 pub struct Obfuscation {
-    secret: String,
+    secret: Zeroizing<Vec<u8>>,
+    dc_id: i16,
     encrypt_cipher: Option<Aes256Ctr128LE>,
     decrypt_cipher: Option<Aes256Ctr128LE>,
-    encrypt_key: [u8; 32],
-    encrypt_iv: [u8; 16],
-    decrypt_key: [u8; 32],
-    decrypt_iv: [u8; 16],
+    encrypt_key: Zeroizing<[u8; 32]>,
+    encrypt_iv: Zeroizing<[u8; 16]>,
+    decrypt_key: Zeroizing<[u8; 32]>,
+    decrypt_iv: Zeroizing<[u8; 16]>,
+    /// 已解密但还没有被 unpack 消费的数据, 对应下一次 unpack 的 `input` 开头
+    recv_plain: Zeroizing<Vec<u8>>,
 }
 
 impl Obfuscation {
-    fn new(secret: String) -> Self {
+    fn new(secret: Vec<u8>, dc_id: i16) -> Self {
         Self {
-            secret,
+            secret: Zeroizing::new(secret),
+            dc_id,
             encrypt_cipher: None,
             decrypt_cipher: None,
-            encrypt_key: [0; 32],
-            encrypt_iv: [0; 16],
-            decrypt_key: [0; 32],
-            decrypt_iv: [0; 16],
+            encrypt_key: Zeroizing::new([0; 32]),
+            encrypt_iv: Zeroizing::new([0; 16]),
+            decrypt_key: Zeroizing::new([0; 32]),
+            decrypt_iv: Zeroizing::new([0; 16]),
+            recv_plain: Zeroizing::new(Vec::new()),
         }
     }
 
@@ -112,13 +172,14 @@ impl Obfuscation {
             }
 
             // 60-62 填充 dc_id (测试服为 dc_id + 10000), media 类型的 dc_id 取负数 (测试服为 -(dc_id + 10000))
-            // 使用 proxy 服务器才需要填充 dc_id, 否则保持随机数
+            // MTProxy 按 dc_id 转发, 直连时服务器忽略
+            header[60..62].copy_from_slice(&self.dc_id.to_le_bytes());
 
             // 剩余位保持随机数
             break;
         }
 
-        let mut temp = [0; 64];
+        let mut temp = Zeroizing::new([0; 64]);
         temp.copy_from_slice(&header);
         self.encrypt_key.copy_from_slice(&temp[8..40]);
         self.encrypt_iv.copy_from_slice(&temp[40..56]);
@@ -126,11 +187,11 @@ impl Obfuscation {
         self.decrypt_key.copy_from_slice(&temp[8..40]);
         self.decrypt_iv.copy_from_slice(&temp[40..56]);
 
-        encrypt_key_with_secret(&self.secret, &mut self.encrypt_key);
-        encrypt_key_with_secret(&self.secret, &mut self.decrypt_key);
+        encrypt_key_with_secret(&self.secret, &mut *self.encrypt_key);
+        encrypt_key_with_secret(&self.secret, &mut *self.decrypt_key);
 
         temp.copy_from_slice(&header);
-        self.aes256_ctr128_encrypt(&mut temp);
+        self.aes256_ctr128_encrypt(&mut *temp);
 
         header[56..].copy_from_slice(&temp[56..]);
 
@@ -139,40 +200,153 @@ impl Obfuscation {
 
     fn aes256_ctr128_encrypt(&mut self, data: &mut [u8]) {
         let cipher = self.encrypt_cipher.get_or_insert_with(|| {
-            StreamCipherCoreWrapper::new(&self.encrypt_key.into(), &self.encrypt_iv.into())
+            StreamCipherCoreWrapper::new(&(*self.encrypt_key).into(), &(*self.encrypt_iv).into())
         });
         cipher.apply_keystream(data);
     }
 
     fn aes256_ctr128_decrypt(&mut self, data: &mut [u8]) {
         let cipher = self.decrypt_cipher.get_or_insert_with(|| {
-            StreamCipherCoreWrapper::new(&self.decrypt_key.into(), &self.decrypt_iv.into())
+            StreamCipherCoreWrapper::new(&(*self.decrypt_key).into(), &(*self.decrypt_iv).into())
         });
         cipher.apply_keystream(data);
     }
 
+    /// 开启 obfuscation 时先解密再交给 [unpack] 解析. 数据不完整时 `input` 会带着已解密的部分再次传入,
+    /// 只解密新增的数据, 保证 keystream 与服务器一致
+    fn unpack_with<F>(obfuscation: &mut Option<Self>, input: &[u8], unpack: F) -> Result<usize>
+    where F: FnOnce(&[u8]) -> Result<usize>
+    {
+        let Some(obf) = obfuscation else { return unpack(input); };
+        let done = obf.recv_plain.len();
+        if input.len() > done {
+            let mut data = input[done..].to_vec();
+            obf.aes256_ctr128_decrypt(&mut data);
+            obf.recv_plain.extend_from_slice(&data);
+        }
+        let used = unpack(&obf.recv_plain[..input.len().min(obf.recv_plain.len())])?;
+        obf.recv_plain.drain(..used);
+        Ok(used)
+    }
+}
+
+impl Debug for Obfuscation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Obfuscation")
+            .field("dc_id", &self.dc_id)
+            .field("started", &self.encrypt_cipher.is_some())
+            .finish_non_exhaustive()
+    }
 }
 
-fn encrypt_key_with_secret(secret: &str, bytes: &mut [u8]) {
+/// `key = sha256(key + secret)`, `dd`/`ee` 开头的密钥去掉第一个字节, 只使用 16 字节
+fn encrypt_key_with_secret(secret: &[u8], bytes: &mut [u8]) {
     if bytes.len() < 32 { return; }
     if secret.is_empty() { return; }
 
-    let mut chars = secret.chars().collect::<Vec<char>>();
-
     let mut start = 0;
-    let mut end = min(16, chars.len());
-    let first_char = chars[0] as u32;
-    if chars.len() >= 17 && (first_char == 0xdd || first_char == 0xee) {
+    let mut end = min(16, secret.len());
+    if secret.len() >= 17 && (secret[0] == 0xdd || secret[0] == 0xee) {
         start = 1;
         end = 17;
     }
 
     let mut hasher: Sha256 = Sha256::new();
     hasher.update(&bytes[..32]);
-    for ch in &chars[start..end] {
-        hasher.update(ch.to_string().as_bytes());
-    }
+    hasher.update(&secret[start..end]);
     let hash = hasher.finalize();
 
     bytes[..32].copy_from_slice(&hash);
-}
\ No newline at end of file
+}
+#[cfg(test)]
+impl Obfuscation {
+    /// 服务器一侧, 加解密方向与 [self] 相反, 已跳过 init header 占用的 keystream
+    fn peer(&self) -> Self {
+        let mut peer = Self {
+            secret: self.secret.clone(),
+            dc_id: self.dc_id,
+            encrypt_cipher: None,
+            decrypt_cipher: None,
+            encrypt_key: self.decrypt_key.clone(),
+            encrypt_iv: self.decrypt_iv.clone(),
+            decrypt_key: self.encrypt_key.clone(),
+            decrypt_iv: self.encrypt_iv.clone(),
+            recv_plain: Zeroizing::new(Vec::new()),
+        };
+        peer.aes256_ctr128_decrypt(&mut [0; 64]);
+        peer
+    }
+}
+
+#[cfg(test)]
+impl TransportImpl {
+    fn peer(&self) -> Self {
+        match self {
+            Self::Abridged(t) => Self::Abridged(t.peer()),
+            Self::Intermediate(t) => Self::Intermediate(t.peer()),
+            Self::PaddedIntermediate(t) => Self::PaddedIntermediate(t.peer()),
+            Self::Full(t) => Self::Full(t.peer()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_dc_id() {
+        let mut obf = Obfuscation::new(vec![], -10002);
+        let mut header = obf.init_header(Some(0xef));
+
+        let mut cipher: Aes256Ctr128LE = StreamCipherCoreWrapper::new(&(*obf.encrypt_key).into(), &(*obf.encrypt_iv).into());
+        cipher.apply_keystream(&mut header);
+        assert_eq!(&header[56..60], &[0xef; 4]);
+        assert_eq!(i16::from_le_bytes([header[60], header[61]]), -10002);
+    }
+
+    #[test]
+    fn key_with_secret() {
+        // dd 开头的密钥去掉第一个字节, 按原始字节计算
+        let mut secret = vec![0xdd];
+        secret.extend(0xf0..=0xff);
+        let mut key = [1; 32];
+        encrypt_key_with_secret(&secret, &mut key);
+        assert_eq!(key, crate::sha256!([1; 32], &secret[1..]));
+
+        let mut key = [1; 32];
+        encrypt_key_with_secret(&[], &mut key);
+        assert_eq!(key, [1; 32]);
+    }
+
+    #[test]
+    fn obfuscated_round_trip() {
+        let types = [TransportType::Abridged, TransportType::Intermediate, TransportType::PaddedIntermediate, TransportType::Full];
+        for typo in types {
+            let mut client = TransportImpl::new(typo, Some(vec![0xdd; 17]), 2);
+            let request = [1; 16];
+            let mut buf = ByteBuffer::new();
+            client.pack(&request, &mut buf);
+
+            let mut server = client.peer();
+            let mut out = ByteBuffer::new();
+            assert_eq!(server.unpack(&buf[64..], &mut out).unwrap(), buf.len() - 64, "{:?}", typo);
+            assert_eq!(&out[..16], &request, "{:?}", typo);
+
+            // 两个回复在同一段数据中, 第一次只收到一部分
+            let (small, large) = ([2; 8], [3; 1024]);
+            let mut buf = ByteBuffer::new();
+            server.pack(&small, &mut buf);
+            let first = buf.len();
+            server.pack(&large, &mut buf);
+
+            let mut out = ByteBuffer::new();
+            assert!(client.unpack(&buf[..first - 1], &mut out).is_err(), "{:?}", typo);
+            assert_eq!(client.unpack(&buf, &mut out).unwrap(), first, "{:?}", typo);
+            assert_eq!(&out[..8], &small, "{:?}", typo);
+            let mut out = ByteBuffer::new();
+            assert_eq!(client.unpack(&buf[first..], &mut out).unwrap(), buf.len() - first, "{:?}", typo);
+            assert_eq!(&out[..1024], &large, "{:?}", typo);
+        }
+    }
+}
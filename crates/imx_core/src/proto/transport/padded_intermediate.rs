@@ -1,9 +1,9 @@
-use anyhow::Result;
-use bytes::BufMut;
+use anyhow::{bail, Result};
+use bytes::Buf;
 use rand::{Rng, RngCore, thread_rng};
 
 use crate::proto::ByteBuffer;
-use crate::proto::transport::{Obfuscation, Transport};
+use crate::proto::transport::{Error, Obfuscation, Transport};
 
 /// [Padded intermediate](https://core.telegram.org/mtproto/mtproto-transports#padded-intermediate)
 ///
@@ -53,13 +53,29 @@ impl PaddedIntermediate {
         }
     }
 
-    pub fn obfuscation(self, secret: Option<String>) -> Self {
+    pub fn obfuscation(self, secret: Option<Vec<u8>>, dc_id: i16) -> Self {
         Self {
-            obfuscation: secret.map(|s| Obfuscation::new(s)),
+            obfuscation: secret.map(|s| Obfuscation::new(s, dc_id)),
             ack: self.ack,
             first_packet_sent: self.first_packet_sent,
         }
     }
+
+    /// 服务器一侧的传输协议, 用于测试收发
+    #[cfg(test)]
+    pub(super) fn peer(&self) -> Self {
+        Self {
+            obfuscation: self.obfuscation.as_ref().map(Obfuscation::peer),
+            ack: self.ack,
+            first_packet_sent: true,
+        }
+    }
+}
+
+impl Default for PaddedIntermediate {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Transport for PaddedIntermediate {
@@ -88,6 +104,7 @@ impl Transport for PaddedIntermediate {
         if padding_len > 0 {
             let mut padding = vec![0; padding_len];
             rng.fill_bytes(&mut padding);
+            output.put_all(&padding);
         }
         let end = output.len();
 
@@ -96,8 +113,22 @@ impl Transport for PaddedIntermediate {
         }
     }
 
+    /// 输出的数据包含末尾 0-15 字节的随机 padding, 传输层无法区分, 调用方需要按消息自身的长度截取:
+    /// 非加密消息使用 `message_data_length`, 加密消息取 16 字节对齐的部分
     fn unpack(&mut self, input: &[u8], output: &mut ByteBuffer) -> Result<usize> {
-        todo!()
+        Obfuscation::unpack_with(&mut self.obfuscation, input, |input| unpack(input, output))
     }
+}
+
+fn unpack(input: &[u8], output: &mut ByteBuffer) -> Result<usize> {
+    if input.len() < 4 { bail!(Error::MissingBytes); }
+
+    let slice = &mut &input[..];
+
+    let len = slice.get_u32_le() as usize;
+    if slice.len() < len { bail!(Error::MissingBytes); }
+
+    output.put_all(&slice[..len]);
 
+    Ok(len + 4)
 }
\ No newline at end of file
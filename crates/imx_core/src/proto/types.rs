@@ -1,6 +1,7 @@
 use bytes::Bytes;
 use serde::{Deserialize, Serialize};
 use with_crc::WithCrc;
+use zeroize::Zeroize;
 
 #[derive(WithCrc, Default, Serialize, Deserialize, Debug, Clone)]
 #[crc(0x5bb8e511)]
@@ -35,7 +36,9 @@ pub enum PQInnerData {
     Dc {
         #[serde(with = "serde_bytes")]
         pq: Vec<u8>,
+        #[serde(with = "serde_bytes")]
         p: [u8; 4],
+        #[serde(with = "serde_bytes")]
         q: [u8; 4],
         nonce: [u8; 16],
         server_nonce: [u8; 16],
@@ -46,7 +49,9 @@ pub enum PQInnerData {
     TempDc {
         #[serde(with = "serde_bytes")]
         pq: Vec<u8>,
+        #[serde(with = "serde_bytes")]
         p: [u8; 4],
+        #[serde(with = "serde_bytes")]
         q: [u8; 4],
         nonce: [u8; 16],
         server_nonce: [u8; 16],
@@ -56,6 +61,15 @@ pub enum PQInnerData {
     },
 }
 
+/// 只清零 `new_nonce`, 其他字段明文传输
+impl Zeroize for PQInnerData {
+    fn zeroize(&mut self) {
+        match self {
+            Self::Dc { new_nonce, .. } | Self::TempDc { new_nonce, .. } => new_nonce.zeroize(),
+        }
+    }
+}
+
 #[derive(WithCrc, Serialize, Deserialize, Debug)]
 pub enum ServerDHParams {
     #[crc(0x79cb045d)]
@@ -68,10 +82,36 @@ pub enum ServerDHParams {
     Ok {
         nonce: [u8; 16],
         server_nonce: [u8; 16],
+        #[serde(with = "serde_bytes")]
         encrypted_answer: Vec<u8>,
     },
 }
 
+/// 解密 [ServerDHParams::Ok] 的 `encrypted_answer` 得到
+#[derive(WithCrc, Serialize, Deserialize, Debug)]
+#[crc(0xb5890dba)]
+pub struct ServerDHInnerData {
+    pub nonce: [u8; 16],
+    pub server_nonce: [u8; 16],
+    pub g: i32,
+    #[serde(with = "serde_bytes")]
+    pub dh_prime: Vec<u8>,
+    #[serde(with = "serde_bytes")]
+    pub g_a: Vec<u8>,
+    pub server_time: i32,
+}
+
+/// 加密后作为 [SetClientDHParams::encrypted_data](crate::proto::SetClientDHParams) 发送
+#[derive(WithCrc, Serialize, Deserialize, Debug)]
+#[crc(0x6643b654)]
+pub struct ClientDHInnerData {
+    pub nonce: [u8; 16],
+    pub server_nonce: [u8; 16],
+    pub retry_id: i64,
+    #[serde(with = "serde_bytes")]
+    pub g_b: Vec<u8>,
+}
+
 #[derive(WithCrc, Serialize, Deserialize, Debug)]
 pub enum SetClientDHParamsAnswer {
     #[crc(0x46dc1fb9)]
@@ -100,3 +140,31 @@ pub struct Pong {
     pub msg_id: i64,
     pub ping_id: i64,
 }
+
+#[derive(WithCrc, Serialize, Deserialize, Debug)]
+#[crc(0xb434e2b8)]
+pub struct ExportedAuthorization {
+    pub id: i64,
+    #[serde(with = "serde_bytes")]
+    pub bytes: Vec<u8>,
+}
+
+#[derive(WithCrc, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bool {
+    #[crc(0x997275b5)]
+    True,
+    #[crc(0xbc799737)]
+    False,
+}
+
+/// [PFS](https://core.telegram.org/api/pfs) 绑定临时密钥的消息, 使用永久密钥加密后作为
+/// [BindTempAuthKey::encrypted_message](crate::proto::BindTempAuthKey) 发送
+#[derive(WithCrc, Default, Serialize, Deserialize, Debug)]
+#[crc(0x75a3f765)]
+pub struct BindAuthKeyInner {
+    pub nonce: i64,
+    pub temp_auth_key_id: i64,
+    pub perm_auth_key_id: i64,
+    pub temp_session_id: i64,
+    pub expires_at: i32,
+}
@@ -0,0 +1,177 @@
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use log::warn;
+
+use crate::proto::service::{get_i32, get_i64, get_string, get_u32};
+
+const UPDATES_TOO_LONG: u32 = 0xe317af7e;
+const UPDATE_SHORT_MESSAGE: u32 = 0x313bc7f8;
+const UPDATE_SHORT_CHAT_MESSAGE: u32 = 0x4d6deea5;
+const UPDATE_SHORT: u32 = 0x78d4dec1;
+const UPDATES_COMBINED: u32 = 0x725b04c3;
+const UPDATES: u32 = 0x74ae4240;
+
+/// 服务器推送的 [Updates](https://core.telegram.org/type/Updates), 只解析处理 updates 顺序需要的字段,
+/// 其余内容保留原始 TL
+#[derive(Debug, Clone, PartialEq)]
+pub enum Update {
+    /// `updatesTooLong`, 需要通过 `updates.getDifference` 获取缺少的 updates
+    TooLong,
+    ShortMessage(ShortMessage),
+    ShortChatMessage(ShortChatMessage),
+    /// `updateShort update:Update date:int`, [update] 为单个 `Update` 的原始 TL
+    Short { update: Bytes, date: i32 },
+    /// `updates` 和 `updatesCombined`, 不是合并的 updates 时 [seq_start] 与 [seq] 相同. [body] 为完整的原始 TL
+    Combined { date: i32, seq_start: i32, seq: i32, body: Bytes },
+    /// 不认识的构造器或解析失败, 原始 TL
+    Raw(Bytes),
+}
+
+/// `updateShortMessage`, 私聊中收到或在其他设备发出的消息. `date` 之后的字段随 layer 变化, 忽略
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShortMessage {
+    /// 自己发出的消息
+    pub out: bool,
+    pub id: i32,
+    pub user_id: i64,
+    pub message: String,
+    pub pts: i32,
+    pub pts_count: i32,
+    pub date: i32,
+}
+
+/// `updateShortChatMessage`, 群组中收到的消息. `date` 之后的字段随 layer 变化, 忽略
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ShortChatMessage {
+    pub out: bool,
+    pub id: i32,
+    pub from_id: i64,
+    pub chat_id: i64,
+    pub message: String,
+    pub pts: i32,
+    pub pts_count: i32,
+    pub date: i32,
+}
+
+impl Update {
+    /// 解析推送的消息, 不认识或格式错误时返回 [Update::Raw]
+    pub(crate) fn parse(body: Bytes) -> Self {
+        match parse(&body) {
+            Ok(Some(update)) => update,
+            Ok(None) => Self::Raw(body),
+            Err(e) => {
+                warn!("Parse updates failed: {}", e);
+                Self::Raw(body)
+            }
+        }
+    }
+}
+
+fn parse(body: &Bytes) -> Result<Option<Update>> {
+    let mut buf = body.clone();
+    let update = match get_u32(&mut buf)? {
+        UPDATES_TOO_LONG => Update::TooLong,
+        UPDATE_SHORT_MESSAGE => {
+            let flags = get_i32(&mut buf)?;
+            Update::ShortMessage(ShortMessage {
+                out: flags & (1 << 1) != 0,
+                id: get_i32(&mut buf)?,
+                user_id: get_i64(&mut buf)?,
+                message: get_string(&mut buf)?,
+                pts: get_i32(&mut buf)?,
+                pts_count: get_i32(&mut buf)?,
+                date: get_i32(&mut buf)?,
+            })
+        }
+        UPDATE_SHORT_CHAT_MESSAGE => {
+            let flags = get_i32(&mut buf)?;
+            Update::ShortChatMessage(ShortChatMessage {
+                out: flags & (1 << 1) != 0,
+                id: get_i32(&mut buf)?,
+                from_id: get_i64(&mut buf)?,
+                chat_id: get_i64(&mut buf)?,
+                message: get_string(&mut buf)?,
+                pts: get_i32(&mut buf)?,
+                pts_count: get_i32(&mut buf)?,
+                date: get_i32(&mut buf)?,
+            })
+        }
+        // 末尾的整数位置固定, 不需要解析中间长度不定的对象
+        UPDATE_SHORT => {
+            if buf.len() < 8 { bail!("message too short"); }
+            let mut tail = buf.split_off(buf.len() - 4);
+            Update::Short { update: buf, date: get_i32(&mut tail)? }
+        }
+        UPDATES_COMBINED => {
+            if buf.len() < 12 { bail!("message too short"); }
+            let mut tail = buf.split_off(buf.len() - 12);
+            let date = get_i32(&mut tail)?;
+            let seq_start = get_i32(&mut tail)?;
+            let seq = get_i32(&mut tail)?;
+            Update::Combined { date, seq_start, seq, body: body.clone() }
+        }
+        UPDATES => {
+            if buf.len() < 8 { bail!("message too short"); }
+            let mut tail = buf.split_off(buf.len() - 8);
+            let date = get_i32(&mut tail)?;
+            let seq = get_i32(&mut tail)?;
+            Update::Combined { date, seq_start: seq, seq, body: body.clone() }
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(update))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proto::ByteBuffer;
+
+    use super::*;
+
+    #[test]
+    fn parse_updates() {
+        let mut buf = ByteBuffer::new();
+        buf.put_u32(UPDATE_SHORT_MESSAGE);
+        buf.put_i32(1 << 1);
+        buf.put_i32(7);
+        buf.put_i64(42);
+        buf.put_u8(2);
+        buf.put_all(b"hi\0");
+        buf.put_i32(100);
+        buf.put_i32(1);
+        buf.put_i32(1700000000);
+        let expected = ShortMessage {
+            out: true,
+            id: 7,
+            user_id: 42,
+            message: "hi".into(),
+            pts: 100,
+            pts_count: 1,
+            date: 1700000000,
+        };
+        assert_eq!(Update::parse(buf.to_bytes()), Update::ShortMessage(expected));
+
+        let mut buf = ByteBuffer::new();
+        buf.put_u32(UPDATE_SHORT);
+        buf.put_u32(0x1bfbd823);
+        buf.put_i32(5);
+        assert_eq!(Update::parse(buf.to_bytes()), Update::Short { update: Bytes::from_static(&[0x23, 0xd8, 0xfb, 0x1b]), date: 5 });
+
+        let mut buf = ByteBuffer::new();
+        buf.put_u32(UPDATES);
+        for _ in 0..3 {
+            buf.put_u32(0x1cb5c415);
+            buf.put_i32(0);
+        }
+        buf.put_i32(9);
+        buf.put_i32(3);
+        let body = buf.to_bytes();
+        assert_eq!(Update::parse(body.clone()), Update::Combined { date: 9, seq_start: 3, seq: 3, body });
+
+        // 不认识或不完整的消息保留原始数据
+        let body = Bytes::from_static(&[1, 2, 3, 4]);
+        assert_eq!(Update::parse(body.clone()), Update::Raw(body));
+        let body = Bytes::from_static(&[0x7e, 0xaf, 0x17]);
+        assert_eq!(Update::parse(body.clone()), Update::Raw(body));
+    }
+}
@@ -0,0 +1,88 @@
+//! 加解密接口, 用于本地数据加密. 密钥由口令通过 PBKDF2-HMAC-SHA256 派生, 数据使用 AES-256-GCM 加密
+
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use aes_gcm::aead::{Aead, Payload};
+use rand::{RngCore, thread_rng};
+use sha2::Sha256;
+use thiserror::Error;
+use zeroize::Zeroizing;
+
+pub use storage::EncryptedStorage;
+
+mod storage;
+
+/// 密钥长度
+pub const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// 对称密钥, drop 时清零
+pub type Key = Zeroizing<[u8; KEY_LEN]>;
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecurityError {
+    /// 已锁定, 需要先解锁
+    #[error("storage is locked")]
+    Locked,
+    #[error("wrong passcode")]
+    WrongPasscode,
+    /// 密钥不正确或数据被修改
+    #[error("decrypt failed")]
+    Decrypt,
+    /// 没有设置口令, 本地数据没有加密
+    #[error("storage is not encrypted")]
+    NotEncrypted,
+}
+
+/// 生成随机密钥
+pub fn random_key() -> Key {
+    let mut key = Zeroizing::new([0; KEY_LEN]);
+    thread_rng().fill_bytes(key.as_mut());
+    key
+}
+
+/// 使用 PBKDF2-HMAC-SHA256 从口令派生密钥
+pub fn derive_key(passcode: &str, salt: &[u8], iterations: u32) -> Key {
+    let mut key = Zeroizing::new([0; KEY_LEN]);
+    pbkdf2::pbkdf2_hmac::<Sha256>(passcode.as_bytes(), salt, iterations, key.as_mut());
+    key
+}
+
+/// 使用 AES-256-GCM 加密, 返回 `nonce || 密文`. [aad] 参与认证但不加密
+pub fn seal(key: &[u8; KEY_LEN], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let mut nonce = [0; NONCE_LEN];
+    thread_rng().fill_bytes(&mut nonce);
+    let ciphertext = Aes256Gcm::new(key.into())
+        .encrypt(Nonce::from_slice(&nonce), Payload { msg: plaintext, aad })
+        .expect("plaintext too long");
+    [nonce.as_slice(), &ciphertext].concat()
+}
+
+/// 解密 [seal] 的结果
+pub fn open(key: &[u8; KEY_LEN], aad: &[u8], data: &[u8]) -> Result<Vec<u8>, SecurityError> {
+    if data.len() < NONCE_LEN { return Err(SecurityError::Decrypt); }
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    Aes256Gcm::new(key.into())
+        .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad })
+        .map_err(|_| SecurityError::Decrypt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_and_open() {
+        let key = derive_key("1234", b"salt", 10);
+        assert_eq!(key, derive_key("1234", b"salt", 10));
+        assert_ne!(key, derive_key("1235", b"salt", 10));
+
+        let data = seal(&key, b"a", b"secret");
+        assert_eq!(open(&key, b"a", &data).unwrap(), b"secret");
+        // aad 不同, 密钥错误或数据被修改时都无法解密
+        assert_eq!(open(&key, b"b", &data), Err(SecurityError::Decrypt));
+        assert_eq!(open(&random_key(), b"a", &data), Err(SecurityError::Decrypt));
+        let mut modified = data.clone();
+        modified[NONCE_LEN] ^= 1;
+        assert_eq!(open(&key, b"a", &modified), Err(SecurityError::Decrypt));
+    }
+}
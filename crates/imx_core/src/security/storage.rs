@@ -0,0 +1,286 @@
+use std::fmt::{Debug, Formatter};
+use std::sync::{Arc, RwLock};
+
+use anyhow::{bail, Result};
+use rand::{RngCore, thread_rng};
+use zeroize::Zeroizing;
+
+use crate::defines::{PASSCODE_KDF_ITERATIONS, PASSCODE_KDF_MAX_ITERATIONS};
+use crate::security::{derive_key, Key, KEY_LEN, open, seal, SecurityError};
+use crate::storage::Storage;
+
+/// 数据密钥的保存位置, 使用口令派生的密钥加密
+const DATA_KEY: &str = "security/data_key";
+/// 迁移明文记录期间保存的数据密钥, 所有记录加密后才写入 [DATA_KEY]. 迁移中断时下次解锁继续
+const PENDING_DATA_KEY: &str = "security/pending_data_key";
+/// 记录格式的版本, 格式变化时增加
+const VERSION: u8 = 1;
+const SALT_LEN: usize = 16;
+
+/// 加密保存所有记录的 [Storage]. 记录使用随机生成的数据密钥加密, 数据密钥再用口令派生的密钥加密保存,
+/// 更换口令时只需要重新加密数据密钥
+///
+/// 创建后处于锁定状态, [EncryptedStorage::unlock] 之后才能读写. clone 得到的实例共用锁定状态
+///
+/// # Examples
+/// ```rust
+/// use imx_core::security::EncryptedStorage;
+/// use imx_core::storage::{MemoryStorage, Storage};
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let storage = EncryptedStorage::new(MemoryStorage::new());
+/// storage.unlock("1234")?;
+/// storage.set("key", b"value")?;
+/// storage.lock();
+/// assert!(storage.get("key").is_err());
+/// # Ok(())
+/// # }
+/// ```
+pub struct EncryptedStorage<S> {
+    inner: Arc<Inner<S>>,
+}
+
+struct Inner<S> {
+    storage: S,
+    /// 设置新口令时 PBKDF2 的迭代次数, 已保存的数据密钥按保存时的次数派生
+    iterations: u32,
+    /// 解锁后的数据密钥
+    key: RwLock<Option<Key>>,
+}
+
+impl<S: Storage> EncryptedStorage<S> {
+    pub fn new(storage: S) -> Self {
+        Self::with_iterations(storage, PASSCODE_KDF_ITERATIONS)
+    }
+
+    /// 指定 PBKDF2 的迭代次数, 默认为 [PASSCODE_KDF_ITERATIONS]
+    pub fn with_iterations(storage: S, iterations: u32) -> Self {
+        let inner = Inner { storage, iterations: iterations.max(1), key: RwLock::new(None) };
+        Self { inner: Arc::new(inner) }
+    }
+
+    pub fn is_locked(&self) -> bool {
+        self.inner.key.read().unwrap().is_none()
+    }
+
+    /// 使用口令解锁. 第一次使用时生成数据密钥, 用 [passcode] 加密保存.
+    /// 存储中已经有明文记录时使用 [EncryptedStorage::unlock_and_migrate], 否则这些记录之后无法读取
+    pub fn unlock(&self, passcode: &str) -> Result<()> {
+        self.unlock_and_migrate(passcode, std::iter::empty::<&str>())
+    }
+
+    /// 与 [EncryptedStorage::unlock] 相同, 第一次使用时把 [keys] 中已有的明文记录加密保存,
+    /// 用于给已经在使用的存储开启加密. 已经有数据密钥时忽略 [keys]
+    pub fn unlock_and_migrate<K: AsRef<str>>(&self, passcode: &str, keys: impl IntoIterator<Item = K>) -> Result<()> {
+        let key = match self.load_data_key(DATA_KEY, passcode)? {
+            Some(key) => key,
+            None => {
+                // 还没有数据密钥, 记录是明文, 或者在上次中断的迁移中已经用 PENDING_DATA_KEY 加密
+                let data_key = match self.load_data_key(PENDING_DATA_KEY, passcode)? {
+                    Some(key) => key,
+                    None => {
+                        let key = super::random_key();
+                        self.save_data_key(PENDING_DATA_KEY, &key, passcode)?;
+                        key
+                    }
+                };
+                for key in keys {
+                    let key = key.as_ref();
+                    Self::check_key(key)?;
+                    let Some(value) = self.inner.storage.get(key)? else { continue; };
+                    if open_record(&data_key, key, &value).is_ok() { continue; }
+                    let value = Zeroizing::new(value);
+                    self.inner.storage.set(key, &seal_record(&data_key, key, &value))?;
+                }
+                self.save_data_key(DATA_KEY, &data_key, passcode)?;
+                self.inner.storage.remove(PENDING_DATA_KEY)?;
+                data_key
+            }
+        };
+        *self.inner.key.write().unwrap() = Some(key);
+        Ok(())
+    }
+
+    /// 锁定, 清除内存中的数据密钥, 之后读写返回 [SecurityError::Locked]
+    pub fn lock(&self) {
+        self.inner.key.write().unwrap().take();
+    }
+
+    /// 更换口令, 已保存的记录不需要重新加密
+    pub fn change_passcode(&self, old: &str, new: &str) -> Result<()> {
+        let Some(key) = self.load_data_key(DATA_KEY, old)? else { bail!(SecurityError::WrongPasscode); };
+        self.save_data_key(DATA_KEY, &key, new)
+    }
+
+    /// 用口令解密 [name] 中保存的数据密钥, 还没有数据密钥时返回 `None`.
+    /// 迭代次数超过 [PASSCODE_KDF_MAX_ITERATIONS] 的记录视为被篡改
+    fn load_data_key(&self, name: &str, passcode: &str) -> Result<Option<Key>> {
+        let Some(record) = self.inner.storage.get(name)? else { return Ok(None); };
+        let header = 1 + 4 + SALT_LEN;
+        if record.len() < header || record[0] != VERSION { bail!(SecurityError::Decrypt); }
+        let iterations = u32::from_le_bytes(record[1..5].try_into().unwrap());
+        if !(1..=PASSCODE_KDF_MAX_ITERATIONS).contains(&iterations) { bail!(SecurityError::Decrypt); }
+        let salt = &record[5..header];
+
+        let wrapping_key = derive_key(passcode, salt, iterations);
+        let plain = Zeroizing::new(open(&wrapping_key, name.as_bytes(), &record[header..]).map_err(|_| SecurityError::WrongPasscode)?);
+        let key: [u8; KEY_LEN] = plain.as_slice().try_into().map_err(|_| SecurityError::Decrypt)?;
+        Ok(Some(Zeroizing::new(key)))
+    }
+
+    /// 格式: `VERSION || iterations (u32 LE) || salt || seal(数据密钥)`
+    fn save_data_key(&self, name: &str, key: &Key, passcode: &str) -> Result<()> {
+        let iterations = self.inner.iterations;
+        let mut salt = [0; SALT_LEN];
+        thread_rng().fill_bytes(&mut salt);
+        let wrapping_key = derive_key(passcode, &salt, iterations);
+
+        let mut record = vec![VERSION];
+        record.extend_from_slice(&iterations.to_le_bytes());
+        record.extend_from_slice(&salt);
+        record.extend(seal(&wrapping_key, name.as_bytes(), key.as_slice()));
+        self.inner.storage.set(name, &record)
+    }
+
+    fn check_key(key: &str) -> Result<()> {
+        if key == DATA_KEY || key == PENDING_DATA_KEY { bail!("reserved key: {}", key); }
+        Ok(())
+    }
+}
+
+/// 记录格式: `VERSION || seal(值)`, 以记录的 key 作为 aad, 防止记录之间被互换
+impl<S: Storage> Storage for EncryptedStorage<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Self::check_key(key)?;
+        let guard = self.inner.key.read().unwrap();
+        let Some(data_key) = guard.as_ref() else { bail!(SecurityError::Locked); };
+        let Some(record) = self.inner.storage.get(key)? else { return Ok(None); };
+        open_record(data_key, key, &record).map(Some)
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        Self::check_key(key)?;
+        let guard = self.inner.key.read().unwrap();
+        let Some(data_key) = guard.as_ref() else { bail!(SecurityError::Locked); };
+        self.inner.storage.set(key, &seal_record(data_key, key, value))
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        Self::check_key(key)?;
+        if self.is_locked() { bail!(SecurityError::Locked); }
+        self.inner.storage.remove(key)
+    }
+}
+
+impl<S> Clone for EncryptedStorage<S> {
+    fn clone(&self) -> Self {
+        Self { inner: self.inner.clone() }
+    }
+}
+
+impl<S> Debug for EncryptedStorage<S> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedStorage")
+            .field("iterations", &self.inner.iterations)
+            .field("locked", &self.inner.key.read().unwrap().is_none())
+            .finish()
+    }
+}
+
+fn seal_record(data_key: &Key, key: &str, value: &[u8]) -> Vec<u8> {
+    let mut record = vec![VERSION];
+    record.extend(seal(data_key, key.as_bytes(), value));
+    record
+}
+
+fn open_record(data_key: &Key, key: &str, record: &[u8]) -> Result<Vec<u8>> {
+    match record.split_first() {
+        Some((&VERSION, data)) => Ok(open(data_key, key.as_bytes(), data)?),
+        _ => bail!(SecurityError::Decrypt),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::MemoryStorage;
+
+    use super::*;
+
+    #[test]
+    fn lock_unlock_and_change_passcode() {
+        let inner = Arc::new(MemoryStorage::new());
+        let storage = EncryptedStorage::with_iterations(inner.clone(), 10);
+        assert!(storage.is_locked());
+        assert_eq!(storage.get("a").unwrap_err().downcast::<SecurityError>().unwrap(), SecurityError::Locked);
+
+        storage.unlock("1234").unwrap();
+        storage.set("a", b"secret").unwrap();
+        // 保存的是密文
+        let record = inner.get("a").unwrap().unwrap();
+        assert!(!record.windows(6).any(|w| w == b"secret"));
+        assert!(storage.set(DATA_KEY, b"").is_err());
+
+        storage.lock();
+        assert_eq!(storage.unlock("0000").unwrap_err().downcast::<SecurityError>().unwrap(), SecurityError::WrongPasscode);
+        assert!(storage.is_locked());
+
+        storage.change_passcode("1234", "5678").unwrap();
+        assert!(storage.unlock("1234").is_err());
+        // 新的实例共用保存的数据密钥
+        let storage = EncryptedStorage::with_iterations(inner.clone(), 10);
+        storage.unlock("5678").unwrap();
+        assert_eq!(storage.get("a").unwrap(), Some(b"secret".to_vec()));
+
+        // 记录被互换后无法解密
+        inner.set("b", &record).unwrap();
+        assert!(storage.get("b").is_err());
+    }
+
+    #[test]
+    fn migrate_plain_records() {
+        let inner = Arc::new(MemoryStorage::new());
+        inner.set("a", b"plain").unwrap();
+        inner.set("b", b"other").unwrap();
+        let storage = EncryptedStorage::with_iterations(inner.clone(), 10);
+        storage.unlock_and_migrate("1234", ["a", "c"]).unwrap();
+        assert_eq!(storage.get("a").unwrap(), Some(b"plain".to_vec()));
+        assert_ne!(inner.get("a").unwrap(), Some(b"plain".to_vec()));
+        assert_eq!(storage.get("c").unwrap(), None);
+        // 没有列出的明文记录无法读取
+        assert!(storage.get("b").is_err());
+
+        // 已经有数据密钥, 不再迁移
+        inner.set("b", b"other").unwrap();
+        storage.unlock_and_migrate("1234", ["b"]).unwrap();
+        assert!(storage.get("b").is_err());
+    }
+
+    #[test]
+    fn resume_interrupted_migration() {
+        let inner = Arc::new(MemoryStorage::new());
+        inner.set("b", b"other").unwrap();
+        let storage = EncryptedStorage::with_iterations(inner.clone(), 10);
+        // 上次迁移在加密 "a" 之后中断, 还没有写入 DATA_KEY
+        let data_key = crate::security::random_key();
+        storage.save_data_key(PENDING_DATA_KEY, &data_key, "1234").unwrap();
+        inner.set("a", &seal_record(&data_key, "a", b"plain")).unwrap();
+
+        assert!(storage.unlock_and_migrate("0000", ["a", "b"]).is_err());
+        assert_eq!(inner.get(DATA_KEY).unwrap(), None);
+        storage.unlock_and_migrate("1234", ["a", "b"]).unwrap();
+        assert_eq!(storage.get("a").unwrap(), Some(b"plain".to_vec()));
+        assert_eq!(storage.get("b").unwrap(), Some(b"other".to_vec()));
+        assert_eq!(inner.get(PENDING_DATA_KEY).unwrap(), None);
+    }
+
+    #[test]
+    fn reject_tampered_iterations() {
+        let inner = Arc::new(MemoryStorage::new());
+        let storage = EncryptedStorage::with_iterations(inner.clone(), 10);
+        storage.unlock("1234").unwrap();
+        let mut record = inner.get(DATA_KEY).unwrap().unwrap();
+        record[1..5].copy_from_slice(&u32::MAX.to_le_bytes());
+        inner.set(DATA_KEY, &record).unwrap();
+        assert_eq!(storage.unlock("1234").unwrap_err().downcast::<SecurityError>().unwrap(), SecurityError::Decrypt);
+    }
+}
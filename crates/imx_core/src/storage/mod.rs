@@ -0,0 +1,101 @@
+//! 本地存储, 保存密钥, 会话状态和 DataCenter 地址, 重新启动后不需要再次握手.
+//! [Storage] 只关注二进制, 记录的编码由上层负责
+
+use std::sync::Arc;
+
+use anyhow::Result;
+
+pub use memory::MemoryStorage;
+pub use session::{ExportedSession, SessionError};
+#[cfg(feature = "sled")]
+pub use self::sled::SledStorage;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteStorage;
+pub(crate) use state::DcState;
+
+mod memory;
+mod session;
+#[cfg(feature = "sled")]
+mod sled;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod state;
+
+/// [DcTable](crate::DcTable) 保存的位置
+pub(crate) const DC_TABLE_KEY: &str = "dc_table";
+
+/// 当前登录的用户 id 保存的位置
+pub(crate) const USER_ID_KEY: &str = "user_id";
+
+/// [dc_id] 的 [DcState] 保存的位置
+pub(crate) fn dc_state_key(dc_id: i32) -> String {
+    format!("dc/{}/state", dc_id)
+}
+
+/// 客户端保存的所有记录, [dc_ids] 为可能保存了 [DcState] 的 DataCenter
+pub(crate) fn client_keys(dc_ids: impl IntoIterator<Item = i32>) -> Vec<String> {
+    let mut keys = vec![DC_TABLE_KEY.to_owned(), USER_ID_KEY.to_owned()];
+    keys.extend(dc_ids.into_iter().map(dc_state_key));
+    keys
+}
+
+/// 二进制键值存储
+///
+/// # Examples
+/// ```rust
+/// use imx_core::storage::{MemoryStorage, Storage};
+///
+/// # fn main() -> anyhow::Result<()> {
+/// let storage = MemoryStorage::new();
+/// storage.set("key", b"value")?;
+/// assert_eq!(storage.get("key")?, Some(b"value".to_vec()));
+/// storage.remove("key")?;
+/// assert_eq!(storage.get("key")?, None);
+/// # Ok(())
+/// # }
+/// ```
+pub trait Storage: Send + Sync + 'static {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    fn set(&self, key: &str, value: &[u8]) -> Result<()>;
+
+    /// 删除 [key], 不存在时忽略
+    fn remove(&self, key: &str) -> Result<()>;
+}
+
+impl<S: Storage + ?Sized> Storage for Arc<S> {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        (**self).get(key)
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        (**self).set(key, value)
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        (**self).remove(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 各个实现共用的测试
+    pub(crate) fn round_trip<S: Storage>(storage: &S) {
+        assert_eq!(storage.get("a").unwrap(), None);
+        storage.set("a", b"1").unwrap();
+        storage.set("b", &[0, 255]).unwrap();
+        storage.set("a", b"2").unwrap();
+        assert_eq!(storage.get("a").unwrap(), Some(b"2".to_vec()));
+        assert_eq!(storage.get("b").unwrap(), Some(vec![0, 255]));
+        storage.remove("a").unwrap();
+        storage.remove("c").unwrap();
+        assert_eq!(storage.get("a").unwrap(), None);
+    }
+
+    #[test]
+    fn memory() {
+        round_trip(&MemoryStorage::new());
+    }
+}
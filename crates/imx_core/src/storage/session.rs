@@ -0,0 +1,181 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::str::FromStr;
+
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use bytes::{Buf, BufMut};
+use thiserror::Error;
+
+use crate::net::{DcStore, DcTable};
+use crate::storage::{dc_state_key, DcState, Storage, USER_ID_KEY};
+
+/// 编码格式的版本, 不兼容的变化时增加
+const VERSION: u8 = 1;
+
+/// 导入会话失败的原因
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionError {
+    /// 不是有效的 base64 或长度不正确
+    #[error("malformed session string")]
+    Malformed,
+    /// 校验和不匹配, 字符串被截断或修改
+    #[error("session string checksum mismatch")]
+    Checksum,
+    /// 由不兼容的版本导出
+    #[error("unsupported session string version {0}")]
+    Version(u8),
+}
+
+/// 可以在不同进程或机器之间迁移的会话, 包含各个 DataCenter 的密钥, 导入后连接时不需要握手.
+/// 通过 [Client::export_session](crate::Client::export_session) 导出, 字符串格式见 [Display] 和 [FromStr]
+///
+/// 字符串包含密钥, 拿到它就可以登录账号, 需要像密码一样保管
+///
+/// # Examples
+/// ```rust,no_run
+/// use imx_core::{Client, storage::ExportedSession};
+///
+/// # async fn run() -> anyhow::Result<()> {
+/// let client = Client::new("127.0.0.1:80")?;
+/// let text = client.export_session().await?.to_string();
+///
+/// let session: ExportedSession = text.parse()?;
+/// let client = Client::builder("127.0.0.1:80").session(session).build()?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, PartialEq)]
+pub struct ExportedSession {
+    pub(crate) home_dc: i32,
+    pub(crate) user_id: i64,
+    pub(crate) dcs: Vec<(i32, DcState)>,
+}
+
+impl ExportedSession {
+    pub fn home_dc(&self) -> i32 {
+        self.home_dc
+    }
+
+    pub fn user_id(&self) -> i64 {
+        self.user_id
+    }
+
+    /// 包含密钥的 DataCenter
+    pub fn dc_ids(&self) -> impl Iterator<Item = i32> + '_ {
+        self.dcs.iter().map(|(id, _)| *id)
+    }
+
+    /// 格式: `VERSION || home_dc || user_id || count || (dc_id || len || DcState)* || crc32`, 整数均为小端序
+    fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![VERSION];
+        buf.put_i32_le(self.home_dc);
+        buf.put_i64_le(self.user_id);
+        buf.put_u8(self.dcs.len() as u8);
+        for (id, state) in &self.dcs {
+            let state = state.encode();
+            buf.put_i32_le(*id);
+            buf.put_u16_le(state.len() as u16);
+            buf.put_slice(&state);
+        }
+        let crc = crc32fast::hash(&buf);
+        buf.put_u32_le(crc);
+        buf
+    }
+
+    fn decode(bytes: &[u8]) -> Result<Self, SessionError> {
+        if bytes.len() < 1 + 4 + 8 + 1 + 4 { return Err(SessionError::Malformed); }
+        let (mut buf, mut crc) = bytes.split_at(bytes.len() - 4);
+        if crc32fast::hash(buf) != crc.get_u32_le() { return Err(SessionError::Checksum); }
+
+        let version = buf.get_u8();
+        if version != VERSION { return Err(SessionError::Version(version)); }
+        let home_dc = buf.get_i32_le();
+        let user_id = buf.get_i64_le();
+        let count = buf.get_u8();
+        let mut dcs = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            if buf.remaining() < 6 { return Err(SessionError::Malformed); }
+            let id = buf.get_i32_le();
+            let len = buf.get_u16_le() as usize;
+            if buf.remaining() < len { return Err(SessionError::Malformed); }
+            let state = DcState::decode(&buf[..len]).map_err(|_| SessionError::Malformed)?;
+            buf.advance(len);
+            dcs.push((id, state));
+        }
+        if buf.has_remaining() { return Err(SessionError::Malformed); }
+        Ok(Self { home_dc, user_id, dcs })
+    }
+
+    /// 写入各个 DataCenter 的状态和用户 id, 并把 [DcTable] 的 home DC 改为导出时的 home DC
+    pub(crate) fn import(&self, storage: &dyn Storage, store: &dyn DcStore) -> anyhow::Result<()> {
+        for (id, state) in &self.dcs {
+            storage.set(&dc_state_key(*id), &state.encode())?;
+        }
+        storage.set(USER_ID_KEY, &self.user_id.to_le_bytes())?;
+        let table = store.load()?.unwrap_or_default();
+        store.save(&DcTable { home_dc: self.home_dc, ..table })
+    }
+}
+
+/// url-safe 的 base64, 不带填充
+impl Display for ExportedSession {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&URL_SAFE_NO_PAD.encode(self.encode()))
+    }
+}
+
+impl FromStr for ExportedSession {
+    type Err = SessionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = URL_SAFE_NO_PAD.decode(s.trim()).map_err(|_| SessionError::Malformed)?;
+        Self::decode(&bytes)
+    }
+}
+
+/// 不输出密钥
+impl Debug for ExportedSession {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ExportedSession")
+            .field("home_dc", &self.home_dc)
+            .field("user_id", &self.user_id)
+            .field("dc_ids", &self.dc_ids().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::net::AuthKey;
+
+    use super::*;
+
+    #[test]
+    fn string_round_trip() {
+        let state = DcState {
+            perm_key: AuthKey::from_bytes([1; 256]),
+            temp_key: None,
+            session_id: 42,
+            server_salt: 7,
+            time_diff: 0,
+        };
+        let session = ExportedSession { home_dc: 2, user_id: 10086, dcs: vec![(2, state.clone()), (4, state)] };
+        let text = session.to_string();
+        assert!(text.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+        assert_eq!(text.parse::<ExportedSession>().unwrap(), session);
+        assert!(!format!("{:?}", session).contains("perm_key"));
+
+        // 截断, 修改或版本不同时拒绝导入
+        assert_eq!("!!".parse::<ExportedSession>(), Err(SessionError::Malformed));
+        let mut bytes = session.encode();
+        assert_eq!(ExportedSession::decode(&bytes[1..]), Err(SessionError::Checksum));
+        bytes[5] ^= 1;
+        assert_eq!(ExportedSession::decode(&bytes), Err(SessionError::Checksum));
+        let mut bytes = session.encode();
+        bytes[0] = 2;
+        let len = bytes.len();
+        let crc = crc32fast::hash(&bytes[..len - 4]);
+        bytes[len - 4..].copy_from_slice(&crc.to_le_bytes());
+        assert_eq!(ExportedSession::decode(&bytes), Err(SessionError::Version(2)));
+    }
+}
@@ -0,0 +1,49 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::storage::Storage;
+
+/// 使用内嵌的 [sled](https://docs.rs/sled) 数据库保存
+#[derive(Debug, Clone)]
+pub struct SledStorage {
+    db: ::sled::Db,
+}
+
+impl SledStorage {
+    /// 打开 [path] 目录下的数据库, 不存在时创建
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Ok(Self { db: ::sled::open(path)? })
+    }
+}
+
+impl Storage for SledStorage {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        Ok(self.db.get(key)?.map(|v| v.to_vec()))
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        self.db.insert(key, value)?;
+        // 密钥等记录很少写入, 每次都落盘, 避免进程退出时丢失
+        self.db.flush()?;
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        self.db.remove(key)?;
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sled() {
+        let path = std::env::temp_dir().join(format!("imx-sled-{}", std::process::id()));
+        crate::storage::tests::round_trip(&SledStorage::open(&path).unwrap());
+        std::fs::remove_dir_all(&path).ok();
+    }
+}
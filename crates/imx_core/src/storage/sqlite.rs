@@ -0,0 +1,68 @@
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use rusqlite::{Connection, OptionalExtension, params};
+
+use crate::storage::Storage;
+
+/// 使用内嵌的 SQLite 数据库保存, 所有记录在一张 `kv` 表中
+#[derive(Debug)]
+pub struct SqliteStorage {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+    /// 打开 [path] 的数据库文件, 不存在时创建
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::new(Connection::open(path)?)
+    }
+
+    /// 只保存在内存中的数据库
+    pub fn open_in_memory() -> Result<Self> {
+        Self::new(Connection::open_in_memory()?)
+    }
+
+    fn new(conn: Connection) -> Result<Self> {
+        conn.execute("CREATE TABLE IF NOT EXISTS kv (key TEXT PRIMARY KEY, value BLOB NOT NULL)", [])?;
+        Ok(Self { conn: Mutex::new(conn) })
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let conn = self.conn.lock().unwrap();
+        let value = conn.query_row("SELECT value FROM kv WHERE key = ?1", [key], |row| row.get(0)).optional()?;
+        Ok(value)
+    }
+
+    fn set(&self, key: &str, value: &[u8]) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("INSERT OR REPLACE INTO kv (key, value) VALUES (?1, ?2)", params![key, value])?;
+        Ok(())
+    }
+
+    fn remove(&self, key: &str) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("DELETE FROM kv WHERE key = ?1", [key])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sqlite() {
+        crate::storage::tests::round_trip(&SqliteStorage::open_in_memory().unwrap());
+
+        let path = std::env::temp_dir().join(format!("imx-sqlite-{}.db", std::process::id()));
+        {
+            let storage = SqliteStorage::open(&path).unwrap();
+            storage.set("a", b"1").unwrap();
+        }
+        assert_eq!(SqliteStorage::open(&path).unwrap().get("a").unwrap(), Some(b"1".to_vec()));
+        std::fs::remove_file(&path).ok();
+    }
+}
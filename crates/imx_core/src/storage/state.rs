@@ -0,0 +1,96 @@
+use anyhow::{bail, Result};
+use bytes::{Buf, BufMut};
+
+use crate::net::AuthKey;
+
+/// 编码格式的版本, 格式变化时增加
+const VERSION: u8 = 1;
+const KEY_LEN: usize = 256;
+
+/// 一个 DataCenter 需要保存的状态, 取自 generic 连接. 恢复后连接时直接使用已有的密钥, 不需要握手
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct DcState {
+    /// 永久密钥
+    pub perm_key: AuthKey,
+    /// PFS 的临时密钥和过期时间 (服务器时间, 单位: 秒)
+    pub temp_key: Option<(AuthKey, i32)>,
+    pub session_id: i64,
+    pub server_salt: i64,
+    /// 与服务器的时间差, 单位: 秒
+    pub time_diff: i32,
+}
+
+impl DcState {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(2 + KEY_LEN * 2 + 24);
+        buf.put_u8(VERSION);
+        buf.put_slice(self.perm_key.as_bytes());
+        match &self.temp_key {
+            Some((key, expires_at)) => {
+                buf.put_u8(1);
+                buf.put_slice(key.as_bytes());
+                buf.put_i32_le(*expires_at);
+            }
+            None => buf.put_u8(0),
+        }
+        buf.put_i64_le(self.session_id);
+        buf.put_i64_le(self.server_salt);
+        buf.put_i32_le(self.time_diff);
+        buf
+    }
+
+    pub fn decode(mut buf: &[u8]) -> Result<Self> {
+        if buf.remaining() < 1 + KEY_LEN + 1 { bail!("dc state too short"); }
+        let version = buf.get_u8();
+        if version != VERSION { bail!("unsupported dc state version {}", version); }
+        let perm_key = get_key(&mut buf);
+        let temp_key = match buf.get_u8() {
+            0 => None,
+            1 if buf.remaining() >= KEY_LEN + 4 => {
+                let key = get_key(&mut buf);
+                Some((key, buf.get_i32_le()))
+            }
+            _ => bail!("invalid temp key in dc state"),
+        };
+        if buf.remaining() != 20 { bail!("invalid dc state length"); }
+        Ok(Self {
+            perm_key,
+            temp_key,
+            session_id: buf.get_i64_le(),
+            server_salt: buf.get_i64_le(),
+            time_diff: buf.get_i32_le(),
+        })
+    }
+}
+
+fn get_key(buf: &mut &[u8]) -> AuthKey {
+    let mut bytes = [0; KEY_LEN];
+    buf.copy_to_slice(&mut bytes);
+    AuthKey::from_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode() {
+        let state = DcState {
+            perm_key: AuthKey::from_bytes([1; 256]),
+            temp_key: Some((AuthKey::from_bytes([2; 256]), 1700000000)),
+            session_id: -42,
+            server_salt: 7,
+            time_diff: -3,
+        };
+        let bytes = state.encode();
+        assert_eq!(DcState::decode(&bytes).unwrap(), state);
+
+        let state = DcState { temp_key: None, ..state };
+        assert_eq!(DcState::decode(&state.encode()).unwrap(), state);
+
+        assert!(DcState::decode(&bytes[..bytes.len() - 1]).is_err());
+        let mut bytes = bytes;
+        bytes[0] = 0;
+        assert!(DcState::decode(&bytes).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+//! 十六进制编码, 用于在配置和文件中保存 obfuscation 密钥等二进制数据
+
+use std::fmt::Write;
+
+pub fn encode(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut s, b| {
+        write!(s, "{:02x}", b).ok();
+        s
+    })
+}
+
+/// 长度不是偶数或包含非十六进制字符时返回 `None`
+pub fn decode(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 { return None; }
+    (0..hex.len()).step_by(2)
+        .map(|i| hex.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+/// 以十六进制字符串序列化 `Option<Vec<u8>>`
+pub mod option {
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        match bytes {
+            Some(bytes) => serializer.serialize_some(&super::encode(bytes)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error> {
+        Option::<String>::deserialize(deserializer)?
+            .map(|hex| super::decode(&hex).ok_or_else(|| de::Error::custom(format!("invalid hex: {}", hex))))
+            .transpose()
+    }
+}
+
+/// 以十六进制字符串序列化 `HashMap<String, Vec<u8>>` 的值
+pub mod map {
+    use std::collections::HashMap;
+
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(map: &HashMap<String, Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(map.iter().map(|(k, v)| (k, super::encode(v))))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<HashMap<String, Vec<u8>>, D::Error> {
+        HashMap::<String, String>::deserialize(deserializer)?
+            .into_iter()
+            .map(|(k, hex)| match super::decode(&hex) {
+                Some(v) => Ok((k, v)),
+                None => Err(de::Error::custom(format!("invalid hex: {}", hex))),
+            })
+            .collect()
+    }
+}
@@ -3,6 +3,7 @@ use dashmap::DashMap;
 pub(crate) use factorize::*;
 
 mod factorize;
+pub(crate) mod hex;
 mod sha;
 
 pub type HashMap<K, V> = DashMap<K, V, RandomState>;
\ No newline at end of file
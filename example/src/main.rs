@@ -26,15 +26,6 @@ async fn main() {
     };
     client.start();
 
-    // std::thread::sleep(Duration::from_secs(2));
-    // client.pause();
-    //
-    // std::thread::sleep(Duration::from_secs(3));
-    // client.resume();
-    //
-    // std::thread::sleep(Duration::from_secs(2));
-    // client.stop();
-
     let mut input = String::new();
     loop {
         input.clear();
@@ -44,6 +35,9 @@ async fn main() {
                 match line {
                     "/start" => client.start(),
                     "/stop" => client.stop(),
+                    "/pause" => client.pause(),
+                    "/background" => client.background(),
+                    "/resume" => client.resume(),
                     "/exit" => {
                         client.release();
                         std::process::exit(0)